@@ -0,0 +1,149 @@
+//! A pool of threads running blocking work off the poll loops.
+//!
+//! Handlers must never block the poll loop of their worker, as every other
+//! connection of that worker would stall meanwhile. `spawn_blocking` moves
+//! such work onto the pool and returns a future completing with its output,
+//! waking the calling task once done.
+
+use std::future::Future;
+use std::io::Error;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::task::{Context, Poll as TaskPoll, Waker};
+use std::thread;
+
+pub(crate) const DEFAULT_BLOCKING_THREADS: usize = 16;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+static POOL: OnceLock<BlockingPool> = OnceLock::new();
+
+struct BlockingPool {
+  sender: Sender<Job>,
+  threads: usize,
+}
+
+impl BlockingPool {
+  fn new(threads: usize) -> Self {
+    let threads = threads.max(1);
+    let (sender, receiver) = channel::<Job>();
+    let receiver = Arc::new(Mutex::new(receiver));
+    for i in 0..threads {
+      let receiver = receiver.clone();
+      thread::Builder::new()
+          .name(format!("hello-blocking-{}", i))
+          .spawn(move || run_jobs(&receiver))
+          .expect("Failed to spawn blocking thread!");
+    }
+    BlockingPool { sender, threads }
+  }
+}
+
+fn run_jobs(receiver: &Mutex<Receiver<Job>>) {
+  loop {
+    let job = match receiver.lock()
+        .expect("Failed to lock blocking queue!")
+        .recv() {
+      Ok(job) => job,
+      Err(_) => return
+    };
+    job();
+  }
+}
+
+/// Start the pool with `threads` threads, `DEFAULT_BLOCKING_THREADS` if
+/// `None`, unless it is already running.
+///
+/// The pool is shared by the whole process, so asking for another number of
+/// threads than it already runs is an error.
+pub(crate) fn init(threads: Option<usize>) -> Result<(), Error> {
+  let pool = POOL.get_or_init(|| {
+    BlockingPool::new(threads.unwrap_or(DEFAULT_BLOCKING_THREADS))
+  });
+  match threads {
+    Some(threads) if threads != pool.threads => Err(Error::other(format!(
+      "Blocking pool already runs [{}] threads, not [{}]!", pool.threads, threads))),
+    _ => Ok(())
+  }
+}
+
+/// State shared between a `BlockingTask` and the job it waits for.
+struct Shared<T> {
+  output: Option<thread::Result<T>>,
+  waker: Option<Waker>,
+}
+
+/// Output of `spawn_blocking`, completing once the job has run.
+///
+/// Polling it panics if the job panicked.
+pub struct BlockingTask<T> {
+  shared: Arc<Mutex<Shared<T>>>,
+}
+
+impl<T> Future for BlockingTask<T> {
+  type Output = T;
+
+  fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> TaskPoll<T> {
+    let mut shared = self.shared.lock()
+        .expect("Failed to lock blocking task!");
+    match shared.output.take() {
+      Some(Ok(output)) => TaskPoll::Ready(output),
+      Some(Err(payload)) => panic::resume_unwind(payload),
+      None => {
+        shared.waker = Some(cx.waker().clone());
+        TaskPoll::Pending
+      }
+    }
+  }
+}
+
+/// Run `job` on the blocking pool, which is started with
+/// `DEFAULT_BLOCKING_THREADS` threads unless `Server` started it already.
+///
+/// Example:
+/// ```no run
+/// async move {
+///   let body = spawn_blocking(move || std::fs::read(path)).await?;
+///   ...
+/// }
+/// ```
+pub fn spawn_blocking<F, T>(job: F) -> BlockingTask<T>
+  where F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static {
+  let shared = Arc::new(Mutex::new(Shared { output: None, waker: None }));
+  let task = BlockingTask { shared: shared.clone() };
+
+  let job: Job = Box::new(move || {
+    // Catch the panic so that the pool thread survives it
+    let output = panic::catch_unwind(AssertUnwindSafe(job));
+    let waker = {
+      let mut shared = shared.lock()
+          .expect("Failed to lock blocking task!");
+      shared.output = Some(output);
+      shared.waker.take()
+    };
+    if let Some(waker) = waker {
+      waker.wake();
+    }
+  });
+
+  POOL.get_or_init(|| BlockingPool::new(DEFAULT_BLOCKING_THREADS))
+      .sender.send(job)
+      .expect("Blocking pool stopped unexpectedly!");
+  task
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn rejects_another_number_of_threads() {
+    init(None).unwrap();
+    let threads = POOL.get().map(|pool| pool.threads).unwrap();
+    assert!(init(Some(threads)).is_ok());
+    assert!(init(Some(threads + 1)).is_err());
+  }
+}
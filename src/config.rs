@@ -0,0 +1,563 @@
+//! Settings of a `Server`, built in code or loaded from a TOML file and the
+//! environment.
+//!
+//! The TOML file is a flat list of `key = value` pairs, optionally under a
+//! `[server]` table, e.g.
+//! ```toml
+//! [server]
+//! bind = ["0.0.0.0:6006", "[::]:6006"]
+//! workers = 4
+//! header_timeout = 10 # seconds
+//! ```
+//! Every key may be overridden by the environment variable of the same name
+//! in upper case, prefixed with `HELLO_SERVER_`, e.g. `HELLO_SERVER_WORKERS`.
+//! Lists are comma-separated there, e.g. `HELLO_SERVER_BIND=0.0.0.0:80,[::]:80`.
+
+use std::env;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::fs;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::num::NonZeroUsize;
+use std::path::Path;
+use std::str::FromStr;
+use std::thread;
+use std::time::Duration;
+
+use crate::connection::Timeouts;
+use crate::http::compression::Compression;
+use crate::http::parser::RequestLimits;
+
+const DEFAULT_PORT: u16 = 6006;
+const DEFAULT_EVENT_CAPACITY: usize = 256;
+const DEFAULT_HEADER_TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_BODY_TIMEOUT: Duration = Duration::from_secs(30);
+const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(15);
+const DEFAULT_SEND_TIMEOUT: Duration = Duration::from_secs(30);
+const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+const ENV_PREFIX: &str = "HELLO_SERVER_";
+
+/// Every key understood by `ServerConfig::set`.
+const KEYS: [&str; 19] = [
+  "bind", "workers", "blocking_threads", "event_capacity", "max_connections",
+  "read_buffer_size", "write_buffer_size", "header_timeout", "body_timeout",
+  "idle_timeout", "send_timeout", "shutdown_timeout", "max_request_line",
+  "max_header_size", "max_headers", "max_head_size", "max_body_size", "compression",
+  "logging",
+];
+
+/// Enum of reasons a `ServerConfig` could not be loaded
+#[derive(Debug)]
+pub enum ConfigError {
+  // Config file could not be read.
+  Io(io::Error),
+
+  // Line of the config file is neither a table header nor `key = value`.
+  InvalidLine(usize),
+
+  // Key or table is not a setting of `ServerConfig`.
+  UnknownKey(String),
+
+  // Value could not be parsed for its key.
+  InvalidValue { key: String, value: String },
+}
+
+impl Display for ConfigError {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    match self {
+      ConfigError::Io(err) =>
+        write!(f, "Failed to read config! [{}]", err),
+      ConfigError::InvalidLine(line) =>
+        write!(f, "Invalid config line #{}!", line),
+      ConfigError::UnknownKey(key) =>
+        write!(f, "Unknown config key [{}]!", key),
+      ConfigError::InvalidValue { key, value } =>
+        write!(f, "Invalid value [{}] for config key [{}]!", value, key),
+    }
+  }
+}
+
+impl Error for ConfigError {
+  fn source(&self) -> Option<&(dyn Error + 'static)> {
+    match self {
+      ConfigError::Io(err) => Some(err),
+      _ => None
+    }
+  }
+}
+
+impl From<io::Error> for ConfigError {
+  fn from(err: io::Error) -> Self {
+    ConfigError::Io(err)
+  }
+}
+
+/// Settings of a `Server`.
+///
+/// The handler is not one of them: it is handed to `Server::serve` or
+/// `Server::serve_async` instead, whose type decides how requests are
+/// dispatched, so that a config stays plain data to clone, compare or load
+/// from a file.
+///
+/// Example:
+/// ```no run
+/// let config = ServerConfig::new(Ipv4Addr::LOCALHOST.into(), 6006)
+///     .workers(4)
+///     .idle_timeout(Duration::from_secs(5));
+/// Server::with_config(config).serve(handle)
+/// ```
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+  pub(crate) socket_addrs: Vec<SocketAddr>,
+  pub(crate) workers: usize,
+  pub(crate) blocking_threads: Option<usize>,
+  pub(crate) event_capacity: usize,
+  pub(crate) max_connections: Option<usize>,
+  pub(crate) read_buffer_size: Option<u32>,
+  pub(crate) write_buffer_size: Option<u32>,
+  pub(crate) header_timeout: Duration,
+  pub(crate) body_timeout: Duration,
+  pub(crate) idle_timeout: Duration,
+  pub(crate) send_timeout: Duration,
+  pub(crate) shutdown_timeout: Duration,
+  pub(crate) limits: RequestLimits,
+  pub(crate) compression: Option<Compression>,
+  pub(crate) logging: Option<bool>,
+}
+
+impl Default for ServerConfig {
+  /// Listen on `127.0.0.1:6006`.
+  fn default() -> Self {
+    ServerConfig::new(Ipv4Addr::LOCALHOST.into(), DEFAULT_PORT)
+  }
+}
+
+impl ServerConfig {
+  pub fn new(ip_addr: IpAddr, port: u16) -> Self {
+    ServerConfig {
+      socket_addrs: vec![SocketAddr::new(ip_addr, port)],
+      workers: thread::available_parallelism().map_or(1, NonZeroUsize::get),
+      blocking_threads: None,
+      event_capacity: DEFAULT_EVENT_CAPACITY,
+      max_connections: None,
+      read_buffer_size: None,
+      write_buffer_size: None,
+      header_timeout: DEFAULT_HEADER_TIMEOUT,
+      body_timeout: DEFAULT_BODY_TIMEOUT,
+      idle_timeout: DEFAULT_IDLE_TIMEOUT,
+      send_timeout: DEFAULT_SEND_TIMEOUT,
+      shutdown_timeout: DEFAULT_SHUTDOWN_TIMEOUT,
+      limits: RequestLimits::default(),
+      compression: None,
+      logging: None,
+    }
+  }
+
+  /// Listen on `port` of every IPv4 and IPv6 interface.
+  pub fn dual_stack(port: u16) -> Self {
+    ServerConfig::new(Ipv4Addr::UNSPECIFIED.into(), port)
+        .also_bind(SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), port))
+  }
+
+  /// Default config overridden by the settings of the TOML file at `path`.
+  pub fn from_toml_file<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
+    Self::from_toml_str(&fs::read_to_string(path)?)
+  }
+
+  /// Default config overridden by the settings of `toml`.
+  pub fn from_toml_str(toml: &str) -> Result<Self, ConfigError> {
+    let mut config = Self::default();
+    for (index, line) in toml.lines().enumerate() {
+      let line = strip_comment(line).trim();
+      if line.is_empty() {
+        continue;
+      }
+
+      if let Some(table) = line.strip_prefix('[') {
+        match table.strip_suffix(']').map(str::trim) {
+          Some("server") => continue,
+          Some(table) => return Err(ConfigError::UnknownKey(table.to_owned())),
+          None => return Err(ConfigError::InvalidLine(index + 1))
+        }
+      }
+
+      let (key, value) = line.split_once('=')
+          .ok_or(ConfigError::InvalidLine(index + 1))?;
+      let value = value.trim();
+      match value.strip_prefix('[').and_then(|value| value.strip_suffix(']')) {
+        Some(array) => {
+          let array = array.split(',')
+              .map(|value| unquote(value.trim()))
+              .filter(|value| !value.is_empty())
+              .collect::<Vec<&str>>()
+              .join(",");
+          config.set(key.trim(), &array)?
+        }
+        None => config.set(key.trim(), unquote(value))?
+      }
+    }
+    Ok(config)
+  }
+
+  /// Override the settings with the `HELLO_SERVER_*` environment variables
+  /// set.
+  pub fn merge_env(mut self) -> Result<Self, ConfigError> {
+    for key in KEYS.iter() {
+      let name = format!("{}{}", ENV_PREFIX, key.to_uppercase());
+      if let Ok(value) = env::var(name) {
+        self.set(key, value.trim())?;
+      }
+    }
+    Ok(self)
+  }
+
+  /// Set the setting named `key` from its textual `value`.
+  ///
+  /// Durations are in seconds, and lists comma-separated.
+  pub fn set(&mut self, key: &str, value: &str) -> Result<(), ConfigError> {
+    match key {
+      "bind" =>
+        self.socket_addrs = value.split(',')
+            .map(|value| parse(key, value.trim()))
+            .collect::<Result<Vec<SocketAddr>, ConfigError>>()?,
+      "workers" => self.workers = parse_non_zero(key, value)?,
+      "blocking_threads" => self.blocking_threads = Some(parse_non_zero(key, value)?),
+      "event_capacity" => self.event_capacity = parse_non_zero(key, value)?,
+      "max_connections" => self.max_connections = Some(parse_non_zero(key, value)?),
+      "read_buffer_size" => self.read_buffer_size = Some(parse(key, value)?),
+      "write_buffer_size" => self.write_buffer_size = Some(parse(key, value)?),
+      "header_timeout" => self.header_timeout = parse_secs(key, value)?,
+      "body_timeout" => self.body_timeout = parse_secs(key, value)?,
+      "idle_timeout" => self.idle_timeout = parse_secs(key, value)?,
+      "send_timeout" => self.send_timeout = parse_secs(key, value)?,
+      "shutdown_timeout" => self.shutdown_timeout = parse_secs(key, value)?,
+      "max_request_line" => self.limits.request_line = parse(key, value)?,
+      "max_header_size" => self.limits.header_line = parse(key, value)?,
+      "max_headers" => self.limits.headers = parse(key, value)?,
+      "max_head_size" => self.limits.head = parse(key, value)?,
+      "max_body_size" => self.limits.body = parse(key, value)?,
+      "compression" =>
+        self.compression = if parse(key, value)? { Some(Compression::new()) } else { None },
+      "logging" => self.logging = Some(parse(key, value)?),
+      _ => return Err(ConfigError::UnknownKey(key.to_owned()))
+    }
+    Ok(())
+  }
+
+  /// Listen on `socket_addr` only.
+  pub fn bind(mut self, socket_addr: SocketAddr) -> Self {
+    self.socket_addrs = vec![socket_addr];
+    self
+  }
+
+  /// Listen on `socket_addr` too.
+  ///
+  /// IPv6 listeners only accept IPv6 connections once an IPv4 address is
+  /// bound too, so that both may share a port.
+  pub fn also_bind(mut self, socket_addr: SocketAddr) -> Self {
+    if !self.socket_addrs.contains(&socket_addr) {
+      self.socket_addrs.push(socket_addr);
+    }
+    self
+  }
+
+  /// Serve connections on `workers` threads, defaulting to the available
+  /// parallelism.
+  ///
+  /// Panics if `workers` is `0`.
+  pub fn workers(mut self, workers: usize) -> Self {
+    assert_ne!(workers, 0, "Server needs at least one worker!");
+    self.workers = workers;
+    self
+  }
+
+  /// Run `spawn_blocking` jobs on `threads` threads.
+  ///
+  /// The blocking pool is shared by the whole process: starting a `Server`
+  /// fails if it already runs another number of threads, started by
+  /// another `Server` or by `spawn_blocking`.
+  ///
+  /// Panics if `threads` is `0`.
+  pub fn blocking_threads(mut self, threads: usize) -> Self {
+    assert_ne!(threads, 0, "Blocking pool needs at least one thread!");
+    self.blocking_threads = Some(threads);
+    self
+  }
+
+  /// Handle up to `capacity` readiness events per poll, per thread.
+  ///
+  /// Panics if `capacity` is `0`.
+  pub fn event_capacity(mut self, capacity: usize) -> Self {
+    assert_ne!(capacity, 0, "Event capacity must not be 0!");
+    self.event_capacity = capacity;
+    self
+  }
+
+  /// Close connections accepted while `max` are already open.
+  ///
+  /// Panics if `max` is `0`.
+  pub fn max_connections(mut self, max: usize) -> Self {
+    assert_ne!(max, 0, "Server needs to accept at least one connection!");
+    self.max_connections = Some(max);
+    self
+  }
+
+  /// Size of the kernel receive buffer (`SO_RCVBUF`) of every connection.
+  pub fn read_buffer_size(mut self, size: u32) -> Self {
+    self.read_buffer_size = Some(size);
+    self
+  }
+
+  /// Size of the kernel send buffer (`SO_SNDBUF`) of every connection.
+  pub fn write_buffer_size(mut self, size: u32) -> Self {
+    self.write_buffer_size = Some(size);
+    self
+  }
+
+  /// Answer `408 Request Timeout` if the head of a request is not received
+  /// within `timeout` of its first byte.
+  pub fn header_timeout(mut self, timeout: Duration) -> Self {
+    self.header_timeout = timeout;
+    self
+  }
+
+  /// Answer `408 Request Timeout` if the body of a request is not received
+  /// within `timeout` of its head.
+  pub fn body_timeout(mut self, timeout: Duration) -> Self {
+    self.body_timeout = timeout;
+    self
+  }
+
+  /// Release connections waiting for a request for longer than `timeout`.
+  pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+    self.idle_timeout = timeout;
+    self
+  }
+
+  /// Close connections whose respond is not produced within `timeout` of
+  /// its request, or of which the peer accepts no bytes for `timeout`.
+  pub fn send_timeout(mut self, timeout: Duration) -> Self {
+    self.send_timeout = timeout;
+    self
+  }
+
+  /// Give connections `timeout` to finish their in-flight requests when
+  /// shutting down on `SIGINT` or `SIGTERM`.
+  pub fn shutdown_timeout(mut self, timeout: Duration) -> Self {
+    self.shutdown_timeout = timeout;
+    self
+  }
+
+  /// Answer `414 URI Too Long` to requests whose request line is longer
+  /// than `len` bytes.
+  pub fn max_request_line(mut self, len: usize) -> Self {
+    self.limits.request_line = len;
+    self
+  }
+
+  /// Answer `431 Request Header Fields Too Large` to requests with a header
+  /// field line longer than `size` bytes.
+  pub fn max_header_size(mut self, size: usize) -> Self {
+    self.limits.header_line = size;
+    self
+  }
+
+  /// Answer `431 Request Header Fields Too Large` to requests with more than
+  /// `count` header fields.
+  pub fn max_headers(mut self, count: usize) -> Self {
+    self.limits.headers = count;
+    self
+  }
+
+  /// Answer `431 Request Header Fields Too Large` to requests whose head is
+  /// larger than `size` bytes.
+  pub fn max_head_size(mut self, size: usize) -> Self {
+    self.limits.head = size;
+    self
+  }
+
+  /// Answer `413 Payload Too Large` to requests whose body is larger than
+  /// `size` bytes.
+  pub fn max_body_size(mut self, size: usize) -> Self {
+    self.limits.body = size;
+    self
+  }
+
+  /// Compress the responds whose request accepts it, see `Compression`.
+  pub fn compression(mut self, compression: Compression) -> Self {
+    self.compression = Some(compression);
+    self
+  }
+
+  /// Print the events of the poll loops, by default only in debug builds.
+  ///
+  /// The setting is shared by every server of the process.
+  pub fn logging(mut self, enabled: bool) -> Self {
+    self.logging = Some(enabled);
+    self
+  }
+
+  /// How long connections may wait for the peer or their handler in each
+  /// state.
+  pub(crate) fn timeouts(&self) -> Timeouts {
+    Timeouts {
+      head: self.header_timeout,
+      body: self.body_timeout,
+      idle: self.idle_timeout,
+      send: self.send_timeout,
+    }
+  }
+}
+
+/// Strip the `#` comment of a line, unless it is inside a string.
+#[inline]
+fn strip_comment(line: &str) -> &str {
+  let mut in_string = false;
+  for (index, char) in line.char_indices() {
+    match char {
+      '"' => in_string = !in_string,
+      '#' if !in_string => return &line[..index],
+      _ => {}
+    }
+  }
+  line
+}
+
+#[inline]
+fn unquote(value: &str) -> &str {
+  value.strip_prefix('"')
+      .and_then(|value| value.strip_suffix('"'))
+      .unwrap_or(value)
+}
+
+#[inline]
+fn parse<T: FromStr>(key: &str, value: &str) -> Result<T, ConfigError> {
+  value.parse().map_err(|_| ConfigError::InvalidValue {
+    key: key.to_owned(),
+    value: value.to_owned(),
+  })
+}
+
+#[inline]
+fn parse_non_zero(key: &str, value: &str) -> Result<usize, ConfigError> {
+  parse::<NonZeroUsize>(key, value).map(NonZeroUsize::get)
+}
+
+#[inline]
+fn parse_secs(key: &str, value: &str) -> Result<Duration, ConfigError> {
+  parse::<f64>(key, value).and_then(|secs| {
+    Duration::try_from_secs_f64(secs).map_err(|_| ConfigError::InvalidValue {
+      key: key.to_owned(),
+      value: value.to_owned(),
+    })
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn addr(s: &str) -> SocketAddr {
+    s.parse().unwrap()
+  }
+
+  #[test]
+  fn reads_quoted_and_bare_values() {
+    let config = ServerConfig::from_toml_str("\
+      [server]
+      bind = [\"127.0.0.1:8080\", \"[::1]:8080\",]
+      workers = 3
+      header_timeout = 2.5
+      max_body_size = \"1024\"
+      compression = true
+      logging = false
+    ").unwrap();
+    assert_eq!(config.socket_addrs, [addr("127.0.0.1:8080"), addr("[::1]:8080")]);
+    assert_eq!(config.workers, 3);
+    assert_eq!(config.header_timeout, Duration::from_millis(2500));
+    assert_eq!(config.limits.body, 1024);
+    assert_eq!(config.compression, Some(Compression::new()));
+    assert_eq!(config.logging, Some(false));
+    assert_eq!(config.idle_timeout, DEFAULT_IDLE_TIMEOUT);
+  }
+
+  #[test]
+  fn skips_comments_and_blank_lines() {
+    let config = ServerConfig::from_toml_str("\
+      # Deployment settings
+
+      bind = \"0.0.0.0:80\" # every interface
+      workers = 2#no space
+    ").unwrap();
+    assert_eq!(config.socket_addrs, [addr("0.0.0.0:80")]);
+    assert_eq!(config.workers, 2);
+    assert_eq!(strip_comment("key = \"a # b\" # c"), "key = \"a # b\" ");
+  }
+
+  #[test]
+  fn rejects_unknown_keys_and_tables() {
+    let err = ServerConfig::from_toml_str("workerz = 2").unwrap_err();
+    assert!(matches!(err, ConfigError::UnknownKey(key) if key == "workerz"));
+    let err = ServerConfig::from_toml_str("[client]\nworkers = 2").unwrap_err();
+    assert!(matches!(err, ConfigError::UnknownKey(key) if key == "client"));
+  }
+
+  #[test]
+  fn rejects_malformed_lines() {
+    let err = ServerConfig::from_toml_str("workers = 2\nworkers 3").unwrap_err();
+    assert!(matches!(err, ConfigError::InvalidLine(2)));
+    let err = ServerConfig::from_toml_str("[server").unwrap_err();
+    assert!(matches!(err, ConfigError::InvalidLine(1)));
+  }
+
+  #[test]
+  fn rejects_values_of_the_wrong_type() {
+    for (key, value) in [
+      ("workers", "\"many\""), ("workers", "0"), ("workers", "-1"), ("header_timeout", "-1"),
+      ("header_timeout", "soon"), ("compression", "yes"), ("bind", "localhost"),
+      ("bind", "[\"127.0.0.1:80\", \"nowhere\"]"), ("read_buffer_size", "4294967296"),
+    ] {
+      let err = ServerConfig::from_toml_str(&format!("{} = {}", key, value)).unwrap_err();
+      assert!(matches!(&err, ConfigError::InvalidValue { key: invalid, .. } if invalid == key),
+              "{} = {} [{:?}]", key, value, err);
+    }
+  }
+
+  #[test]
+  fn sets_every_key() {
+    let mut config = ServerConfig::default();
+    for key in KEYS.iter() {
+      let value = match *key {
+        "bind" => "127.0.0.1:1,127.0.0.1:2",
+        "compression" | "logging" => "true",
+        _ => "7"
+      };
+      config.set(key, value).unwrap();
+    }
+    assert_eq!(config.socket_addrs, [addr("127.0.0.1:1"), addr("127.0.0.1:2")]);
+    assert_eq!((config.workers, config.limits.headers), (7, 7));
+    assert_eq!(config.shutdown_timeout, Duration::from_secs(7));
+    assert!(matches!(config.set("port", "80"), Err(ConfigError::UnknownKey(_))));
+  }
+
+  #[test]
+  fn overrides_the_file_with_the_environment() {
+    // Only this test touches the environment, as tests run concurrently
+    env::set_var("HELLO_SERVER_MAX_HEADERS", " 12 ");
+    env::set_var("HELLO_SERVER_BIND", "127.0.0.1:81,[::1]:81");
+    let config = ServerConfig::from_toml_str("max_headers = 5\nmax_head_size = 9000")
+        .and_then(ServerConfig::merge_env);
+    env::remove_var("HELLO_SERVER_MAX_HEADERS");
+    env::remove_var("HELLO_SERVER_BIND");
+    let config = config.unwrap();
+    assert_eq!(config.limits.headers, 12);
+    assert_eq!(config.limits.head, 9000);
+    assert_eq!(config.socket_addrs, [addr("127.0.0.1:81"), addr("[::1]:81")]);
+
+    env::set_var("HELLO_SERVER_WORKERS", "none");
+    let err = ServerConfig::default().merge_env();
+    env::remove_var("HELLO_SERVER_WORKERS");
+    assert!(matches!(err, Err(ConfigError::InvalidValue { key, .. }) if key == "workers"));
+  }
+}
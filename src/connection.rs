@@ -0,0 +1,425 @@
+use std::collections::VecDeque;
+use std::io::{Error, ErrorKind, Write};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use mio::{Interest, Registry};
+use mio::event::Source;
+
+use crate::{ServerConfig, TcpStream, Token};
+use crate::http::chunked::{encode_chunk, LAST_CHUNK};
+use crate::http::compression::{Compression, ContentCoding};
+use crate::http::conditional::Preconditions;
+use crate::http::parser::RequestParser;
+use crate::http::range::RangeRequest;
+use crate::http::request::{HTTPRequest, HttpMethod};
+use crate::http::respond::{Chunks, HTTPRespond};
+use crate::http::version::HttpVersion;
+
+/// Enum of stages in the lifecycle of a `Connection`
+///
+/// The poll loop moves a connection to its next stage after every read,
+/// write or completed handler, and registers the interest matching it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ConnState {
+  // Some bytes of a request head have been received.
+  ReadingHead,
+
+  // The head has been received, the body has not been in full.
+  ReadingBody,
+
+  // An async handler is producing the respond of the current request.
+  Handling,
+
+  // Responds are being written, reading the next request afterwards.
+  Writing,
+
+  // Waiting for the first byte of a request, on a new or kept-alive
+  // connection.
+  KeepAliveIdle,
+
+  // The last respond is being written, the connection is released after.
+  Closing,
+}
+
+/// Per-connection state kept by `ConnMgr` while serving HTTP.
+pub(crate) struct Connection {
+  pub stream: TcpStream,
+  state: ConnState,
+  pub local_addr: Option<SocketAddr>,
+  pub peer_addr: Option<SocketAddr>,
+
+  // Bytes received but not yet consumed by a complete request
+  pub read_buf: Vec<u8>,
+  pub parser: RequestParser,
+
+  // Serialized responds waiting to be written, and how much of the front
+  // one has already been written
+  outbound: VecDeque<Vec<u8>>,
+  write_offset: usize,
+
+  // Chunks of a chunked respond body not pulled into `outbound` yet, and
+  // whether to frame them with chunked Transfer-Encoding
+  chunks: Option<Chunks<'static>>,
+  frame_chunks: bool,
+
+  // Whether to release the connection once the outbound queue is flushed
+  pub closing: bool,
+
+  // Whether the peer has shut down its writing half
+  pub read_closed: bool,
+
+  // HTTP-Version of the request being answered
+  pub request_version: HttpVersion,
+
+  // `Connection` header for the respond of an in-flight async handler
+  pub respond_connection: Option<&'static str>,
+
+  // Header fields of the request the respond is checked against
+  pub respond_checks: RespondChecks,
+
+  // Compression of the responds, if enabled
+  pub compression: Option<Compression>,
+
+  // When the current state times out
+  timeouts: Timeouts,
+  deadline: Instant,
+  deadline_scheduled: bool,
+
+  // Interest the stream is currently registered with
+  interest: Interest,
+
+  // Slot among the open connections, freed once dropped
+  _permit: ConnPermit,
+}
+
+/// How long a `Connection` may stay in each state waiting for the peer or
+/// its handler. Writing restarts the send timeout whenever the peer accepts
+/// more bytes.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Timeouts {
+  pub head: Duration,
+  pub body: Duration,
+  pub idle: Duration,
+  pub send: Duration,
+}
+
+impl Timeouts {
+  fn of(&self, state: ConnState) -> Duration {
+    match state {
+      ConnState::ReadingHead => self.head,
+      ConnState::ReadingBody => self.body,
+      ConnState::KeepAliveIdle => self.idle,
+      ConnState::Handling | ConnState::Writing | ConnState::Closing => self.send,
+    }
+  }
+}
+
+/// Header fields of a request its respond is checked against once produced:
+/// conditional ones, and the byte ranges to cut it down to.
+#[derive(Debug, Default)]
+pub(crate) struct RespondChecks {
+  preconditions: Option<Preconditions>,
+  range: Option<RangeRequest>,
+  compression: Option<(Compression, ContentCoding)>,
+}
+
+impl RespondChecks {
+  /// Only responds to `GET` and `HEAD` are checked, as the handlers of other
+  /// methods have already acted by then, and only those to `GET` are
+  /// compressed.
+  pub fn of(request: &HTTPRequest, compression: Option<Compression>) -> Self {
+    match request.method {
+      HttpMethod::Get | HttpMethod::Head => RespondChecks {
+        preconditions: request.preconditions(),
+        range: RangeRequest::of(request),
+        compression: compression.filter(|_| request.method == HttpMethod::Get)
+            .map(|compression| (compression, compression.negotiate(request))),
+      },
+      _ => RespondChecks::default()
+    }
+  }
+
+  pub fn apply(self, mut respond: HTTPRespond<'static>) -> HTTPRespond<'static> {
+    if let Some(preconditions) = self.preconditions {
+      respond = preconditions.apply(respond);
+    }
+    if let Some(range) = self.range {
+      respond = range.apply(respond);
+    }
+    if let Some((compression, coding)) = self.compression {
+      respond = compression.apply(coding, respond);
+    }
+    respond
+  }
+}
+
+/// Slot among the open connections of a `Server`, freed once dropped.
+pub(crate) struct ConnPermit(Arc<AtomicUsize>);
+
+impl ConnPermit {
+  /// Take a slot of `open`, unless `max` are taken already.
+  pub fn acquire(open: &Arc<AtomicUsize>, max: Option<usize>) -> Option<Self> {
+    let taken = open.fetch_add(1, Ordering::SeqCst);
+    let permit = ConnPermit(open.clone());
+    match max {
+      Some(max) if taken >= max => None, // Dropping `permit` frees the slot
+      _ => Some(permit)
+    }
+  }
+}
+
+impl Drop for ConnPermit {
+  fn drop(&mut self) {
+    self.0.fetch_sub(1, Ordering::SeqCst);
+  }
+}
+
+impl Connection {
+  pub fn new(stream: TcpStream, permit: ConnPermit, config: &ServerConfig) -> Self {
+    let timeouts = config.timeouts();
+    Connection {
+      local_addr: stream.local_addr().ok(),
+      peer_addr: stream.peer_addr().ok(),
+      stream,
+      state: ConnState::KeepAliveIdle,
+      read_buf: Vec::new(),
+      parser: RequestParser::with_limits(config.limits),
+      outbound: VecDeque::new(),
+      write_offset: 0,
+      chunks: None,
+      frame_chunks: true,
+      closing: false,
+      read_closed: false,
+      request_version: HttpVersion::Http_1_1,
+      respond_connection: None,
+      respond_checks: RespondChecks::default(),
+      compression: config.compression,
+      timeouts,
+      deadline: Instant::now() + timeouts.idle,
+      deadline_scheduled: false,
+      interest: Interest::READABLE,
+      _permit: permit,
+    }
+  }
+
+  pub fn state(&self) -> ConnState {
+    self.state
+  }
+
+  /// Move to `state`, starting its timeout if it differs from the current
+  /// one.
+  pub fn set_state(&mut self, state: ConnState) {
+    if self.state == state {
+      return;
+    }
+    self.state = state;
+    self.restart_deadline();
+  }
+
+  fn restart_deadline(&mut self) {
+    self.deadline = Instant::now() + self.timeouts.of(self.state);
+    self.deadline_scheduled = false;
+  }
+
+  /// When the current state times out.
+  pub fn deadline(&self) -> Instant {
+    self.deadline
+  }
+
+  /// The deadline of the current state, if not handed to the timer wheel
+  /// yet.
+  pub fn take_unscheduled_deadline(&mut self) -> Option<Instant> {
+    if self.deadline_scheduled {
+      return None;
+    }
+    self.deadline_scheduled = true;
+    Some(self.deadline)
+  }
+
+  /// Append `bytes` to the outbound queue.
+  pub fn queue(&mut self, bytes: Vec<u8>) {
+    if !bytes.is_empty() {
+      self.outbound.push_back(bytes);
+    }
+  }
+
+  /// Stream `chunks` once everything queued so far has been written, framed
+  /// with chunked Transfer-Encoding if `framed`, else as is, in which case
+  /// closing the connection ends the body.
+  pub fn stream_chunks(&mut self, chunks: Chunks<'static>, framed: bool) {
+    self.chunks = Some(chunks);
+    self.frame_chunks = framed;
+  }
+
+  pub fn is_streaming(&self) -> bool {
+    self.chunks.is_some()
+  }
+
+  pub fn has_outbound(&self) -> bool {
+    !self.outbound.is_empty() || self.is_streaming()
+  }
+
+  /// Write as much of the outbound queue as the stream accepts, pulling the
+  /// next chunk whenever the queue runs dry, and return whether everything
+  /// was flushed.
+  pub fn flush(&mut self) -> Result<bool, Error> {
+    loop {
+      if self.outbound.is_empty() && !self.pull_chunk() {
+        return Ok(true);
+      }
+      let bytes = &self.outbound[0];
+
+      match self.stream.write(&bytes[self.write_offset..]) {
+        Ok(0) => return Err(ErrorKind::WriteZero.into()),
+
+        Ok(size) => {
+          self.write_offset += size;
+          if self.write_offset == bytes.len() {
+            self.outbound.pop_front();
+            self.write_offset = 0;
+          }
+          self.restart_deadline();
+        }
+
+        Err(err) => {
+          match err.kind() {
+            ErrorKind::WouldBlock => return Ok(false),
+            ErrorKind::Interrupted => continue,
+            _ => return Err(err)
+          }
+        }
+      }
+    }
+  }
+
+  /// Queue the next non-empty chunk, or the last chunk once the iterator is
+  /// exhausted if framing. Returns `false` if there is nothing to stream.
+  fn pull_chunk(&mut self) -> bool {
+    let chunks = match self.chunks.as_mut() {
+      Some(chunks) => chunks,
+      None => return false
+    };
+    match chunks.find(|chunk| !chunk.is_empty()) {
+      Some(chunk) if self.frame_chunks => self.outbound.push_back(encode_chunk(&chunk)),
+      Some(chunk) => self.outbound.push_back(chunk),
+      None => {
+        self.chunks = None;
+        if !self.frame_chunks {
+          return false;
+        }
+        self.outbound.push_back(LAST_CHUNK.to_vec());
+      }
+    }
+    true
+  }
+
+  /// Reregister the stream if `interest` differs from the current one.
+  ///
+  /// Readiness is edge-triggered, so reregistering with an unchanged
+  /// interest would only report the same readiness again.
+  pub fn set_interest(&mut self, registry: &Registry,
+                      token: Token, interest: Interest) -> Result<(), Error> {
+    if self.interest == interest {
+      return Ok(());
+    }
+    self.interest = interest;
+    self.stream.reregister(registry, token, interest)
+  }
+}
+
+impl Source for Connection {
+  fn register(&mut self, registry: &Registry,
+              token: Token, interests: Interest) -> Result<(), Error> {
+    self.stream.register(registry, token, interests)
+  }
+
+  fn reregister(&mut self, registry: &Registry,
+                token: Token, interests: Interest) -> Result<(), Error> {
+    self.stream.reregister(registry, token, interests)
+  }
+
+  fn deregister(&mut self, registry: &Registry) -> Result<(), Error> {
+    self.stream.deregister(registry)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::net::{Ipv4Addr, TcpListener};
+
+  use super::*;
+
+  const SECS: Duration = Duration::from_secs(1);
+
+  /// Connection to a listener on loopback, along with the listener keeping
+  /// the peer alive.
+  fn connection() -> (Connection, TcpListener) {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let config = ServerConfig::default()
+        .header_timeout(10 * SECS)
+        .body_timeout(20 * SECS)
+        .idle_timeout(30 * SECS)
+        .send_timeout(40 * SECS);
+    let permit = ConnPermit::acquire(&Arc::new(AtomicUsize::new(0)), None).unwrap();
+    (Connection::new(stream, permit, &config), listener)
+  }
+
+  /// Whether the deadline of `conn` is `timeout` after a moment between
+  /// `before` and now.
+  fn expires_after(conn: &Connection, before: Instant, timeout: Duration) -> bool {
+    before + timeout <= conn.deadline() && conn.deadline() <= Instant::now() + timeout
+  }
+
+  #[test]
+  fn starts_idle() {
+    let before = Instant::now();
+    let (conn, _listener) = connection();
+    assert_eq!(conn.state(), ConnState::KeepAliveIdle);
+    assert!(expires_after(&conn, before, 30 * SECS));
+  }
+
+  #[test]
+  fn times_out_each_state_with_its_timeout() {
+    let (mut conn, _listener) = connection();
+    let transitions = [
+      (ConnState::ReadingHead, 10 * SECS),
+      (ConnState::ReadingBody, 20 * SECS),
+      (ConnState::Handling, 40 * SECS),
+      (ConnState::Writing, 40 * SECS),
+      (ConnState::KeepAliveIdle, 30 * SECS),
+      (ConnState::Closing, 40 * SECS),
+    ];
+    for (state, timeout) in transitions {
+      let before = Instant::now();
+      conn.set_state(state);
+      assert_eq!(conn.state(), state);
+      assert!(expires_after(&conn, before, timeout), "{:?}", state);
+    }
+  }
+
+  #[test]
+  fn keeps_the_deadline_within_the_same_state() {
+    let (mut conn, _listener) = connection();
+    conn.set_state(ConnState::ReadingHead);
+    let deadline = conn.deadline();
+    assert_eq!(conn.take_unscheduled_deadline(), Some(deadline));
+
+    conn.set_state(ConnState::ReadingHead);
+    assert_eq!(conn.deadline(), deadline);
+    assert_eq!(conn.take_unscheduled_deadline(), None);
+  }
+
+  #[test]
+  fn reschedules_the_deadline_of_a_new_state() {
+    let (mut conn, _listener) = connection();
+    assert!(conn.take_unscheduled_deadline().is_some());
+    assert_eq!(conn.take_unscheduled_deadline(), None);
+
+    conn.set_state(ConnState::ReadingHead);
+    assert_eq!(conn.take_unscheduled_deadline(), Some(conn.deadline()));
+  }
+}
@@ -1,42 +1,275 @@
-use std::collections::HashMap;
 use std::io::Error;
 
-use crate::{Poll, TcpStream, Token};
-use crate::no_hash_hasher::BuildNoHashUsizeHasher;
+use mio::event::Source;
 
-type V = TcpStream;
+use crate::{Poll, Token};
 
-/// A Map that maps `primitive type` to `object`.
-pub(crate) struct ConnMgr(
-  HashMap<usize, V, BuildNoHashUsizeHasher>);
+/// Bits of a token id holding the slot index, the others holding the
+/// generation of the slot. Half of them on 64-bit targets, and enough for a
+/// million slots on 32-bit ones, wrapping the generation sooner.
+const INDEX_BITS: u32 = if usize::BITS >= 64 { usize::BITS / 2 } else { 20 };
+const INDEX_MASK: usize = (1 << INDEX_BITS) - 1;
 
-impl ConnMgr {
+struct Slot<V> {
+  // Bumped every time the slot is vacated
+  generation: usize,
+  value: Option<V>,
+
+  // Whether the slot index is in the free list
+  listed: bool,
+}
+
+impl<V> Slot<V> {
+  fn new() -> Self {
+    Slot { generation: 0, value: None, listed: false }
+  }
+}
+
+/// A slab that maps `Token` to `object`.
+///
+/// Tokens are allocated in O(1) from a free list of vacant slots. Each token
+/// id packs the slot index with the generation of the slot, so a token
+/// released and then reused no longer matches events or wakes meant for its
+/// previous owner.
+///
+/// Token ids whose index bits are all `0`, as well as `usize::MAX - 1` and
+/// `usize::MAX`, are reserved for the event loop itself: slot indices are
+/// offset by `1`, and never get close to `INDEX_MASK`.
+pub(crate) struct ConnMgr<V> {
+  slots: Vec<Slot<V>>,
+  vacant: Vec<usize>,
+  len: usize,
+}
+
+/// The `n`-th token id never handed out by a `ConnMgr`, e.g. for listeners
+/// registered on the same `Poll` as its connections.
+#[inline]
+pub(crate) fn reserved_token(n: usize) -> Token {
+  Token(n << INDEX_BITS)
+}
+
+/// Inverse of `reserved_token`, `None` for any other token.
+#[inline]
+pub(crate) fn reserved_index(token: Token) -> Option<usize> {
+  if token.0 & INDEX_MASK == 0 {
+    Some(token.0 >> INDEX_BITS)
+  } else {
+    None
+  }
+}
+
+impl<V> ConnMgr<V> {
   pub fn new() -> Self {
-    let map =
-      HashMap::with_hasher(BuildNoHashUsizeHasher::default());
-    ConnMgr(map)
+    ConnMgr {
+      slots: Vec::new(),
+      vacant: Vec::new(),
+      len: 0,
+    }
+  }
+
+  #[inline]
+  fn token_id(index: usize, generation: usize) -> usize {
+    (generation << INDEX_BITS) | (index + 1)
+  }
+
+  /// Split `token_id` into its slot index and generation.
+  #[inline]
+  fn split(token_id: usize) -> Option<(usize, usize)> {
+    let index = (token_id & INDEX_MASK).checked_sub(1)?;
+    Some((index, token_id >> INDEX_BITS))
   }
 
-  pub fn generate_token(&mut self, value: V) -> Token {
-    for i in 1..=usize::max_value() {
-      if !self.0.contains_key(&i) {
-        self.0.insert(i, value);
-        return Token(i);
+  /// Token of a vacant slot, `None` if every slot is taken.
+  ///
+  /// The slot is not reserved: the same token is returned until it is passed
+  /// to `insert`, here or in another `ConnMgr`, so each token must be used
+  /// before asking for the next one.
+  pub fn vacant_token(&mut self) -> Option<Token> {
+    // Slots may have been filled by `insert` since they were vacated
+    while let Some(&index) = self.vacant.last() {
+      let slot = &mut self.slots[index];
+      if slot.value.is_none() {
+        return Some(Token(Self::token_id(index, slot.generation)));
       }
+      slot.listed = false;
+      self.vacant.pop();
+    }
+
+    let index = self.slots.len();
+    if index + 1 >= INDEX_MASK - 1 {
+      return None;
+    }
+    self.slots.push(Slot { listed: true, ..Slot::new() });
+    self.vacant.push(index);
+    Some(Token(Self::token_id(index, 0)))
+  }
+
+  /// Store `value` in a vacant slot, dropping it if every slot is taken.
+  pub fn generate_token(&mut self, value: V) -> Option<Token> {
+    let token = self.vacant_token()?;
+    self.insert(token, value);
+    Some(token)
+  }
+
+  /// Store `value` under `token`, which may come from another `ConnMgr`.
+  pub fn insert(&mut self, token: Token, value: V) {
+    let (index, generation) = match Self::split(token.0) {
+      Some(split) => split,
+      None => panic!("Token [{}] is reserved!", token.0)
+    };
+    if index >= self.slots.len() {
+      // Slots skipped over stay vacant for later tokens
+      let len = self.slots.len();
+      self.slots.resize_with(index + 1, || Slot { listed: true, ..Slot::new() });
+      self.slots[index].listed = false;
+      self.vacant.extend((len..index).rev());
+    }
+
+    let slot = &mut self.slots[index];
+    if slot.value.is_some() {
+      panic!("Token [{}] already occupied unexpectedly!", token.0)
+    }
+    slot.generation = generation;
+    slot.value = Some(value);
+    self.len += 1;
+    if self.vacant.last() == Some(&index) {
+      slot.listed = false;
+      self.vacant.pop();
+    }
+  }
+
+  pub fn get_mut(&mut self, token_id: &usize) -> Option<&mut V> {
+    let (index, generation) = Self::split(*token_id)?;
+    match self.slots.get_mut(index) {
+      Some(slot) if slot.generation == generation => slot.value.as_mut(),
+      _ => None
     }
-    panic!("No more available tokens!")
   }
 
-  pub fn get_stream(&mut self, token_id: &usize) -> Option<&mut V> {
-    self.0.get_mut(token_id)
+  pub fn iter(&self) -> impl Iterator<Item=(usize, &V)> {
+    self.slots.iter().enumerate().filter_map(|(index, slot)| {
+      slot.value.as_ref().map(|value| (Self::token_id(index, slot.generation), value))
+    })
   }
 
+  pub fn iter_mut(&mut self) -> impl Iterator<Item=(usize, &mut V)> {
+    self.slots.iter_mut().enumerate().filter_map(|(index, slot)| {
+      let generation = slot.generation;
+      slot.value.as_mut().map(|value| (Self::token_id(index, generation), value))
+    })
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len == 0
+  }
+
+  pub fn remove(&mut self, token_id: &usize) -> Option<V> {
+    let (index, generation) = Self::split(*token_id)?;
+    let slot = self.slots.get_mut(index)?;
+    if slot.generation != generation {
+      return None;
+    }
+    let value = slot.value.take()?;
+    slot.generation = (slot.generation + 1) & (usize::MAX >> INDEX_BITS);
+    if !slot.listed {
+      slot.listed = true;
+      self.vacant.push(index);
+    }
+    self.len -= 1;
+    Some(value)
+  }
+}
+
+impl<V: Source> ConnMgr<V> {
   pub fn release_token(&mut self, token: &mut Token, poll: &Poll) -> Result<(), Error> {
-    match self.0.remove(&token.0) {
+    match self.remove(&token.0) {
       Some(mut listener) =>
         poll.registry().deregister(&mut listener),
       _ =>
         panic!("Token [{}] already removed from map unexpectedly!", token.0)
     }
   }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn reuses_vacated_slots_with_a_new_generation() {
+    let mut conn_mgr = ConnMgr::new();
+    let first = conn_mgr.generate_token("first").unwrap();
+    let second = conn_mgr.generate_token("second").unwrap();
+    assert_ne!(first, second);
+
+    assert_eq!(conn_mgr.remove(&first.0), Some("first"));
+    let third = conn_mgr.generate_token("third").unwrap();
+    assert_eq!(third.0 & INDEX_MASK, first.0 & INDEX_MASK);
+    assert_ne!(third, first);
+    assert_eq!(conn_mgr.get_mut(&third.0), Some(&mut "third"));
+  }
+
+  #[test]
+  fn rejects_stale_tokens() {
+    let mut conn_mgr = ConnMgr::new();
+    let stale = conn_mgr.generate_token(1).unwrap();
+    conn_mgr.remove(&stale.0);
+    let fresh = conn_mgr.generate_token(2).unwrap();
+
+    assert_eq!(conn_mgr.get_mut(&stale.0), None);
+    assert_eq!(conn_mgr.remove(&stale.0), None);
+    assert_eq!(conn_mgr.remove(&fresh.0), Some(2));
+    assert!(conn_mgr.is_empty());
+  }
+
+  #[test]
+  fn keeps_reserved_tokens_out_of_slots() {
+    let mut conn_mgr = ConnMgr::new();
+    let tokens = (0..100).map(|value| conn_mgr.generate_token(value).unwrap())
+        .collect::<Vec<Token>>();
+    let reserved = [reserved_token(0), reserved_token(1), Token(usize::MAX - 1), Token(usize::MAX)];
+    for reserved in reserved {
+      assert!(!tokens.contains(&reserved));
+      assert_eq!(conn_mgr.get_mut(&reserved.0), None);
+    }
+    assert_eq!(reserved_index(reserved_token(3)), Some(3));
+    assert_eq!(reserved_index(tokens[0]), None);
+  }
+
+  #[test]
+  fn inserts_tokens_of_another_conn_mgr() {
+    let mut tasks = ConnMgr::<()>::new();
+    let mut conns = ConnMgr::new();
+    let token = tasks.vacant_token().unwrap();
+    conns.insert(token, "conn");
+    assert_eq!(conns.get_mut(&token.0), Some(&mut "conn"));
+    assert_eq!(conns.iter().map(|(token_id, _)| token_id).collect::<Vec<usize>>(), [token.0]);
+  }
+
+  #[test]
+  fn hands_out_the_same_vacant_token_until_inserted() {
+    let mut conn_mgr = ConnMgr::new();
+    let token = conn_mgr.vacant_token().unwrap();
+    assert_eq!(conn_mgr.vacant_token(), Some(token));
+
+    conn_mgr.insert(token, "first");
+    let next = conn_mgr.vacant_token().unwrap();
+    assert_ne!(next, token);
+    assert_eq!(conn_mgr.generate_token("second"), Some(next));
+  }
+
+  #[test]
+  fn lists_slots_skipped_by_insert_as_vacant() {
+    let mut tasks = ConnMgr::<()>::new();
+    let tokens = (0..3).map(|_| tasks.generate_token(()).unwrap()).collect::<Vec<Token>>();
+
+    let mut conns = ConnMgr::new();
+    conns.insert(tokens[2], 2);
+    let mut generated = [conns.generate_token(0).unwrap(), conns.generate_token(1).unwrap()];
+    generated.sort();
+    assert_eq!(generated, tokens[..2]);
+    let next = conns.generate_token(3).unwrap();
+    assert!(!tokens.contains(&next));
+    assert_eq!(conns.iter().map(|(_, &value)| value).collect::<Vec<i32>>(), [0, 1, 2, 3]);
+  }
+}
@@ -0,0 +1,251 @@
+//! A minimal single-threaded executor living inside the mio `Poll` loop.
+//!
+//! Every task is bound to the token its `TcpStream` was registered with, so
+//! a readiness event on that token may poll the task again. Tasks may also
+//! be woken through their `Waker`, which queues the token and breaks the
+//! poll loop through a `mio::Waker`. The output of a completed task is handed
+//! back to the poll loop.
+
+use std::future::Future;
+use std::io::Error;
+use std::mem;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll as TaskPoll, Wake, Waker};
+
+use crate::{logging, Poll, Token};
+use crate::connection_manager::ConnMgr;
+
+pub(crate) type BoxedFuture<O> = Pin<Box<dyn Future<Output=O> + Send>>;
+
+/// Queue of token ids woken outside of mio readiness events.
+type ReadyQueue = Arc<Mutex<Vec<usize>>>;
+
+struct Task<O> {
+  future: BoxedFuture<O>,
+  waker: Waker,
+}
+
+struct TaskWaker {
+  token_id: usize,
+  ready: ReadyQueue,
+  poll_waker: Arc<mio::Waker>,
+}
+
+impl Wake for TaskWaker {
+  fn wake(self: Arc<Self>) {
+    self.wake_by_ref()
+  }
+
+  fn wake_by_ref(self: &Arc<Self>) {
+    self.ready.lock()
+        .expect("Failed to lock ready queue!")
+        .push(self.token_id);
+    if let Err(err) = self.poll_waker.wake() {
+      if logging() {
+        println!("Failed to wake poll for token id [{}]! [{:?}]",
+                 self.token_id, err);
+      }
+    }
+  }
+}
+
+pub(crate) struct Executor<O> {
+  tasks: ConnMgr<Task<O>>,
+  ready: ReadyQueue,
+  poll_waker: Arc<mio::Waker>,
+}
+
+impl<O> Executor<O> {
+  pub fn new(poll: &Poll, waker_token: Token) -> Result<Self, Error> {
+    Ok(Executor {
+      tasks: ConnMgr::new(),
+      ready: Arc::new(Mutex::new(Vec::new())),
+      poll_waker: Arc::new(mio::Waker::new(poll.registry(), waker_token)?),
+    })
+  }
+
+  /// Token for the next task, the same one until a task is spawned with it.
+  pub fn vacant_token(&mut self) -> Option<Token> {
+    self.tasks.vacant_token()
+  }
+
+  /// `mio::Waker` breaking the poll loop this executor lives in.
+  pub fn poll_waker(&self) -> Arc<mio::Waker> {
+    self.poll_waker.clone()
+  }
+
+  /// Whether every task has completed.
+  pub fn is_empty(&self) -> bool {
+    self.tasks.is_empty()
+  }
+
+  /// Drop the task bound to `token_id`, if any.
+  pub fn cancel(&mut self, token_id: &usize) {
+    self.tasks.remove(token_id);
+  }
+
+  /// Bind `future` to `token` and poll it for the first time, returning its
+  /// output if it completed right away.
+  pub fn spawn<T>(&mut self, token: Token, future: T) -> Option<O>
+    where T: Future<Output=O> + Send + 'static {
+    let waker = Waker::from(Arc::new(TaskWaker {
+      token_id: token.0,
+      ready: self.ready.clone(),
+      poll_waker: self.poll_waker.clone(),
+    }));
+    self.tasks.insert(token, Task {
+      future: Box::pin(future),
+      waker,
+    });
+    self.poll_task(token.0)
+  }
+
+  /// Poll the task bound to `token_id`, dropping it and returning its output
+  /// once completed.
+  pub fn poll_task(&mut self, token_id: usize) -> Option<O> {
+    let task = self.tasks.get_mut(&token_id)?;
+
+    let mut context = Context::from_waker(&task.waker);
+    match task.future.as_mut().poll(&mut context) {
+      TaskPoll::Ready(output) => {
+        if logging() {
+          println!("Task with token id [{}] completed!", token_id);
+        }
+        self.tasks.remove(&token_id);
+        Some(output)
+      }
+      TaskPoll::Pending => None
+    }
+  }
+
+  /// Poll every task woken through its `Waker` since the last call,
+  /// returning the outputs of those completed.
+  pub fn poll_woken(&mut self) -> Vec<(usize, O)> {
+    let ready = mem::take(&mut *self.ready.lock()
+        .expect("Failed to lock ready queue!"));
+    ready.into_iter()
+        .filter_map(|token_id| {
+          self.poll_task(token_id).map(|output| (token_id, output))
+        })
+        .collect()
+  }
+}
+
+/// Completes on the second poll, i.e. after the next readiness event (or
+/// wake) of the calling task.
+///
+/// Use it in the callback futures of `hello` to wait when their `TcpStream`
+/// returns `WouldBlock`. Only those are polled again on the readiness events
+/// of their stream: the `AsyncHandler` of a `Server` does not own the stream,
+/// and awaiting this there would never complete. Await a future waking its
+/// task instead, e.g. a `spawn_blocking` job.
+pub fn next_event() -> impl Future<Output=()> {
+  NextEvent(false)
+}
+
+struct NextEvent(bool);
+
+impl Future for NextEvent {
+  type Output = ();
+
+  fn poll(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> TaskPoll<()> {
+    if self.0 {
+      TaskPoll::Ready(())
+    } else {
+      self.0 = true;
+      TaskPoll::Pending
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::atomic::{AtomicBool, Ordering};
+  use std::thread;
+  use std::time::Duration;
+
+  use mio::Events;
+
+  use super::*;
+
+  const WAKER_TOKEN: Token = Token(usize::MAX);
+
+  /// Pending until `done` is set, handing its waker to `waker` meanwhile.
+  struct Flag {
+    done: Arc<AtomicBool>,
+    waker: Arc<Mutex<Option<Waker>>>,
+  }
+
+  impl Future for Flag {
+    type Output = &'static str;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> TaskPoll<&'static str> {
+      if self.done.load(Ordering::SeqCst) {
+        return TaskPoll::Ready("flagged");
+      }
+      *self.waker.lock().unwrap() = Some(cx.waker().clone());
+      TaskPoll::Pending
+    }
+  }
+
+  #[test]
+  fn returns_the_output_of_tasks_completing_right_away() {
+    let poll = Poll::new().unwrap();
+    let mut executor = Executor::new(&poll, WAKER_TOKEN).unwrap();
+    let token = executor.vacant_token().unwrap();
+    assert_eq!(executor.spawn(token, async { 42 }), Some(42));
+    assert!(executor.is_empty());
+    assert_eq!(executor.poll_task(token.0), None);
+  }
+
+  #[test]
+  fn completes_tasks_polled_again_on_their_events() {
+    let poll = Poll::new().unwrap();
+    let mut executor = Executor::new(&poll, WAKER_TOKEN).unwrap();
+    let token = executor.vacant_token().unwrap();
+    assert_eq!(executor.spawn(token, async {
+      next_event().await;
+      next_event().await;
+      "done"
+    }), None);
+    assert!(!executor.is_empty());
+    assert_eq!(executor.poll_task(token.0), None);
+    assert_eq!(executor.poll_task(token.0), Some("done"));
+    assert!(executor.is_empty());
+  }
+
+  #[test]
+  fn polls_tasks_woken_through_their_waker() {
+    let mut poll = Poll::new().unwrap();
+    let mut executor = Executor::new(&poll, WAKER_TOKEN).unwrap();
+    let done = Arc::new(AtomicBool::new(false));
+    let waker = Arc::new(Mutex::new(None));
+    let token = executor.vacant_token().unwrap();
+    let flag = Flag { done: done.clone(), waker: waker.clone() };
+    assert_eq!(executor.spawn(token, flag), None);
+    assert_eq!(executor.poll_woken(), []);
+
+    let waking = thread::spawn(move || {
+      done.store(true, Ordering::SeqCst);
+      waker.lock().unwrap().take().unwrap().wake();
+    });
+    let mut events = Events::with_capacity(4);
+    poll.poll(&mut events, Some(Duration::from_secs(5))).unwrap();
+    waking.join().unwrap();
+    assert!(events.iter().any(|event| event.token() == WAKER_TOKEN));
+    assert_eq!(executor.poll_woken(), [(token.0, "flagged")]);
+    assert!(executor.is_empty());
+  }
+
+  #[test]
+  fn drops_cancelled_tasks() {
+    let poll = Poll::new().unwrap();
+    let mut executor = Executor::new(&poll, WAKER_TOKEN).unwrap();
+    let token = executor.vacant_token().unwrap();
+    assert_eq!(executor.spawn(token, async { next_event().await }), None);
+    executor.cancel(&token.0);
+    assert!(executor.is_empty());
+    assert_eq!(executor.poll_task(token.0), None);
+  }
+}
@@ -0,0 +1,67 @@
+use std::future::Future;
+
+use crate::executor::BoxedFuture;
+use crate::http::request::HTTPRequest;
+use crate::http::respond::HTTPRespond;
+
+/// Produces a `HTTPRespond` for every `HTTPRequest` served by `Server`.
+///
+/// Implemented for every `fn(&HTTPRequest) -> HTTPRespond<'static>`.
+pub trait Handler: Send + Sync + 'static {
+  fn handle(&self, request: &HTTPRequest) -> HTTPRespond<'static>;
+}
+
+impl<F> Handler for F
+  where F: Fn(&HTTPRequest) -> HTTPRespond<'static> + Send + Sync + 'static {
+  fn handle(&self, request: &HTTPRequest) -> HTTPRespond<'static> {
+    self(request)
+  }
+}
+
+/// Asynchronous variant of `Handler`.
+///
+/// The request borrows the connection buffer, so everything the future needs
+/// must be copied out of it before `handle` returns. The future is polled by
+/// the poll loop whenever its `Waker` is woken.
+pub trait AsyncHandler: Send + Sync + 'static {
+  type Future: Future<Output=HTTPRespond<'static>> + Send + 'static;
+
+  fn handle(&self, request: &HTTPRequest) -> Self::Future;
+}
+
+impl<F, T> AsyncHandler for F
+  where F: Fn(&HTTPRequest) -> T + Send + Sync + 'static,
+        T: Future<Output=HTTPRespond<'static>> + Send + 'static {
+  type Future = T;
+
+  fn handle(&self, request: &HTTPRequest) -> T {
+    self(request)
+  }
+}
+
+/// Outcome of dispatching a request to a handler.
+pub(crate) enum Dispatch {
+  Ready(HTTPRespond<'static>),
+  Pending(BoxedFuture<HTTPRespond<'static>>),
+}
+
+/// Object-safe union of `Handler` and `AsyncHandler` used by the poll loop.
+pub(crate) trait Service: Send + Sync + 'static {
+  fn dispatch(&self, request: &HTTPRequest) -> Dispatch;
+}
+
+pub(crate) struct SyncService<H>(pub H);
+
+impl<H: Handler> Service for SyncService<H> {
+  fn dispatch(&self, request: &HTTPRequest) -> Dispatch {
+    Dispatch::Ready(self.0.handle(request))
+  }
+}
+
+pub(crate) struct AsyncService<H>(pub H);
+
+impl<H: AsyncHandler> Service for AsyncService<H> {
+  fn dispatch(&self, request: &HTTPRequest) -> Dispatch {
+    Dispatch::Pending(Box::pin(self.0.handle(request)))
+  }
+}
@@ -0,0 +1,200 @@
+//! Chunked Transfer Coding
+//! ```no run
+//! chunked-body = *chunk
+//!                last-chunk
+//!                trailer-part
+//!                CRLF
+//!
+//! chunk        = chunk-size [ chunk-ext ] CRLF
+//!                chunk-data CRLF
+//! last-chunk   = 1*("0") [ chunk-ext ] CRLF
+//! ```
+
+use crate::http::error::ParseError;
+
+const CRLF: &[u8] = b"\r\n";
+
+/// Terminates a chunked body without any trailer.
+pub const LAST_CHUNK: &[u8] = b"0\r\n\r\n";
+
+/// Frame `data` as a single chunk. `data` must not be empty, as an empty
+/// chunk would be read as the last one.
+pub fn encode_chunk(data: &[u8]) -> Vec<u8> {
+  let mut chunk = format!("{:X}\r\n", data.len()).into_bytes();
+  chunk.reserve(data.len() + CRLF.len());
+  chunk.extend_from_slice(data);
+  chunk.extend_from_slice(CRLF);
+  chunk
+}
+
+/// Enum of states of a resumable chunked body decode
+#[derive(Debug)]
+enum DecoderState {
+  Size,
+  Data { remaining: usize },
+  DataEnd,
+  Trailer,
+}
+
+/// Incremental decoder of a chunked body, fed the bytes following the head.
+#[derive(Debug)]
+pub(crate) struct ChunkedDecoder {
+  state: DecoderState,
+
+  // Offset of the first byte not decoded yet
+  pos: usize,
+
+  // Offset of the first trailer field line
+  trailer_start: usize,
+
+  // Largest decoded body accepted
+  max_body: usize,
+
+  pub body: Vec<u8>,
+}
+
+impl ChunkedDecoder {
+  pub fn new(max_body: usize) -> Self {
+    ChunkedDecoder {
+      state: DecoderState::Size,
+      pos: 0,
+      trailer_start: 0,
+      max_body,
+      body: Vec::new(),
+    }
+  }
+
+  /// Decode as much of `buf` as possible, returning the length of the whole
+  /// chunked body once its final CRLF arrived.
+  pub fn decode(&mut self, buf: &[u8]) -> Result<Option<usize>, ParseError> {
+    loop {
+      match self.state {
+        DecoderState::Size => {
+          let line_end = match find_crlf(&buf[self.pos..]) {
+            Some(index) => self.pos + index,
+            None => return Ok(None)
+          };
+          let line = &buf[self.pos..line_end];
+          // Chunk extensions are ignored
+          let size = match line.iter().position(|byte| *byte == b';') {
+            Some(semicolon) => &line[..semicolon],
+            None => line
+          };
+          let size = std::str::from_utf8(size)
+              .ok()
+              .map(str::trim)
+              .filter(|size| !size.is_empty() && size.bytes().all(|byte| byte.is_ascii_hexdigit()))
+              .and_then(|size| usize::from_str_radix(size, 16).ok())
+              .ok_or(ParseError::InvalidChunk)?;
+          if size > self.max_body - self.body.len() {
+            return Err(ParseError::BodyTooLarge);
+          }
+
+          self.pos = line_end + CRLF.len();
+          if size == 0 {
+            self.trailer_start = self.pos;
+            self.state = DecoderState::Trailer;
+          } else {
+            self.state = DecoderState::Data { remaining: size };
+          }
+        }
+
+        DecoderState::Data { remaining } => {
+          let available = remaining.min(buf.len() - self.pos);
+          self.body.extend_from_slice(&buf[self.pos..self.pos + available]);
+          self.pos += available;
+          if available < remaining {
+            self.state = DecoderState::Data { remaining: remaining - available };
+            return Ok(None);
+          }
+          self.state = DecoderState::DataEnd;
+        }
+
+        DecoderState::DataEnd => {
+          if buf.len() < self.pos + CRLF.len() {
+            return Ok(None);
+          }
+          if &buf[self.pos..self.pos + CRLF.len()] != CRLF {
+            return Err(ParseError::InvalidChunk);
+          }
+          self.pos += CRLF.len();
+          self.state = DecoderState::Size;
+        }
+
+        DecoderState::Trailer => {
+          let line_end = match find_crlf(&buf[self.pos..]) {
+            Some(index) => self.pos + index,
+            None => return Ok(None)
+          };
+          let is_last = line_end == self.pos;
+          self.pos = line_end + CRLF.len();
+          if is_last {
+            return Ok(Some(self.pos));
+          }
+        }
+      }
+    }
+  }
+
+  /// Range of the trailer field lines, excluding the final CRLF, within the
+  /// bytes passed to `decode`.
+  pub fn trailer(&self) -> (usize, usize) {
+    (self.trailer_start, self.pos.saturating_sub(CRLF.len()))
+  }
+}
+
+#[inline]
+fn find_crlf(buf: &[u8]) -> Option<usize> {
+  buf.windows(CRLF.len()).position(|window| window == CRLF)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn decode(body: &[u8]) -> Result<Option<usize>, ParseError> {
+    ChunkedDecoder::new(1024).decode(body)
+  }
+
+  #[test]
+  fn decodes_chunks_ignoring_extensions() {
+    let mut decoder = ChunkedDecoder::new(1024);
+    let body = b"5;name=value\r\nhello\r\n6 ; quoted=\"a;b\"\r\n world\r\n0;last\r\n\r\n";
+    assert_eq!(decoder.decode(body), Ok(Some(body.len())));
+    assert_eq!(decoder.body, b"hello world");
+  }
+
+  #[test]
+  fn resumes_across_feeds() {
+    let mut decoder = ChunkedDecoder::new(1024);
+    let body = b"A\r\n0123456789\r\n0\r\n\r\n";
+    for end in 0..body.len() {
+      assert_eq!(decoder.decode(&body[..end]), Ok(None));
+    }
+    assert_eq!(decoder.decode(body), Ok(Some(body.len())));
+    assert_eq!(decoder.body, b"0123456789");
+  }
+
+  #[test]
+  fn locates_trailer_fields() {
+    let mut decoder = ChunkedDecoder::new(1024);
+    let body = b"1\r\na\r\n0\r\nExpires: never\r\nX-Sum: 1\r\n\r\n";
+    assert_eq!(decoder.decode(body), Ok(Some(body.len())));
+    let (start, end) = decoder.trailer();
+    assert_eq!(&body[start..end], b"Expires: never\r\nX-Sum: 1\r\n");
+  }
+
+  #[test]
+  fn rejects_bad_sizes_and_delimiters() {
+    for body in [&b"zz\r\n"[..], b"\r\n", b"+5\r\nhello\r\n", b"-1\r\n", b"0x5\r\n",
+                 b"FFFFFFFFFFFFFFFFFFFF\r\n", b"5\r\nhelloXX"] {
+      assert_eq!(decode(body), Err(ParseError::InvalidChunk), "{:?}", body);
+    }
+    assert_eq!(ChunkedDecoder::new(4).decode(b"5\r\n"), Err(ParseError::BodyTooLarge));
+  }
+
+  #[test]
+  fn encodes_chunks_in_hex() {
+    assert_eq!(encode_chunk(&[b'x'; 26]), [&b"1A\r\n"[..], &[b'x'; 26], b"\r\n"].concat());
+  }
+}
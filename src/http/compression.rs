@@ -0,0 +1,497 @@
+//! Respond compression, negotiated from `Accept-Encoding`.
+//!
+//! Once enabled with `ServerConfig::compression`, `200 OK` responds to `GET`
+//! requests are compressed with the coding the client prefers among those
+//! enabled, provided their `Content-Type` is compressible and their body is
+//! either chunked or at least `Compression::min_size` bytes long. Chunked
+//! bodies are compressed chunk by chunk as they are streamed.
+
+use std::io::Write;
+use std::mem;
+
+use flate2::write::{GzEncoder, ZlibEncoder};
+
+use crate::http::conditional::EntityTag;
+use crate::http::negotiation::split_q;
+use crate::http::request::HTTPRequest;
+use crate::http::respond::{Chunks, HTTPRespond, HttpRespondHeader, RespondBody, StatusCode};
+
+const DEFAULT_MIN_SIZE: usize = 1024;
+const DEFAULT_LEVEL: u32 = 6;
+
+// Window of brotli, as the base-2 logarithm of its size
+const BROTLI_WINDOW: u32 = 22;
+const BROTLI_BUFFER_SIZE: usize = 4096;
+
+/// Enum of content-codings of a respond body
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentCoding {
+  Brotli,
+  Gzip,
+  Deflate,
+  Identity,
+}
+
+impl ContentCoding {
+  /// Coding as written in `Accept-Encoding` and `Content-Encoding`.
+  pub fn as_str(self) -> &'static str {
+    match self {
+      ContentCoding::Brotli => "br",
+      ContentCoding::Gzip => "gzip",
+      ContentCoding::Deflate => "deflate",
+      ContentCoding::Identity => "identity",
+    }
+  }
+
+  #[inline]
+  fn matches(self, name: &str) -> bool {
+    name.eq_ignore_ascii_case(self.as_str()) ||
+        (self == ContentCoding::Gzip && name.eq_ignore_ascii_case("x-gzip"))
+  }
+}
+
+/// Settings of respond compression.
+///
+/// Example:
+/// ```no run
+/// let config = ServerConfig::default()
+///     .compression(Compression::new().min_size(512).brotli(false));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Compression {
+  min_size: usize,
+  level: u32,
+  brotli: bool,
+  gzip: bool,
+  deflate: bool,
+}
+
+impl Default for Compression {
+  fn default() -> Self {
+    Compression::new()
+  }
+}
+
+impl Compression {
+  /// Compress bodies of at least 1 KiB with any of brotli, gzip and deflate.
+  pub fn new() -> Self {
+    Compression {
+      min_size: DEFAULT_MIN_SIZE,
+      level: DEFAULT_LEVEL,
+      brotli: true,
+      gzip: true,
+      deflate: true,
+    }
+  }
+
+  /// Leave bodies of known length shorter than `size` bytes uncompressed.
+  pub fn min_size(mut self, size: usize) -> Self {
+    self.min_size = size;
+    self
+  }
+
+  /// Trade speed for size from `0` to `9`, defaulting to `6`.
+  ///
+  /// Panics if `level` is above `9`.
+  pub fn level(mut self, level: u32) -> Self {
+    assert!(level <= 9, "Compression level [{}] must be within 0 and 9!", level);
+    self.level = level;
+    self
+  }
+
+  pub fn brotli(mut self, enabled: bool) -> Self {
+    self.brotli = enabled;
+    self
+  }
+
+  pub fn gzip(mut self, enabled: bool) -> Self {
+    self.gzip = enabled;
+    self
+  }
+
+  pub fn deflate(mut self, enabled: bool) -> Self {
+    self.deflate = enabled;
+    self
+  }
+
+  /// Coding the respond to `request` is to be compressed with: the enabled
+  /// one of highest q-value in `Accept-Encoding`, brotli winning ties over
+  /// gzip and gzip over deflate. `Identity` if the request has no
+  /// `Accept-Encoding`, accepts none of the enabled codings, or prefers
+  /// `identity` explicitly.
+  pub fn negotiate(&self, request: &HTTPRequest) -> ContentCoding {
+    let accepted = match request.header.accept_encoding() {
+      Some(codings) => codings.iter()
+          .filter_map(|coding| split_q(coding))
+          .collect::<Vec<(&str, f32)>>(),
+      None => return ContentCoding::Identity
+    };
+    let q_of = |coding: ContentCoding| -> Option<f32> {
+      accepted.iter().find(|(name, _)| coding.matches(name))
+          .or_else(|| accepted.iter().find(|(name, _)| *name == "*"))
+          .map(|(_, q)| *q)
+    };
+
+    let enabled = [
+      (ContentCoding::Brotli, self.brotli),
+      (ContentCoding::Gzip, self.gzip),
+      (ContentCoding::Deflate, self.deflate),
+    ];
+    let mut best = (ContentCoding::Identity, 0.0);
+    for (coding, _) in enabled.iter().filter(|(_, enabled)| *enabled) {
+      match q_of(*coding) {
+        Some(q) if q > best.1 => best = (*coding, q),
+        _ => {}
+      }
+    }
+    // `identity` is only preferred over an accepted coding when listed
+    let identity = accepted.iter()
+        .find(|(name, _)| ContentCoding::Identity.matches(name))
+        .map_or(0.0, |(_, q)| *q);
+    if identity > best.1 { ContentCoding::Identity } else { best.0 }
+  }
+
+  /// Compress `respond` with `coding` if eligible, adding
+  /// `Vary: Accept-Encoding` to every eligible respond.
+  pub fn apply(&self, coding: ContentCoding,
+               mut respond: HTTPRespond<'static>) -> HTTPRespond<'static> {
+    if respond.status_code != StatusCode::Ok || !self.is_eligible(&respond) {
+      return respond;
+    }
+    add_vary(&mut respond);
+    if coding == ContentCoding::Identity {
+      return respond;
+    }
+
+    respond.body = match respond.body {
+      RespondBody::Full(body) => {
+        let mut encoder = Encoder::new(coding, self.level);
+        encoder.write(&body);
+        RespondBody::Full(encoder.finish().into())
+      }
+      RespondBody::Chunked(chunks) => RespondBody::Chunked(Box::new(CompressedChunks {
+        chunks,
+        encoder: Some(Encoder::new(coding, self.level)),
+      })),
+    };
+    for header in &mut respond.header {
+      // The compressed representation is no longer byte for byte the same
+      if let HttpRespondHeader::ETag(value) = header {
+        if let Some(EntityTag { weak: false, tag }) = EntityTag::parse(value) {
+          *value = EntityTag::weak(tag).to_string().into();
+        }
+      }
+    }
+    respond.header.remove("Content-Length");
+    HTTPRespond::with_header(&mut respond,
+                             HttpRespondHeader::ContentEncoding(coding.as_str().into()));
+    respond
+  }
+
+  fn is_eligible(&self, respond: &HTTPRespond) -> bool {
+    let compressible = respond.header.content_encoding().is_none() &&
+        respond.header.content_type().is_some_and(is_compressible);
+    compressible && match &respond.body {
+      RespondBody::Full(body) => body.len() >= self.min_size,
+      RespondBody::Chunked(_) => true,
+    }
+  }
+}
+
+/// Whether bodies of `content_type` are worth compressing, i.e. are text.
+fn is_compressible(content_type: &str) -> bool {
+  let essence = content_type.split(';').next().unwrap_or_default().trim().to_ascii_lowercase();
+  essence.starts_with("text/") || essence.ends_with("+json") || essence.ends_with("+xml") ||
+      matches!(essence.as_str(), "application/json" | "application/javascript" |
+          "application/xml" | "application/wasm" | "application/x-javascript")
+}
+
+fn add_vary(respond: &mut HTTPRespond) {
+  if let Some(HttpRespondHeader::Vary(value)) = respond.header.get_mut("Vary") {
+    let varies = value.split(',')
+        .map(str::trim)
+        .any(|name| name == "*" || name.eq_ignore_ascii_case("Accept-Encoding"));
+    if !varies {
+      *value = format!("{}, Accept-Encoding", value).into();
+    }
+    return;
+  }
+  HTTPRespond::with_header(respond, HttpRespondHeader::Vary("Accept-Encoding".into()));
+}
+
+/// Compressor writing into a buffer
+enum Encoder {
+  Brotli(Box<brotli::CompressorWriter<Vec<u8>>>),
+  Gzip(GzEncoder<Vec<u8>>),
+  Deflate(ZlibEncoder<Vec<u8>>),
+}
+
+impl Encoder {
+  fn new(coding: ContentCoding, level: u32) -> Self {
+    let flate_level = flate2::Compression::new(level);
+    match coding {
+      ContentCoding::Brotli => Encoder::Brotli(Box::new(brotli::CompressorWriter::new(
+        Vec::new(), BROTLI_BUFFER_SIZE, level, BROTLI_WINDOW))),
+      ContentCoding::Gzip => Encoder::Gzip(GzEncoder::new(Vec::new(), flate_level)),
+      ContentCoding::Deflate => Encoder::Deflate(ZlibEncoder::new(Vec::new(), flate_level)),
+      ContentCoding::Identity => unreachable!("Identity is not a compression!"),
+    }
+  }
+
+  fn write(&mut self, bytes: &[u8]) {
+    let result = match self {
+      Encoder::Brotli(encoder) => encoder.write_all(bytes),
+      Encoder::Gzip(encoder) => encoder.write_all(bytes),
+      Encoder::Deflate(encoder) => encoder.write_all(bytes),
+    };
+    result.expect("Writing into a Vec should never fail!");
+  }
+
+  /// Compressed bytes of everything written so far, and not taken yet.
+  fn flush(&mut self) -> Vec<u8> {
+    let result = match self {
+      Encoder::Brotli(encoder) => encoder.flush(),
+      Encoder::Gzip(encoder) => encoder.flush(),
+      Encoder::Deflate(encoder) => encoder.flush(),
+    };
+    result.expect("Writing into a Vec should never fail!");
+    mem::take(match self {
+      Encoder::Brotli(encoder) => encoder.get_mut(),
+      Encoder::Gzip(encoder) => encoder.get_mut(),
+      Encoder::Deflate(encoder) => encoder.get_mut(),
+    })
+  }
+
+  /// Compressed bytes not taken yet, ending the stream.
+  fn finish(self) -> Vec<u8> {
+    match self {
+      Encoder::Brotli(encoder) => Ok(encoder.into_inner()),
+      Encoder::Gzip(encoder) => encoder.finish(),
+      Encoder::Deflate(encoder) => encoder.finish(),
+    }.expect("Writing into a Vec should never fail!")
+  }
+}
+
+/// Chunks of a chunked body compressed one by one, so that each is sent as
+/// soon as it is produced.
+struct CompressedChunks {
+  chunks: Chunks<'static>,
+  encoder: Option<Encoder>,
+}
+
+impl Iterator for CompressedChunks {
+  type Item = Vec<u8>;
+
+  fn next(&mut self) -> Option<Vec<u8>> {
+    let encoder = self.encoder.as_mut()?;
+    match self.chunks.next() {
+      Some(chunk) => {
+        encoder.write(&chunk);
+        Some(encoder.flush())
+      }
+      None => self.encoder.take().map(Encoder::finish)
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::convert::TryFrom;
+  use std::io::Read;
+
+  use flate2::read::{GzDecoder, ZlibDecoder};
+
+  use super::*;
+  use crate::connection::RespondChecks;
+  use crate::http::version::HttpVersion;
+
+  fn negotiate(compression: Compression, accept_encoding: &str) -> ContentCoding {
+    let head = format!("GET / HTTP/1.1\r\nAccept-Encoding: {}\r\n\r\n", accept_encoding);
+    compression.negotiate(&HTTPRequest::try_from(head.as_str()).unwrap())
+  }
+
+  fn text_respond(body: Vec<u8>) -> HTTPRespond<'static> {
+    let mut respond = HTTPRespond::from_body(body, HttpVersion::Http_1_1, StatusCode::Ok, None);
+    HTTPRespond::with_header(&mut respond, HttpRespondHeader::ContentType("text/plain".into()));
+    respond
+  }
+
+  fn header<'r>(respond: &'r HTTPRespond, name: &str) -> Option<&'r str> {
+    respond.header.get(name).map(HttpRespondHeader::value)
+  }
+
+  fn is_compressed(respond: &HTTPRespond) -> bool {
+    respond.header.content_encoding().is_some()
+  }
+
+  fn decode(coding: ContentCoding, bytes: &[u8]) -> Vec<u8> {
+    let mut decoded = Vec::new();
+    match coding {
+      ContentCoding::Brotli => brotli::Decompressor::new(bytes, BROTLI_BUFFER_SIZE)
+          .read_to_end(&mut decoded),
+      ContentCoding::Gzip => GzDecoder::new(bytes).read_to_end(&mut decoded),
+      ContentCoding::Deflate => ZlibDecoder::new(bytes).read_to_end(&mut decoded),
+      ContentCoding::Identity => return bytes.to_vec(),
+    }.unwrap();
+    decoded
+  }
+
+  #[test]
+  fn negotiates_the_coding_of_highest_q_value() {
+    let compression = Compression::new();
+    assert_eq!(negotiate(compression, "gzip;q=0.5, deflate"), ContentCoding::Deflate);
+    assert_eq!(negotiate(compression, "gzip, deflate, br"), ContentCoding::Brotli);
+    assert_eq!(negotiate(compression, "deflate, gzip"), ContentCoding::Gzip);
+    assert_eq!(negotiate(compression, "x-gzip"), ContentCoding::Gzip);
+    assert_eq!(negotiate(compression, "X-GZIP;q=0.3, deflate;q=0.2"), ContentCoding::Gzip);
+    assert_eq!(negotiate(compression, "compress"), ContentCoding::Identity);
+    assert_eq!(negotiate(compression.brotli(false), "br, deflate;q=0.1"),
+               ContentCoding::Deflate);
+  }
+
+  #[test]
+  fn negotiates_wildcards_and_exclusions() {
+    let compression = Compression::new();
+    assert_eq!(negotiate(compression, "*"), ContentCoding::Brotli);
+    assert_eq!(negotiate(compression, "br;q=0, *"), ContentCoding::Gzip);
+    assert_eq!(negotiate(compression, "*;q=0.2, deflate;q=0.5"), ContentCoding::Deflate);
+    assert_eq!(negotiate(compression, "gzip;q=0"), ContentCoding::Identity);
+    assert_eq!(negotiate(compression, "gzip;q=x, deflate"), ContentCoding::Deflate);
+  }
+
+  #[test]
+  fn negotiates_identity_only_when_preferred() {
+    let compression = Compression::new();
+    assert_eq!(negotiate(compression, "gzip, identity;q=0"), ContentCoding::Gzip);
+    assert_eq!(negotiate(compression, "identity;q=0"), ContentCoding::Identity);
+    assert_eq!(negotiate(compression, "identity, gzip;q=0.5"), ContentCoding::Identity);
+    assert_eq!(negotiate(compression, "identity;q=0.5, gzip"), ContentCoding::Gzip);
+    assert_eq!(negotiate(compression, "identity;q=0.5, gzip;q=0.5"), ContentCoding::Gzip);
+    let request = HTTPRequest::try_from("GET / HTTP/1.1\r\n\r\n").unwrap();
+    assert_eq!(compression.negotiate(&request), ContentCoding::Identity);
+  }
+
+  #[test]
+  fn compresses_eligible_responds_only() {
+    let compression = Compression::new().min_size(16);
+    let long = b"Hello, compression! ".repeat(4);
+
+    let respond = compression.apply(ContentCoding::Gzip, text_respond(long[..15].to_vec()));
+    assert!(!is_compressed(&respond));
+    assert_eq!(header(&respond, "Vary"), None);
+
+    let respond = compression.apply(ContentCoding::Gzip, text_respond(long[..16].to_vec()));
+    assert_eq!(header(&respond, "Content-Encoding"), Some("gzip"));
+
+    let mut image = text_respond(long.clone());
+    image.header.insert(HttpRespondHeader::ContentType("image/png".into()));
+    assert!(!is_compressed(&compression.apply(ContentCoding::Gzip, image)));
+
+    let mut json = text_respond(long.clone());
+    json.header.insert(HttpRespondHeader::ContentType("application/ld+json".into()));
+    assert!(is_compressed(&compression.apply(ContentCoding::Gzip, json)));
+
+    let mut encoded = text_respond(long.clone());
+    HTTPRespond::with_header(&mut encoded, HttpRespondHeader::ContentEncoding("br".into()));
+    let respond = compression.apply(ContentCoding::Gzip, encoded);
+    assert_eq!(header(&respond, "Content-Encoding"), Some("br"));
+    assert_eq!(respond.header.get_all("Content-Encoding").count(), 1);
+
+    let mut not_found = text_respond(long.clone());
+    not_found.status_code = StatusCode::NotFound;
+    assert!(!is_compressed(&compression.apply(ContentCoding::Gzip, not_found)));
+  }
+
+  #[test]
+  fn compresses_responds_to_get_only() {
+    let compression = Some(Compression::new().min_size(0));
+    let body = b"Hello, compression!".to_vec();
+    for (method, compressed) in [("GET", true), ("HEAD", false), ("POST", false)] {
+      let head = format!("{} / HTTP/1.1\r\nAccept-Encoding: gzip\r\n\r\n", method);
+      let request = HTTPRequest::try_from(head.as_str()).unwrap();
+      let respond = RespondChecks::of(&request, compression).apply(text_respond(body.clone()));
+      assert_eq!(is_compressed(&respond), compressed, "{}", method);
+    }
+  }
+
+  #[test]
+  fn adds_accept_encoding_to_vary() {
+    let compression = Compression::new().min_size(0);
+    let with_vary = |vary: &'static str| {
+      let mut respond = text_respond(b"Hello".to_vec());
+      HTTPRespond::with_header(&mut respond, HttpRespondHeader::Vary(vary.into()));
+      respond
+    };
+
+    let respond = compression.apply(ContentCoding::Identity, text_respond(b"Hello".to_vec()));
+    assert_eq!(header(&respond, "Vary"), Some("Accept-Encoding"));
+    assert!(!is_compressed(&respond));
+
+    let respond = compression.apply(ContentCoding::Gzip, with_vary("Origin"));
+    assert_eq!(header(&respond, "Vary"), Some("Origin, Accept-Encoding"));
+    assert_eq!(respond.header.get_all("Vary").count(), 1);
+
+    let respond = compression.apply(ContentCoding::Gzip, with_vary("Origin, accept-encoding"));
+    assert_eq!(header(&respond, "Vary"), Some("Origin, accept-encoding"));
+
+    let respond = compression.apply(ContentCoding::Gzip, with_vary("*"));
+    assert_eq!(header(&respond, "Vary"), Some("*"));
+  }
+
+  #[test]
+  fn weakens_etags_of_compressed_responds() {
+    let compression = Compression::new().min_size(0);
+    let with_etag = |etag: &'static str| {
+      let mut respond = text_respond(b"Hello".to_vec());
+      HTTPRespond::with_header(&mut respond, HttpRespondHeader::ETag(etag.into()));
+      respond
+    };
+
+    let respond = compression.apply(ContentCoding::Gzip, with_etag("\"v1\""));
+    assert_eq!(header(&respond, "ETag"), Some("W/\"v1\""));
+    let respond = compression.apply(ContentCoding::Gzip, with_etag("W/\"v1\""));
+    assert_eq!(header(&respond, "ETag"), Some("W/\"v1\""));
+    let respond = compression.apply(ContentCoding::Identity, with_etag("\"v1\""));
+    assert_eq!(header(&respond, "ETag"), Some("\"v1\""));
+  }
+
+  #[test]
+  fn round_trips_full_bodies() {
+    let body = b"Hello, compression! ".repeat(100);
+    for coding in [ContentCoding::Brotli, ContentCoding::Gzip, ContentCoding::Deflate] {
+      let mut respond = text_respond(body.clone());
+      HTTPRespond::with_header(&mut respond,
+                               HttpRespondHeader::ContentLength(body.len().to_string().into()));
+      let respond = Compression::new().apply(coding, respond);
+      assert_eq!(header(&respond, "Content-Encoding"), Some(coding.as_str()));
+      assert_eq!(header(&respond, "Content-Length"), None);
+      match respond.body {
+        RespondBody::Full(compressed) => {
+          assert!(compressed.len() < body.len());
+          assert_eq!(decode(coding, &compressed), body, "{:?}", coding);
+        }
+        RespondBody::Chunked(_) => panic!("Unexpected chunked body!")
+      }
+    }
+  }
+
+  #[test]
+  fn round_trips_chunked_bodies_chunk_by_chunk() {
+    let chunks = (0..20).map(|index| format!("Chunk #{} of text, ", index).repeat(10))
+        .map(String::into_bytes)
+        .collect::<Vec<Vec<u8>>>();
+    for coding in [ContentCoding::Brotli, ContentCoding::Gzip, ContentCoding::Deflate] {
+      let mut respond = HTTPRespond::from_chunks(chunks.clone().into_iter(),
+                                                 HttpVersion::Http_1_1, StatusCode::Ok, None);
+      HTTPRespond::with_header(&mut respond, HttpRespondHeader::ContentType("text/csv".into()));
+      let respond = Compression::new().apply(coding, respond);
+      let compressed = match respond.body {
+        RespondBody::Chunked(compressed) => compressed.collect::<Vec<Vec<u8>>>(),
+        RespondBody::Full(_) => panic!("Unexpected full body!")
+      };
+      // One per chunk, as soon as produced, then the end of the stream
+      assert_eq!(compressed.len(), chunks.len() + 1);
+      assert_eq!(decode(coding, &compressed.concat()), chunks.concat(), "{:?}", coding);
+    }
+  }
+}
@@ -0,0 +1,456 @@
+//! Conditional requests and caching, see RFC 7232 and RFC 7234.
+//!
+//! A respond carrying an `ETag` or `Last-Modified` validator is checked
+//! against the `If-*` header fields of a `GET` or `HEAD` request before
+//! being written, and replaced with `304 Not Modified` or
+//! `412 Precondition Failed` as they dictate. Handlers of other methods
+//! should check preconditions themselves before acting, with
+//! `HTTPRequest::evaluate_preconditions`.
+
+use std::fmt::{self, Display, Formatter};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::http::date::parse_http_date;
+use crate::http::request::{HTTPRequest, HTTPRequestHeader, HttpMethod};
+use crate::http::respond::{HTTPRespond, HttpRespondHeader, StatusCode};
+
+/// Opaque validator of a representation, written as `"tag"`, or `W/"tag"`
+/// if weak.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntityTag {
+  pub weak: bool,
+  pub tag: String,
+}
+
+impl EntityTag {
+  /// Validator changing with every byte of the representation.
+  ///
+  /// Panics if `tag` contains a `"`.
+  pub fn strong<S: Into<String>>(tag: S) -> Self {
+    EntityTag::new(false, tag.into())
+  }
+
+  /// Validator only changing with the meaning of the representation.
+  ///
+  /// Panics if `tag` contains a `"`.
+  pub fn weak<S: Into<String>>(tag: S) -> Self {
+    EntityTag::new(true, tag.into())
+  }
+
+  fn new(weak: bool, tag: String) -> Self {
+    assert!(!tag.contains('"'), "Entity tag [{}] must not contain `\"`!", tag);
+    EntityTag { weak, tag }
+  }
+
+  /// Strong validator hashing `bytes`.
+  pub fn from_bytes(bytes: &[u8]) -> Self {
+    // FNV-1a, stable across builds unlike `DefaultHasher`
+    let hash = bytes.iter().fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+      (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    });
+    EntityTag::strong(format!("{:016x}", hash))
+  }
+
+  /// Strong validator of a file of `len` bytes last modified at `modified`.
+  pub fn from_metadata(len: u64, modified: SystemTime) -> Self {
+    let modified = modified.duration_since(UNIX_EPOCH).unwrap_or_default();
+    EntityTag::strong(format!("{:x}-{:x}", len, modified.as_secs()))
+  }
+
+  /// Parse an entity-tag, `None` if malformed.
+  pub fn parse(s: &str) -> Option<Self> {
+    let (weak, quoted) = match s.strip_prefix("W/") {
+      Some(quoted) => (true, quoted),
+      None => (false, s)
+    };
+    let tag = quoted.strip_prefix('"')?.strip_suffix('"')?;
+    if tag.contains('"') {
+      return None;
+    }
+    Some(EntityTag { weak, tag: tag.to_owned() })
+  }
+
+  /// Both tags are strong and identical.
+  pub fn strong_eq(&self, other: &EntityTag) -> bool {
+    !self.weak && !other.weak && self.tag == other.tag
+  }
+
+  /// Both tags are identical, weak or not.
+  pub fn weak_eq(&self, other: &EntityTag) -> bool {
+    self.tag == other.tag
+  }
+}
+
+impl Display for EntityTag {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    if self.weak {
+      f.write_str("W/")?;
+    }
+    write!(f, "\"{}\"", self.tag)
+  }
+}
+
+/// Value of a `Cache-Control` respond header field.
+///
+/// Example:
+/// ```no run
+/// let cache_control = CacheControl::new().public().max_age(Duration::from_secs(3600));
+/// HTTPRespond::with_cache_control(&mut respond, &cache_control);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CacheControl {
+  directives: Vec<String>,
+}
+
+impl CacheControl {
+  pub fn new() -> Self {
+    CacheControl { directives: Vec::new() }
+  }
+
+  /// Add `directive` as is, e.g. `stale-while-revalidate=60`.
+  pub fn directive<S: Into<String>>(mut self, directive: S) -> Self {
+    self.directives.push(directive.into());
+    self
+  }
+
+  pub fn public(self) -> Self {
+    self.directive("public")
+  }
+
+  pub fn private(self) -> Self {
+    self.directive("private")
+  }
+
+  /// Caches must revalidate before every reuse.
+  pub fn no_cache(self) -> Self {
+    self.directive("no-cache")
+  }
+
+  /// Caches must not store the respond at all.
+  pub fn no_store(self) -> Self {
+    self.directive("no-store")
+  }
+
+  pub fn must_revalidate(self) -> Self {
+    self.directive("must-revalidate")
+  }
+
+  /// The respond never changes while fresh, e.g. for fingerprinted assets.
+  pub fn immutable(self) -> Self {
+    self.directive("immutable")
+  }
+
+  pub fn max_age(self, max_age: Duration) -> Self {
+    self.directive(format!("max-age={}", max_age.as_secs()))
+  }
+
+  /// `max_age` for shared caches only.
+  pub fn s_maxage(self, max_age: Duration) -> Self {
+    self.directive(format!("s-maxage={}", max_age.as_secs()))
+  }
+}
+
+impl Display for CacheControl {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    f.write_str(&self.directives.join(", "))
+  }
+}
+
+/// Enum of values of `If-Match` and `If-None-Match`
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum TagList {
+  Any,
+  Tags(Vec<EntityTag>),
+}
+
+impl TagList {
+  /// Parse `*` or a list of entity-tags, skipping malformed ones.
+  fn parse(value: &str) -> Self {
+    if value.trim() == "*" {
+      return TagList::Any;
+    }
+    TagList::Tags(value.split(',')
+        .filter_map(|tag| EntityTag::parse(tag.trim()))
+        .collect())
+  }
+
+  fn matches(&self, etag: Option<&EntityTag>,
+             eq: fn(&EntityTag, &EntityTag) -> bool) -> bool {
+    match (self, etag) {
+      (TagList::Any, etag) => etag.is_some(),
+      (TagList::Tags(tags), Some(etag)) => tags.iter().any(|tag| eq(tag, etag)),
+      (TagList::Tags(_), None) => false,
+    }
+  }
+}
+
+/// Conditional header fields of a request, kept beyond the request buffer.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Preconditions {
+  safe: bool,
+  if_match: Option<TagList>,
+  if_none_match: Option<TagList>,
+  if_modified_since: Option<SystemTime>,
+  if_unmodified_since: Option<SystemTime>,
+}
+
+impl Preconditions {
+  /// Conditional header fields of `request`, `None` if it has none.
+  ///
+  /// Fields with an invalid date are ignored, as RFC 7232 requires.
+  pub fn of(request: &HTTPRequest) -> Option<Self> {
+    let mut preconditions = Preconditions {
+      safe: matches!(request.method, HttpMethod::Get | HttpMethod::Head),
+      ..Preconditions::default()
+    };
+    let mut any = false;
+    for header in &request.header {
+      match header {
+        HTTPRequestHeader::IfMatch(value) =>
+          preconditions.if_match = Some(TagList::parse(value)),
+        HTTPRequestHeader::IfNoneMatch(value) =>
+          preconditions.if_none_match = Some(TagList::parse(value)),
+        HTTPRequestHeader::IfModifiedSince(value) =>
+          preconditions.if_modified_since = parse_http_date(value),
+        HTTPRequestHeader::IfUnmodifiedSince(value) =>
+          preconditions.if_unmodified_since = parse_http_date(value),
+        _ => continue
+      }
+      any = true;
+    }
+    if any { Some(preconditions) } else { None }
+  }
+
+  /// Evaluate the preconditions in the order of RFC 7232 section 6 against
+  /// the current validators of the target resource. Returns the status code
+  /// to answer with instead if any fails.
+  pub fn evaluate(&self, etag: Option<&EntityTag>,
+                  last_modified: Option<SystemTime>) -> Option<StatusCode> {
+    // HTTP-dates only have a resolution of one second
+    let last_modified = last_modified.map(truncate_to_secs);
+
+    if let Some(if_match) = &self.if_match {
+      if !if_match.matches(etag, EntityTag::strong_eq) {
+        return Some(StatusCode::PreconditionFailed);
+      }
+    } else if let (Some(since), Some(modified)) = (self.if_unmodified_since, last_modified) {
+      if modified > since {
+        return Some(StatusCode::PreconditionFailed);
+      }
+    }
+
+    if let Some(if_none_match) = &self.if_none_match {
+      if if_none_match.matches(etag, EntityTag::weak_eq) {
+        return Some(if self.safe {
+          StatusCode::NotModified
+        } else {
+          StatusCode::PreconditionFailed
+        });
+      }
+    } else if let (true, Some(since), Some(modified)) =
+        (self.safe, self.if_modified_since, last_modified) {
+      if modified <= since {
+        return Some(StatusCode::NotModified);
+      }
+    }
+    None
+  }
+
+  /// Replace a successful `respond` by `304 Not Modified` or
+  /// `412 Precondition Failed` if its validators fail the preconditions.
+  pub fn apply(&self, respond: HTTPRespond<'static>) -> HTTPRespond<'static> {
+    if !(200..300).contains(&respond.status_code.code()) {
+      return respond;
+    }
+    let etag = respond.header.etag();
+    match self.evaluate(etag.as_ref(), respond.header.last_modified()) {
+      Some(StatusCode::NotModified) => {
+        // Only the header fields a `200 OK` would also update caches with
+        let mut not_modified = HTTPRespond::from_body(
+          &b""[..], respond.http_version, StatusCode::NotModified, None);
+        not_modified.header = respond.header.into_iter()
+            .filter(|header| matches!(header,
+              HttpRespondHeader::CacheControl(_) | HttpRespondHeader::Date(_) |
+              HttpRespondHeader::ETag(_) | HttpRespondHeader::Expires(_) |
+              HttpRespondHeader::Vary(_)))
+            .collect();
+        not_modified
+      }
+      Some(status_code) => HTTPRespond::from_body(
+        status_code.reason_phrase().as_bytes(), respond.http_version, status_code, None),
+      None => respond
+    }
+  }
+}
+
+#[inline]
+fn truncate_to_secs(time: SystemTime) -> SystemTime {
+  let secs = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+  UNIX_EPOCH + Duration::from_secs(secs)
+}
+
+#[cfg(test)]
+mod tests {
+  use std::convert::TryFrom;
+
+  use super::*;
+  use crate::http::date::fmt_http_date;
+  use crate::http::version::HttpVersion;
+
+  const MODIFIED: u64 = 784_111_777;
+
+  fn at(secs: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(secs)
+  }
+
+  fn date(secs: u64) -> String {
+    fmt_http_date(at(secs))
+  }
+
+  fn preconditions(method: &str, fields: &[(&str, &str)]) -> Preconditions {
+    let mut head = format!("{} / HTTP/1.1\r\n", method);
+    for (name, value) in fields {
+      head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+    Preconditions::of(&HTTPRequest::try_from(head.as_str()).unwrap()).unwrap()
+  }
+
+  /// Status code the request of `fields` is answered with instead, against
+  /// the strong `"v1"` last modified at `MODIFIED`.
+  fn evaluate(method: &str, fields: &[(&str, &str)]) -> Option<StatusCode> {
+    preconditions(method, fields).evaluate(Some(&EntityTag::strong("v1")), Some(at(MODIFIED)))
+  }
+
+  #[test]
+  fn parses_and_formats_entity_tags() {
+    assert_eq!(EntityTag::parse("\"v1\""), Some(EntityTag::strong("v1")));
+    assert_eq!(EntityTag::parse("W/\"v1\""), Some(EntityTag::weak("v1")));
+    assert_eq!(EntityTag::parse("v1"), None);
+    assert_eq!(EntityTag::parse("\"v\"1\""), None);
+    assert_eq!(EntityTag::weak("v1").to_string(), "W/\"v1\"");
+  }
+
+  #[test]
+  fn compares_weak_and_strong_tags() {
+    let (strong, weak) = (EntityTag::strong("v1"), EntityTag::weak("v1"));
+    assert!(strong.strong_eq(&EntityTag::strong("v1")));
+    assert!(!strong.strong_eq(&weak));
+    assert!(!weak.strong_eq(&weak));
+    assert!(strong.weak_eq(&weak) && weak.weak_eq(&weak));
+    assert!(!strong.weak_eq(&EntityTag::strong("v2")));
+  }
+
+  #[test]
+  fn matches_if_match_strongly() {
+    assert_eq!(evaluate("PUT", &[("If-Match", "\"v0\", \"v1\"")]), None);
+    assert_eq!(evaluate("PUT", &[("If-Match", "W/\"v1\"")]),
+               Some(StatusCode::PreconditionFailed));
+    assert_eq!(evaluate("PUT", &[("If-Match", "\"v2\"")]), Some(StatusCode::PreconditionFailed));
+  }
+
+  #[test]
+  fn matches_if_none_match_weakly() {
+    assert_eq!(evaluate("GET", &[("If-None-Match", "W/\"v1\"")]), Some(StatusCode::NotModified));
+    assert_eq!(evaluate("GET", &[("If-None-Match", "\"v2\"")]), None);
+    let weak = preconditions("GET", &[("If-None-Match", "\"v1\"")])
+        .evaluate(Some(&EntityTag::weak("v1")), None);
+    assert_eq!(weak, Some(StatusCode::NotModified));
+  }
+
+  #[test]
+  fn matches_any_tag_with_a_star() {
+    assert_eq!(evaluate("PUT", &[("If-Match", "*")]), None);
+    assert_eq!(evaluate("PUT", &[("If-None-Match", "*")]), Some(StatusCode::PreconditionFailed));
+    // `*` only matches a current representation
+    let absent = preconditions("PUT", &[("If-Match", "*")]).evaluate(None, None);
+    assert_eq!(absent, Some(StatusCode::PreconditionFailed));
+    let absent = preconditions("PUT", &[("If-None-Match", "*")]).evaluate(None, None);
+    assert_eq!(absent, None);
+  }
+
+  #[test]
+  fn answers_safe_methods_not_modified_and_others_failed() {
+    for method in ["GET", "HEAD"] {
+      assert_eq!(evaluate(method, &[("If-None-Match", "\"v1\"")]), Some(StatusCode::NotModified));
+    }
+    for method in ["POST", "PUT", "DELETE"] {
+      assert_eq!(evaluate(method, &[("If-None-Match", "\"v1\"")]),
+                 Some(StatusCode::PreconditionFailed));
+    }
+    // If-Modified-Since only applies to `GET` and `HEAD`
+    let since = date(MODIFIED);
+    assert_eq!(evaluate("GET", &[("If-Modified-Since", &since)]), Some(StatusCode::NotModified));
+    assert_eq!(evaluate("PUT", &[("If-Modified-Since", &since)]), None);
+  }
+
+  #[test]
+  fn compares_dates_to_the_second() {
+    let modified = Some(at(MODIFIED) + Duration::from_millis(500));
+    let since = date(MODIFIED);
+    let unmodified = preconditions("GET", &[("If-Modified-Since", &since)]);
+    assert_eq!(unmodified.evaluate(None, modified), Some(StatusCode::NotModified));
+    let earlier = date(MODIFIED - 1);
+    let modified_since = preconditions("GET", &[("If-Modified-Since", &earlier)]);
+    assert_eq!(modified_since.evaluate(None, modified), None);
+    let unmodified_since = preconditions("PUT", &[("If-Unmodified-Since", &earlier)]);
+    assert_eq!(unmodified_since.evaluate(None, modified), Some(StatusCode::PreconditionFailed));
+  }
+
+  #[test]
+  fn evaluates_if_match_before_if_unmodified_since() {
+    let earlier = date(MODIFIED - 1);
+    // A matching If-Match makes If-Unmodified-Since irrelevant
+    assert_eq!(evaluate("PUT", &[("If-Unmodified-Since", &earlier), ("If-Match", "\"v1\"")]),
+               None);
+    assert_eq!(evaluate("PUT", &[("If-Unmodified-Since", &earlier)]),
+               Some(StatusCode::PreconditionFailed));
+    // And a failing one fails before If-None-Match is looked at
+    assert_eq!(evaluate("GET", &[("If-None-Match", "\"v1\""), ("If-Match", "\"v2\"")]),
+               Some(StatusCode::PreconditionFailed));
+  }
+
+  #[test]
+  fn evaluates_if_none_match_before_if_modified_since() {
+    let since = date(MODIFIED);
+    // A failing If-None-Match makes If-Modified-Since irrelevant
+    assert_eq!(evaluate("GET", &[("If-Modified-Since", &since), ("If-None-Match", "\"v2\"")]),
+               None);
+    assert_eq!(evaluate("GET", &[("If-Modified-Since", &since)]), Some(StatusCode::NotModified));
+  }
+
+  #[test]
+  fn ignores_invalid_dates() {
+    assert_eq!(evaluate("GET", &[("If-Modified-Since", "yesterday")]), None);
+    assert_eq!(evaluate("PUT", &[("If-Unmodified-Since", "yesterday")]), None);
+    let request = HTTPRequest::try_from("GET / HTTP/1.1\r\nHost: a\r\n\r\n").unwrap();
+    assert_eq!(Preconditions::of(&request), None);
+  }
+
+  #[test]
+  fn replaces_successful_responds_only() {
+    let respond = || {
+      let mut respond = HTTPRespond::from_body(
+        &b"Hello"[..], HttpVersion::Http_1_1, StatusCode::Ok, None);
+      HTTPRespond::with_etag(&mut respond, &EntityTag::strong("v1"));
+      HTTPRespond::with_last_modified(&mut respond, at(MODIFIED));
+      HTTPRespond::with_header(&mut respond, HttpRespondHeader::ContentType("text/plain".into()));
+      HTTPRespond::with_header(&mut respond, HttpRespondHeader::Vary("Accept".into()));
+      respond
+    };
+
+    let not_modified = preconditions("GET", &[("If-None-Match", "\"v1\"")]).apply(respond());
+    assert_eq!(not_modified.status_code, StatusCode::NotModified);
+    assert_eq!(not_modified.header.etag(), Some(EntityTag::strong("v1")));
+    assert!(not_modified.header.contains("Vary"));
+    assert!(!not_modified.header.contains("Content-Type"));
+
+    let failed = preconditions("GET", &[("If-Match", "\"v2\"")]).apply(respond());
+    assert_eq!(failed.status_code, StatusCode::PreconditionFailed);
+
+    let mut not_found = respond();
+    not_found.status_code = StatusCode::NotFound;
+    let not_found = preconditions("GET", &[("If-Match", "\"v2\"")]).apply(not_found);
+    assert_eq!(not_found.status_code, StatusCode::NotFound);
+  }
+}
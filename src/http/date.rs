@@ -0,0 +1,161 @@
+//! HTTP-date in the preferred IMF-fixdate format, e.g.
+//! `Sun, 06 Nov 1994 08:49:37 GMT`.
+//!
+//! The obsolete RFC 850 and asctime formats are understood when parsing,
+//! e.g. `Sunday, 06-Nov-94 08:49:37 GMT` and `Sun Nov  6 08:49:37 1994`.
+
+use std::convert::TryFrom;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const WEEKDAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun",
+  "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+/// Format `time` as IMF-fixdate.
+pub fn fmt_http_date(time: SystemTime) -> String {
+  let secs = time.duration_since(UNIX_EPOCH)
+      .map(|duration| duration.as_secs())
+      .unwrap_or(0);
+  let days = (secs / 86400) as i64;
+  let secs_of_day = secs % 86400;
+  let (year, month, day) = civil_from_days(days);
+
+  format!("{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+          // 1970-01-01 was a Thursday
+          WEEKDAYS[((days + 4) % 7) as usize],
+          day, MONTHS[(month - 1) as usize], year,
+          secs_of_day / 3600, secs_of_day % 3600 / 60, secs_of_day % 60)
+}
+
+/// Parse an HTTP-date in any of the three formats, `None` if invalid.
+pub fn parse_http_date(date: &str) -> Option<SystemTime> {
+  let fields = date.split([' ', ',']).filter(|field| !field.is_empty())
+      .collect::<Vec<&str>>();
+  let (day, month, year, time) = match fields.as_slice() {
+    // IMF-fixdate
+    [_, day, month, year, time, "GMT"] => (*day, *month, parse_year(year)?, *time),
+    // RFC 850, with a two-digit year
+    [_, date, time, "GMT"] => {
+      let mut parts = date.split('-');
+      let (day, month, year) = (parts.next()?, parts.next()?, parts.next()?);
+      if parts.next().is_some() {
+        return None;
+      }
+      (day, month, parse_year(year)?, *time)
+    }
+    // asctime
+    [_, month, day, time, year] => (*day, *month, parse_year(year)?, *time),
+    _ => return None
+  };
+
+  let day: u32 = day.parse().ok()?;
+  let month = MONTHS.iter().position(|name| *name == month)? as u32 + 1;
+  let mut time = time.split(':').map(|field| field.parse::<u64>().ok());
+  let (hour, minute, second) = (time.next()??, time.next()??, time.next()??);
+  if time.next().is_some() || !(1..=31).contains(&day) ||
+      hour > 23 || minute > 59 || second > 60 {
+    return None;
+  }
+
+  let days = u64::try_from(days_from_civil(year, month, day)).ok()?;
+  let secs = days.checked_mul(86400)?.checked_add(hour * 3600 + minute * 60 + second)?;
+  UNIX_EPOCH.checked_add(Duration::from_secs(secs))
+}
+
+/// Parse a year of four digits, or of two digits taken to be within 1970
+/// and 2069, `None` otherwise so that dates stay within range.
+fn parse_year(year: &str) -> Option<i64> {
+  if !year.bytes().all(|byte| byte.is_ascii_digit()) {
+    return None;
+  }
+  let value: i64 = year.parse().ok()?;
+  match year.len() {
+    4 => Some(value),
+    2 => Some(if value < 70 { 2000 + value } else { 1900 + value }),
+    _ => None
+  }
+}
+
+/// Convert (year, month, day) into days since 1970-01-01, see
+/// <http://howardhinnant.github.io/date_algorithms.html#days_from_civil>.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+  let year = if month <= 2 { year - 1 } else { year };
+  let era = year.div_euclid(400);
+  let yoe = year.rem_euclid(400);
+  let mp = i64::from(if month > 2 { month - 3 } else { month + 9 });
+  let doy = (153 * mp + 2) / 5 + i64::from(day) - 1;
+  let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+  era * 146_097 + doe - 719_468
+}
+
+/// Convert days since 1970-01-01 into (year, month, day), see
+/// <http://howardhinnant.github.io/date_algorithms.html#civil_from_days>.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+  let z = days + 719_468;
+  let era = z.div_euclid(146_097);
+  let doe = z.rem_euclid(146_097);
+  let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+  let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+  let mp = (5 * doy + 2) / 153;
+  let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+  let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+  let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+  (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn secs(date: &str) -> Option<u64> {
+    parse_http_date(date).map(|time| time.duration_since(UNIX_EPOCH).unwrap().as_secs())
+  }
+
+  #[test]
+  fn parses_all_three_formats() {
+    assert_eq!(secs("Sun, 06 Nov 1994 08:49:37 GMT"), Some(784_111_777));
+    assert_eq!(secs("Sunday, 06-Nov-94 08:49:37 GMT"), Some(784_111_777));
+    assert_eq!(secs("Sun Nov  6 08:49:37 1994"), Some(784_111_777));
+  }
+
+  #[test]
+  fn maps_two_digit_years_within_1970_and_2069() {
+    assert_eq!(secs("Thursday, 01-Jan-70 00:00:00 GMT"), Some(0));
+    assert_eq!(secs("Sunday, 06-Nov-94 08:49:37 GMT"),
+               secs("Sun, 06 Nov 1994 08:49:37 GMT"));
+    assert_eq!(secs("Wednesday, 01-Jan-69 00:00:00 GMT"),
+               secs("Tue, 01 Jan 2069 00:00:00 GMT"));
+  }
+
+  #[test]
+  fn keeps_four_digit_years_of_rfc_850() {
+    assert_eq!(secs("Sunday, 06-Nov-1994 08:49:37 GMT"), Some(784_111_777));
+  }
+
+  #[test]
+  fn round_trips_through_imf_fixdate() {
+    for secs in [0, 784_111_777, 951_782_400, 4_102_444_799] {
+      let time = UNIX_EPOCH + Duration::from_secs(secs);
+      assert_eq!(parse_http_date(&fmt_http_date(time)), Some(time));
+    }
+  }
+
+  #[test]
+  fn rejects_out_of_range_years() {
+    assert_eq!(secs("Sun, 06 Nov 317000000000 08:49:37 GMT"), None);
+    assert_eq!(secs("Sun, 06 Nov 9223372036854775807 08:49:37 GMT"), None);
+    assert_eq!(secs("Sunday, 06-Nov-317000000000 08:49:37 GMT"), None);
+    assert_eq!(secs("Sun Nov  6 08:49:37 317000000000"), None);
+    assert_eq!(secs("Sun, 06 Nov 1969 08:49:37 GMT"), None);
+  }
+
+  #[test]
+  fn rejects_malformed_dates() {
+    assert_eq!(secs(""), None);
+    assert_eq!(secs("Sun, 06 Nov 1994 24:00:00 GMT"), None);
+    assert_eq!(secs("Sun, 32 Nov 1994 08:49:37 GMT"), None);
+    assert_eq!(secs("Sun, 06 Foo 1994 08:49:37 GMT"), None);
+    assert_eq!(secs("Sun, 06 Nov +994 08:49:37 GMT"), None);
+    assert_eq!(secs("Sun, 06 Nov 1994 08:49:37 UTC"), None);
+  }
+}
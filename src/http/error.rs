@@ -0,0 +1,172 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter, Result};
+
+use crate::http::respond::StatusCode;
+
+/// Enum of reasons a HTTP Request could not be parsed
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+  // Request line does not consist of Method SP Request-URI SP HTTP-Version.
+  InvalidRequestLine,
+
+  // Method is not one of `HttpMethod`.
+  InvalidMethod(String),
+
+  // Request-URI is neither `*`, an absolute URI nor an absolute path.
+  InvalidUri(String),
+
+  // HTTP-Version is not one of `HttpVersion`.
+  InvalidVersion(String),
+
+  // Header line without a colon, or with an unparsable value.
+  MalformedHeader { line: String },
+
+  // Content-Length is not a non-negative integer, or conflicts with another
+  // Content-Length or with Transfer-Encoding.
+  InvalidContentLength,
+
+  // Chunk size line or chunk delimiter of a chunked body is malformed.
+  InvalidChunk,
+
+  // Transfer-Encoding does not end with `chunked`.
+  UnsupportedTransferEncoding,
+
+  // Request head is not valid UTF-8.
+  InvalidEncoding,
+
+  // Request line is longer than `RequestLimits::request_line`.
+  RequestLineTooLong,
+
+  // Header line is longer than `RequestLimits::header_line`.
+  HeaderTooLarge,
+
+  // More header fields than `RequestLimits::headers`.
+  TooManyHeaders,
+
+  // Request head is larger than `RequestLimits::head`.
+  HeadTooLarge,
+
+  // Request body is larger than `RequestLimits::body`.
+  BodyTooLarge,
+}
+
+impl ParseError {
+  /// Status code of the respond to a request failing with this error.
+  pub fn status_code(&self) -> StatusCode {
+    match self {
+      ParseError::RequestLineTooLong => StatusCode::URITooLong,
+      ParseError::HeaderTooLarge |
+      ParseError::TooManyHeaders |
+      ParseError::HeadTooLarge => StatusCode::RequestHeaderFieldsTooLarge,
+      ParseError::BodyTooLarge => StatusCode::PayloadTooLarge,
+      _ => StatusCode::BadRequest
+    }
+  }
+}
+
+impl Display for ParseError {
+  fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+    match self {
+      ParseError::InvalidRequestLine =>
+        write!(f, "Invalid Request Line!"),
+      ParseError::InvalidMethod(method) =>
+        write!(f, "Bad HTTP Method [{}]!", method),
+      ParseError::InvalidUri(uri) =>
+        write!(f, "Bad Request URI [{}]!", uri),
+      ParseError::InvalidVersion(version) =>
+        write!(f, "Bad Http Version [{}]!", version),
+      ParseError::MalformedHeader { line } =>
+        write!(f, "Malformed header [{}]!", line),
+      ParseError::InvalidContentLength =>
+        write!(f, "Invalid Content-Length!"),
+      ParseError::InvalidChunk =>
+        write!(f, "Invalid chunk in chunked body!"),
+      ParseError::UnsupportedTransferEncoding =>
+        write!(f, "Unsupported Transfer-Encoding!"),
+      ParseError::InvalidEncoding =>
+        write!(f, "Request head is not valid UTF-8!"),
+      ParseError::RequestLineTooLong =>
+        write!(f, "Request line too long!"),
+      ParseError::HeaderTooLarge =>
+        write!(f, "Header field too large!"),
+      ParseError::TooManyHeaders =>
+        write!(f, "Too many header fields!"),
+      ParseError::HeadTooLarge =>
+        write!(f, "Request head too large!"),
+      ParseError::BodyTooLarge =>
+        write!(f, "Request body too large!"),
+    }
+  }
+}
+
+impl Error for ParseError {}
+
+#[cfg(test)]
+mod tests {
+  use std::convert::TryFrom;
+
+  use super::*;
+  use crate::http::request::{HTTPRequest, HTTPRequestHeader, HttpMethod, RequestURI};
+  use crate::http::version::HttpVersion;
+
+  fn parse_err(head: &str) -> ParseError {
+    HTTPRequest::try_from(head).unwrap_err()
+  }
+
+  #[test]
+  fn fails_to_parse_request_lines() {
+    assert_eq!(parse_err("GET /\r\n\r\n"), ParseError::InvalidRequestLine);
+    assert_eq!(parse_err("GET  / HTTP/1.1\r\n\r\n"), ParseError::InvalidRequestLine);
+    assert_eq!(parse_err("FETCH / HTTP/1.1\r\n\r\n"),
+               ParseError::InvalidMethod("FETCH".to_owned()));
+    assert_eq!(parse_err("GET index.html HTTP/1.1\r\n\r\n"),
+               ParseError::InvalidUri("index.html".to_owned()));
+    assert_eq!(parse_err("GET / HTTP/3\r\n\r\n"), ParseError::InvalidVersion("HTTP/3".to_owned()));
+  }
+
+  #[test]
+  fn fails_to_parse_header_fields() {
+    assert_eq!(parse_err("GET / HTTP/1.1\r\nHost\r\n\r\n"),
+               ParseError::MalformedHeader { line: "Host".to_owned() });
+    assert_eq!(parse_err("GET / HTTP/1.1\r\nHost : a\r\n\r\n"),
+               ParseError::MalformedHeader { line: "Host : a".to_owned() });
+    assert_eq!(parse_err("GET / HTTP/1.1\r\n: a\r\n\r\n"),
+               ParseError::MalformedHeader { line: ": a".to_owned() });
+    assert_eq!(parse_err("POST / HTTP/1.1\r\nContent-Length: 1e3\r\n\r\n"),
+               ParseError::InvalidContentLength);
+  }
+
+  #[test]
+  fn parses_each_part_on_its_own() {
+    assert_eq!(HttpMethod::try_from("options"), Ok(HttpMethod::Option));
+    assert_eq!(HttpVersion::try_from("HTTP/1.0"), Ok(HttpVersion::Http_1_0));
+    assert_eq!(HttpVersion::try_from("http/1.0"),
+               Err(ParseError::InvalidVersion("http/1.0".to_owned())));
+    assert!(matches!(RequestURI::try_from("*"), Ok(RequestURI::Asterisk)));
+    assert!(matches!(RequestURI::try_from("http://a/b"), Ok(RequestURI::AbsoluteUri(_))));
+    assert!(matches!(HTTPRequestHeader::try_from("X-Id:  7 "),
+                     Ok(HTTPRequestHeader::_OtherHeader("X-Id", "7"))));
+  }
+
+  #[test]
+  fn maps_errors_to_status_codes() {
+    for err in [
+      ParseError::InvalidRequestLine,
+      ParseError::InvalidMethod("FETCH".to_owned()),
+      ParseError::InvalidUri("index.html".to_owned()),
+      ParseError::InvalidVersion("HTTP/3".to_owned()),
+      ParseError::MalformedHeader { line: "Host".to_owned() },
+      ParseError::InvalidContentLength,
+      ParseError::InvalidChunk,
+      ParseError::UnsupportedTransferEncoding,
+      ParseError::InvalidEncoding,
+    ] {
+      assert_eq!(err.status_code(), StatusCode::BadRequest, "{}", err);
+    }
+    assert_eq!(ParseError::RequestLineTooLong.status_code(), StatusCode::URITooLong);
+    for err in [ParseError::HeaderTooLarge, ParseError::TooManyHeaders, ParseError::HeadTooLarge] {
+      assert_eq!(err.status_code(), StatusCode::RequestHeaderFieldsTooLarge, "{}", err);
+    }
+    assert_eq!(ParseError::BodyTooLarge.status_code(), StatusCode::PayloadTooLarge);
+  }
+}
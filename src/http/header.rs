@@ -0,0 +1,227 @@
+//! Header fields of requests and responds.
+//!
+//! `HeaderMap` keeps fields in the order they were received or added, and
+//! looks them up by name case-insensitively. A field name may repeat, e.g.
+//! `Set-Cookie`: `get` returns the first occurrence, `get_all` every one of
+//! them, `append` adds another one and `insert` replaces all of them.
+//!
+//! Example:
+//! ```no run
+//! let user_agent = request.header.user_agent();
+//! let token = request.header.get("x-token").map(HTTPRequestHeader::value);
+//!
+//! respond.header.append(HttpRespondHeader::_OtherHeader("Set-Cookie".into(), "a=1".into()));
+//! respond.header.append(HttpRespondHeader::_OtherHeader("Set-Cookie".into(), "b=2".into()));
+//! respond.header.insert(HttpRespondHeader::CacheControl("no-store".into()));
+//! ```
+
+use std::fmt::{self, Debug, Formatter};
+use std::iter::FromIterator;
+use std::mem;
+use std::slice;
+use std::vec;
+
+/// Header field, named case-insensitively.
+pub trait Header {
+  /// Field name as written on the wire.
+  fn name(&self) -> &str;
+}
+
+/// Ordered multimap of header fields of type `H`.
+pub struct HeaderMap<H> {
+  fields: Vec<H>,
+}
+
+impl<H> Default for HeaderMap<H> {
+  fn default() -> Self {
+    HeaderMap { fields: Vec::new() }
+  }
+}
+
+impl<H: Debug> Debug for HeaderMap<H> {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    f.debug_list().entries(&self.fields).finish()
+  }
+}
+
+impl<H: Header> HeaderMap<H> {
+  pub fn new() -> Self {
+    HeaderMap::default()
+  }
+
+  /// Number of fields, counting every occurrence of a repeated name.
+  pub fn len(&self) -> usize {
+    self.fields.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.fields.is_empty()
+  }
+
+  pub fn contains(&self, name: &str) -> bool {
+    self.get(name).is_some()
+  }
+
+  /// First field named `name`, if any.
+  pub fn get(&self, name: &str) -> Option<&H> {
+    self.fields.iter().find(|field| field.name().eq_ignore_ascii_case(name))
+  }
+
+  /// First field named `name`, if any.
+  pub fn get_mut(&mut self, name: &str) -> Option<&mut H> {
+    self.fields.iter_mut().find(|field| field.name().eq_ignore_ascii_case(name))
+  }
+
+  /// Every field named `name`, in order.
+  pub fn get_all<'m>(&'m self, name: &'m str) -> impl Iterator<Item=&'m H> + 'm {
+    self.fields.iter().filter(move |field| field.name().eq_ignore_ascii_case(name))
+  }
+
+  /// Add `field` after the others, even if its name is already present.
+  pub fn append(&mut self, field: H) {
+    self.fields.push(field);
+  }
+
+  /// Replace every field of the same name as `field` with it, in place of
+  /// the first one. Returns that first one, if any.
+  pub fn insert(&mut self, field: H) -> Option<H> {
+    let index = self.fields.iter()
+        .position(|present| present.name().eq_ignore_ascii_case(field.name()));
+    match index {
+      Some(index) => {
+        let replaced = mem::replace(&mut self.fields[index], field);
+        let name = replaced.name();
+        let mut first = true;
+        self.fields.retain(|present| {
+          !present.name().eq_ignore_ascii_case(name) || mem::take(&mut first)
+        });
+        Some(replaced)
+      }
+      None => {
+        self.fields.push(field);
+        None
+      }
+    }
+  }
+
+  /// Remove every field named `name`. Returns the first one, if any.
+  pub fn remove(&mut self, name: &str) -> Option<H> {
+    let index = self.fields.iter().position(|field| field.name().eq_ignore_ascii_case(name))?;
+    let removed = self.fields.remove(index);
+    self.fields.retain(|field| !field.name().eq_ignore_ascii_case(name));
+    Some(removed)
+  }
+
+  /// Keep only the fields `keep` returns `true` for, in order.
+  pub fn retain<F: FnMut(&H) -> bool>(&mut self, keep: F) {
+    self.fields.retain(keep);
+  }
+
+  pub fn iter(&self) -> slice::Iter<'_, H> {
+    self.fields.iter()
+  }
+
+  pub fn iter_mut(&mut self) -> slice::IterMut<'_, H> {
+    self.fields.iter_mut()
+  }
+}
+
+impl<H> IntoIterator for HeaderMap<H> {
+  type Item = H;
+  type IntoIter = vec::IntoIter<H>;
+
+  fn into_iter(self) -> Self::IntoIter {
+    self.fields.into_iter()
+  }
+}
+
+impl<'m, H> IntoIterator for &'m HeaderMap<H> {
+  type Item = &'m H;
+  type IntoIter = slice::Iter<'m, H>;
+
+  fn into_iter(self) -> Self::IntoIter {
+    self.fields.iter()
+  }
+}
+
+impl<'m, H> IntoIterator for &'m mut HeaderMap<H> {
+  type Item = &'m mut H;
+  type IntoIter = slice::IterMut<'m, H>;
+
+  fn into_iter(self) -> Self::IntoIter {
+    self.fields.iter_mut()
+  }
+}
+
+impl<H> FromIterator<H> for HeaderMap<H> {
+  fn from_iter<I: IntoIterator<Item=H>>(iter: I) -> Self {
+    HeaderMap { fields: iter.into_iter().collect() }
+  }
+}
+
+impl<H> Extend<H> for HeaderMap<H> {
+  fn extend<I: IntoIterator<Item=H>>(&mut self, iter: I) {
+    self.fields.extend(iter);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[derive(Debug, PartialEq)]
+  struct Field(&'static str, &'static str);
+
+  impl Header for Field {
+    fn name(&self) -> &str {
+      self.0
+    }
+  }
+
+  fn fields(map: &HeaderMap<Field>) -> Vec<(&str, &str)> {
+    map.iter().map(|field| (field.0, field.1)).collect()
+  }
+
+  #[test]
+  fn looks_fields_up_case_insensitively() {
+    let mut map: HeaderMap<Field> = vec![Field("Content-Type", "text/html")].into_iter().collect();
+    assert_eq!(map.get("content-type"), Some(&Field("Content-Type", "text/html")));
+    assert!(map.contains("CONTENT-TYPE"));
+    assert!(!map.contains("Content-Length"));
+    map.get_mut("CoNtEnT-tYpE").unwrap().1 = "text/plain";
+    assert_eq!(fields(&map), [("Content-Type", "text/plain")]);
+  }
+
+  #[test]
+  fn appends_repeated_fields_in_order() {
+    let mut map = HeaderMap::new();
+    map.append(Field("Set-Cookie", "a=1"));
+    map.append(Field("Vary", "Accept"));
+    map.append(Field("set-cookie", "b=2"));
+    assert_eq!(map.len(), 3);
+    assert_eq!(map.get("SET-COOKIE"), Some(&Field("Set-Cookie", "a=1")));
+    assert_eq!(map.get_all("set-cookie").map(|field| field.1).collect::<Vec<&str>>(),
+               ["a=1", "b=2"]);
+  }
+
+  #[test]
+  fn inserts_in_place_of_every_field_of_the_same_name() {
+    let mut map: HeaderMap<Field> = vec![Field("Vary", "Accept"), Field("Set-Cookie", "a=1"),
+      Field("Date", "now"), Field("set-cookie", "b=2")].into_iter().collect();
+    assert_eq!(map.insert(Field("SET-COOKIE", "c=3")), Some(Field("Set-Cookie", "a=1")));
+    assert_eq!(fields(&map), [("Vary", "Accept"), ("SET-COOKIE", "c=3"), ("Date", "now")]);
+    assert_eq!(map.insert(Field("Server", "hello")), None);
+    assert_eq!(fields(&map).last(), Some(&("Server", "hello")));
+  }
+
+  #[test]
+  fn removes_every_field_of_the_name() {
+    let mut map: HeaderMap<Field> = vec![Field("Set-Cookie", "a=1"), Field("Vary", "Accept"),
+      Field("SET-COOKIE", "b=2")].into_iter().collect();
+    assert_eq!(map.remove("set-cookie"), Some(Field("Set-Cookie", "a=1")));
+    assert_eq!(fields(&map), [("Vary", "Accept")]);
+    assert_eq!(map.remove("Set-Cookie"), None);
+    assert_eq!(map.remove("vary"), Some(Field("Vary", "Accept")));
+    assert!(map.is_empty());
+  }
+}
@@ -0,0 +1,60 @@
+//! Media types of files, inferred from their extension.
+
+use std::path::Path;
+
+/// Media type of files of unknown extension.
+pub const DEFAULT_MIME_TYPE: &str = "application/octet-stream";
+
+/// Media type of the file at `path`, from its extension.
+pub fn from_path<P: AsRef<Path>>(path: P) -> &'static str {
+  path.as_ref().extension()
+      .and_then(|extension| extension.to_str())
+      .map_or(DEFAULT_MIME_TYPE, from_extension)
+}
+
+/// Media type of files ending in `.extension`, matched case-insensitively.
+pub fn from_extension(extension: &str) -> &'static str {
+  match extension.to_ascii_lowercase().as_str() {
+    // Text
+    "html" | "htm" => "text/html; charset=utf-8",
+    "css" => "text/css; charset=utf-8",
+    "js" | "mjs" => "text/javascript; charset=utf-8",
+    "txt" => "text/plain; charset=utf-8",
+    "csv" => "text/csv; charset=utf-8",
+    "md" => "text/markdown; charset=utf-8",
+    "xml" => "text/xml; charset=utf-8",
+
+    // Data
+    "json" | "map" => "application/json",
+    "webmanifest" => "application/manifest+json",
+    "wasm" => "application/wasm",
+    "pdf" => "application/pdf",
+    "zip" => "application/zip",
+    "gz" => "application/gzip",
+
+    // Images
+    "png" => "image/png",
+    "jpg" | "jpeg" => "image/jpeg",
+    "gif" => "image/gif",
+    "svg" => "image/svg+xml",
+    "ico" => "image/x-icon",
+    "webp" => "image/webp",
+    "avif" => "image/avif",
+    "bmp" => "image/bmp",
+
+    // Fonts
+    "woff" => "font/woff",
+    "woff2" => "font/woff2",
+    "ttf" => "font/ttf",
+    "otf" => "font/otf",
+
+    // Audio and video
+    "mp3" => "audio/mpeg",
+    "ogg" => "audio/ogg",
+    "wav" => "audio/wav",
+    "mp4" => "video/mp4",
+    "webm" => "video/webm",
+
+    _ => DEFAULT_MIME_TYPE
+  }
+}
@@ -1,3 +1,13 @@
+pub mod chunked;
+pub mod compression;
+pub mod conditional;
+pub mod date;
+pub mod error;
+pub mod header;
+pub mod mime;
+pub mod negotiation;
+pub mod parser;
+pub mod range;
 pub mod request;
 pub mod respond;
 pub mod version;
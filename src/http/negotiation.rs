@@ -0,0 +1,564 @@
+//! Proactive content negotiation, see RFC 7231 section 5.3.
+//!
+//! Handlers offer the media types or languages they can answer with, in
+//! order of preference, and serve the one the client ranks highest:
+//! ```no run
+//! let offers = ["text/html", "application/json"];
+//! match request.negotiate(&offers) {
+//!   Some(media_type) if media_type.essence() == "application/json" => { ... }
+//!   Some(_) => { ... }
+//!   None => not_acceptable(request, &offers)
+//! }
+//! ```
+
+use std::borrow::Cow;
+use std::fmt::{self, Display, Formatter};
+
+use crate::http::error::ParseError;
+use crate::http::request::HTTPRequest;
+use crate::http::respond::{HTTPRespond, HttpRespondHeader, StatusCode};
+
+/// Media type offered by a handler, e.g. `text/html; charset=utf-8`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MediaType<'a> {
+  pub mime_type: &'a str,
+  pub mime_subtype: &'a str,
+
+  // Parameters as written, e.g. `charset=utf-8`, empty if none
+  pub params: &'a str,
+}
+
+impl<'a> MediaType<'a> {
+  /// Parse `type/subtype` followed by any parameters, `None` if malformed
+  /// or a wildcard.
+  pub fn parse(s: &'a str) -> Option<Self> {
+    let range = MediaRange::parse(s)?;
+    MediaType::of(s, &range)
+  }
+
+  fn of(s: &'a str, range: &MediaRange<'a>) -> Option<Self> {
+    if range.mime_type == "*" || range.mime_subtype == "*" || range.q_factor_weighting.is_some() {
+      return None;
+    }
+    Some(MediaType {
+      mime_type: range.mime_type,
+      mime_subtype: range.mime_subtype,
+      params: s.split_once(';').map_or("", |(_, params)| params.trim()),
+    })
+  }
+
+  /// `type/subtype`, without parameters.
+  pub fn essence(&self) -> String {
+    format!("{}/{}", self.mime_type, self.mime_subtype)
+  }
+}
+
+impl Display for MediaType<'_> {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    write!(f, "{}/{}", self.mime_type, self.mime_subtype)?;
+    if !self.params.is_empty() {
+      write!(f, "; {}", self.params)?;
+    }
+    Ok(())
+  }
+}
+
+/// Media range of an `Accept` header field, e.g.
+/// `text/html;level=1;q=0.5`, see RFC 7231 section 5.3.2.
+///
+/// Parameters before the `q` weight belong to the media range, those after
+/// it are accept-extensions. Names are matched case-insensitively, and
+/// quoted values are unescaped.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MediaRange<'a> {
+  mime_type: &'a str,
+  mime_subtype: &'a str,
+  params: Vec<(&'a str, Cow<'a, str>)>,
+  q_factor_weighting: Option<f32>,
+  extensions: Vec<(&'a str, Option<Cow<'a, str>>)>,
+}
+
+impl<'a> MediaRange<'a> {
+  /// Parse a single media range, `None` if malformed.
+  pub fn parse(s: &'a str) -> Option<Self> {
+    let mut cursor = Cursor::new(s);
+    cursor.skip_ows();
+    let range = cursor.media_range().ok()?;
+    cursor.skip_ows();
+    if cursor.is_end() { Some(range) } else { None }
+  }
+
+  /// Parse the value of an `Accept` header field, skipping empty elements
+  /// and dropping malformed ones, e.g. of an invalid q-value.
+  pub fn parse_list(s: &'a str) -> Vec<Self> {
+    let mut cursor = Cursor::new(s);
+    let mut ranges = Vec::new();
+    loop {
+      cursor.skip_ows();
+      if cursor.eat(b',') {
+        continue;
+      }
+      if cursor.is_end() {
+        return ranges;
+      }
+      if let Ok(range) = cursor.media_range() {
+        cursor.skip_ows();
+        if cursor.is_end() || cursor.peek() == Some(b',') {
+          ranges.push(range);
+          continue;
+        }
+      }
+      cursor.skip_element();
+    }
+  }
+
+  /// Type of the media range, `*` if any.
+  pub fn mime_type(&self) -> &'a str {
+    self.mime_type
+  }
+
+  /// Subtype of the media range, `*` if any.
+  pub fn mime_subtype(&self) -> &'a str {
+    self.mime_subtype
+  }
+
+  /// Parameters of the media range, e.g. `level=1`, in order.
+  pub fn params(&self) -> &[(&'a str, Cow<'a, str>)] {
+    &self.params
+  }
+
+  /// Value of the parameter `name` of the media range, if any.
+  pub fn param(&self, name: &str) -> Option<&str> {
+    self.params.iter()
+        .find(|(param, _)| param.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.as_ref())
+  }
+
+  /// The `q` parameter, if given.
+  pub fn q_factor_weighting(&self) -> Option<f32> {
+    self.q_factor_weighting
+  }
+
+  /// Relative weight of the media range, `1` unless given.
+  pub fn q(&self) -> f32 {
+    self.q_factor_weighting.unwrap_or(1.0)
+  }
+
+  /// Accept-extensions following the `q` parameter, in order.
+  pub fn extensions(&self) -> &[(&'a str, Option<Cow<'a, str>>)] {
+    &self.extensions
+  }
+
+  /// How specifically the media range matches `offer`, `None` if it does
+  /// not: `*/*`, then `type/*`, then `type/subtype`, then `type/subtype`
+  /// with parameters, all of which the offer must have.
+  fn specificity(&self, offer: &MediaRange) -> Option<u8> {
+    let type_matches = self.mime_type.eq_ignore_ascii_case(offer.mime_type);
+    let subtype_matches = self.mime_subtype.eq_ignore_ascii_case(offer.mime_subtype);
+    match (self.mime_type, self.mime_subtype) {
+      ("*", "*") => Some(0),
+      (_, "*") if type_matches => Some(1),
+      _ if type_matches && subtype_matches && self.params.is_empty() => Some(2),
+      _ if type_matches && subtype_matches => {
+        let offered = self.params.iter().all(|(name, value)| {
+          offer.param(name).is_some_and(|offered| offered.eq_ignore_ascii_case(value))
+        });
+        if offered { Some(3) } else { None }
+      }
+      _ => None
+    }
+  }
+}
+
+impl Display for MediaRange<'_> {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    write!(f, "{}/{}", self.mime_type, self.mime_subtype)?;
+    for (name, value) in &self.params {
+      write!(f, ";{}=", name)?;
+      write_value(f, value)?;
+    }
+    if let Some(q) = self.q_factor_weighting {
+      write!(f, ";q={}", q)?;
+    }
+    for (name, value) in &self.extensions {
+      write!(f, ";{}", name)?;
+      if let Some(value) = value {
+        f.write_str("=")?;
+        write_value(f, value)?;
+      }
+    }
+    Ok(())
+  }
+}
+
+/// Write `value` as a token if it is one, else as a quoted-string.
+fn write_value(f: &mut Formatter<'_>, value: &str) -> fmt::Result {
+  if !value.is_empty() && value.bytes().all(is_tchar) {
+    return f.write_str(value);
+  }
+  f.write_str("\"")?;
+  for char in value.chars() {
+    if matches!(char, '"' | '\\') {
+      f.write_str("\\")?;
+    }
+    write!(f, "{}", char)?;
+  }
+  f.write_str("\"")
+}
+
+/// Offered media type `request` prefers per `Accept`, `None` if it accepts
+/// none of `offers`.
+///
+/// Each offer is ranked by the q-value of the most specific media range
+/// matching it, `type/subtype` with parameters over `type/subtype` over
+/// `type/*` over `*/*`, and is excluded by a q-value of `0`. Ties go to the
+/// earliest offer, as does every offer if the request has no `Accept`.
+pub fn negotiate<'o>(request: &HTTPRequest, offers: &[&'o str]) -> Option<MediaType<'o>> {
+  let mut offers = offers.iter().filter_map(|offer| {
+    let range = MediaRange::parse(offer)?;
+    Some((MediaType::of(offer, &range)?, range))
+  });
+  let ranges = match request.accept() {
+    Some(ranges) if !ranges.is_empty() => ranges,
+    _ => return offers.next().map(|(offer, _)| offer)
+  };
+
+  best_offer(offers.map(|(offer, offered)| {
+    let q = ranges.iter()
+        .filter_map(|range| range.specificity(&offered).map(|specificity| (specificity, range.q())))
+        .max_by_key(|(specificity, _)| *specificity)
+        .map(|(_, q)| q);
+    (offer, q)
+  }))
+}
+
+/// Offered language tag `request` prefers per `Accept-Language`, `None` if
+/// it accepts none of `offers`.
+///
+/// Language ranges match the tags they are a prefix of, e.g. `en` matches
+/// `en-US`, and the longest matching range ranks each offer. Ties go to the
+/// earliest offer, as does every offer if the request has no
+/// `Accept-Language`, or none but malformed ranges.
+pub fn negotiate_language<'o>(request: &HTTPRequest, offers: &[&'o str]) -> Option<&'o str> {
+  let ranges = request.header.accept_language()
+      .map(|ranges| ranges.into_iter().filter_map(split_q).collect::<Vec<(&str, f32)>>())
+      .unwrap_or_default();
+  if ranges.is_empty() {
+    return offers.first().copied();
+  }
+
+  best_offer(offers.iter().map(|offer| {
+    let q = ranges.iter()
+        .filter_map(|(range, q)| {
+          language_specificity(range, offer).map(|specificity| (specificity, *q))
+        })
+        .max_by_key(|(specificity, _)| *specificity)
+        .map(|(_, q)| q);
+    (*offer, q)
+  }))
+}
+
+/// `406 Not Acceptable` respond to `request`, listing the media types or
+/// languages that were on offer.
+pub fn not_acceptable(request: &HTTPRequest, offers: &[&str]) -> HTTPRespond<'static> {
+  let mut body = String::from(StatusCode::NotAcceptable.reason_phrase());
+  body.push_str("\n\nAvailable:\n");
+  for offer in offers {
+    body.push_str(offer);
+    body.push('\n');
+  }
+  let mut respond = HTTPRespond::from_body(
+    body.into_bytes(), request.http_version, StatusCode::NotAcceptable, None);
+  HTTPRespond::with_header(&mut respond,
+                           HttpRespondHeader::ContentType("text/plain; charset=utf-8".into()));
+  respond
+}
+
+/// First offer of highest q-value above `0`.
+fn best_offer<T>(ranked: impl Iterator<Item=(T, Option<f32>)>) -> Option<T> {
+  let mut best: Option<(T, f32)> = None;
+  for (offer, q) in ranked {
+    match q {
+      Some(q) if q > 0.0 && best.as_ref().is_none_or(|(_, best)| q > *best) =>
+        best = Some((offer, q)),
+      _ => {}
+    }
+  }
+  best.map(|(offer, _)| offer)
+}
+
+/// How specifically the language `range` matches the tag `offer`, i.e. the
+/// length of the range, `None` if it does not.
+fn language_specificity(range: &str, offer: &str) -> Option<usize> {
+  if range == "*" {
+    return Some(0);
+  }
+  let prefix = offer.get(..range.len())?;
+  let at_boundary = offer.len() == range.len() || offer[range.len()..].starts_with('-');
+  if prefix.eq_ignore_ascii_case(range) && at_boundary { Some(range.len()) } else { None }
+}
+
+/// Split an item of an `Accept-*` list into its value and q-value, `None`
+/// if empty or its q-value is invalid, so that the item alone is dropped.
+pub(crate) fn split_q(item: &str) -> Option<(&str, f32)> {
+  let mut params = item.split(';');
+  let value = params.next()?.trim();
+  let mut q = 1.0;
+  for param in params {
+    if let Some((name, weight)) = param.split_once('=') {
+      if name.trim().eq_ignore_ascii_case("q") {
+        q = parse_qvalue(weight.trim())?;
+      }
+    }
+  }
+  if value.is_empty() { None } else { Some((value, q)) }
+}
+
+/// Parse a weight of `0` to `1` with up to three decimals, `None` if
+/// malformed.
+fn parse_qvalue(s: &str) -> Option<f32> {
+  let (int, frac) = s.split_once('.').unwrap_or((s, ""));
+  let valid = frac.len() <= 3 && frac.bytes().all(|byte| byte.is_ascii_digit()) && match int {
+    "0" => true,
+    "1" => frac.bytes().all(|byte| byte == b'0'),
+    _ => false
+  };
+  if valid { s.parse().ok() } else { None }
+}
+
+/// Whether `byte` may appear in a token, see RFC 7230 section 3.2.6.
+#[inline]
+fn is_tchar(byte: u8) -> bool {
+  byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte)
+}
+
+/// Position within a header field value being parsed.
+struct Cursor<'a> {
+  s: &'a str,
+  pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+  fn new(s: &'a str) -> Self {
+    Cursor { s, pos: 0 }
+  }
+
+  fn malformed(&self) -> ParseError {
+    ParseError::MalformedHeader { line: self.s.to_owned() }
+  }
+
+  #[inline]
+  fn peek(&self) -> Option<u8> {
+    self.s.as_bytes().get(self.pos).copied()
+  }
+
+  #[inline]
+  fn is_end(&self) -> bool {
+    self.pos == self.s.len()
+  }
+
+  fn eat(&mut self, byte: u8) -> bool {
+    let eaten = self.peek() == Some(byte);
+    if eaten {
+      self.pos += 1;
+    }
+    eaten
+  }
+
+  /// Skip to the comma ending the current list element, if any, stepping
+  /// over quoted-strings.
+  fn skip_element(&mut self) {
+    while let Some(byte) = self.peek() {
+      match byte {
+        b',' => return,
+        b'"' => {
+          let _ = self.value();
+        }
+        _ => self.pos += self.s[self.pos..].chars().next().map_or(1, char::len_utf8)
+      }
+    }
+  }
+
+  fn skip_ows(&mut self) {
+    while matches!(self.peek(), Some(b' ') | Some(b'\t')) {
+      self.pos += 1;
+    }
+  }
+
+  fn token(&mut self) -> Result<&'a str, ParseError> {
+    let start = self.pos;
+    while self.peek().is_some_and(is_tchar) {
+      self.pos += 1;
+    }
+    if self.pos == start { Err(self.malformed()) } else { Ok(&self.s[start..self.pos]) }
+  }
+
+  /// A token, or a quoted-string without its quotes and escapes.
+  fn value(&mut self) -> Result<Cow<'a, str>, ParseError> {
+    if !self.eat(b'"') {
+      return self.token().map(Cow::Borrowed);
+    }
+    let start = self.pos;
+    let mut unescaped: Option<String> = None;
+    loop {
+      match self.peek() {
+        Some(b'"') => {
+          let value = match unescaped {
+            Some(unescaped) => Cow::Owned(unescaped),
+            None => Cow::Borrowed(&self.s[start..self.pos])
+          };
+          self.pos += 1;
+          return Ok(value);
+        }
+        Some(b'\\') => {
+          let unescaped = unescaped.get_or_insert_with(|| self.s[start..self.pos].to_owned());
+          self.pos += 1;
+          let escaped = self.s[self.pos..].chars().next().ok_or_else(|| self.malformed())?;
+          unescaped.push(escaped);
+          self.pos += escaped.len_utf8();
+        }
+        Some(_) => {
+          let char = self.s[self.pos..].chars().next().unwrap_or_default();
+          if char.is_control() && char != '\t' {
+            return Err(self.malformed());
+          }
+          if let Some(unescaped) = &mut unescaped {
+            unescaped.push(char);
+          }
+          self.pos += char.len_utf8();
+        }
+        None => return Err(self.malformed())
+      }
+    }
+  }
+
+  /// A media range followed by its parameters, weight and extensions.
+  fn media_range(&mut self) -> Result<MediaRange<'a>, ParseError> {
+    let mime_type = self.token()?;
+    if !self.eat(b'/') {
+      return Err(self.malformed());
+    }
+    let mime_subtype = self.token()?;
+    if mime_type == "*" && mime_subtype != "*" {
+      return Err(self.malformed());
+    }
+
+    let mut range = MediaRange {
+      mime_type,
+      mime_subtype,
+      params: Vec::new(),
+      q_factor_weighting: None,
+      extensions: Vec::new(),
+    };
+    loop {
+      let start = self.pos;
+      self.skip_ows();
+      if !self.eat(b';') {
+        self.pos = start;
+        return Ok(range);
+      }
+      self.skip_ows();
+      let name = self.token()?;
+      if range.q_factor_weighting.is_some() {
+        let value = if self.eat(b'=') { Some(self.value()?) } else { None };
+        range.extensions.push((name, value));
+        continue;
+      }
+      if !self.eat(b'=') {
+        return Err(self.malformed());
+      }
+      let value = self.value()?;
+      if name.eq_ignore_ascii_case("q") {
+        range.q_factor_weighting = Some(parse_qvalue(&value).ok_or_else(|| self.malformed())?);
+      } else {
+        range.params.push((name, value));
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::convert::TryFrom;
+
+  use super::*;
+
+  fn request(head: &str) -> HTTPRequest<'_> {
+    HTTPRequest::try_from(head).unwrap()
+  }
+
+  #[test]
+  fn drops_malformed_media_ranges() {
+    let ranges = MediaRange::parse_list(
+      "text/html;q=2, bogus, */html, \"a,b\"/c, application/json");
+    assert_eq!(ranges.iter().map(ToString::to_string).collect::<Vec<String>>(),
+               ["application/json"]);
+    let ranges = MediaRange::parse_list("text/plain;x=\"a, b\" oops, image/png;q=0.5");
+    assert_eq!(ranges.len(), 1);
+    assert_eq!(ranges[0].q(), 0.5);
+  }
+
+  #[test]
+  fn accepts_requests_with_malformed_accept() {
+    let request = request("GET / HTTP/1.1\r\nAccept: text/html;q=high\r\n\r\n");
+    assert_eq!(request.accept().map(|ranges| ranges.len()), Some(0));
+    assert_eq!(request.negotiate(&["application/json"]).map(|offer| offer.essence()),
+               Some("application/json".to_owned()));
+  }
+
+  #[test]
+  fn ignores_accept_language_of_malformed_q_values() {
+    let malformed = request("GET / HTTP/1.1\r\nAccept-Language: fr;q=x, de;q=2\r\n\r\n");
+    assert_eq!(malformed.negotiate_language(&["en", "fr"]), Some("en"));
+    let excluded = request("GET / HTTP/1.1\r\nAccept-Language: fr;q=x, en;q=0\r\n\r\n");
+    assert_eq!(excluded.negotiate_language(&["en", "fr"]), None);
+  }
+
+  #[test]
+  fn parses_params_weight_and_extensions() {
+    let range = MediaRange::parse("text/html ; level=1;Q=0.5; ext; foo=\"bar\"").unwrap();
+    assert_eq!((range.mime_type(), range.mime_subtype()), ("text", "html"));
+    assert_eq!(range.params(), [("level", Cow::Borrowed("1"))]);
+    assert_eq!(range.q_factor_weighting(), Some(0.5));
+    assert_eq!(range.extensions(), [("ext", None), ("foo", Some(Cow::Borrowed("bar")))]);
+  }
+
+  #[test]
+  fn unescapes_quoted_param_values() {
+    let range = MediaRange::parse(r#"text/plain;title="a \"b\" \\c, d""#).unwrap();
+    assert_eq!(range.param("title"), Some(r#"a "b" \c, d"#));
+    assert_eq!(range.q(), 1.0);
+  }
+
+  #[test]
+  fn looks_params_up_case_insensitively() {
+    let range = MediaRange::parse("text/html;Charset=utf-8").unwrap();
+    assert_eq!(range.param("charset"), Some("utf-8"));
+    assert_eq!(range.param("CHARSET"), Some("utf-8"));
+    assert_eq!(range.param("level"), None);
+  }
+
+  #[test]
+  fn rejects_malformed_media_ranges() {
+    for s in ["", "text", "text/", "/html", "*/html", "text/html;", "text/html;level",
+      "text/html;q=1.5", "text/html;q=0.1234", "text/html;x=\"open", "text/html extra"] {
+      assert_eq!(MediaRange::parse(s), None, "{:?}", s);
+    }
+  }
+
+  #[test]
+  fn displays_media_ranges_back_in_canonical_form() {
+    let range = MediaRange::parse("text/html ;level=1; q=0.5 ;ext;x=\"a b\"").unwrap();
+    assert_eq!(range.to_string(), "text/html;level=1;q=0.5;ext;x=\"a b\"");
+    assert_eq!(MediaRange::parse(&range.to_string()), Some(range));
+    let escaped = MediaRange::parse(r#"text/plain;x="\"""#).unwrap();
+    assert_eq!(escaped.to_string(), r#"text/plain;x="\"""#);
+  }
+
+  #[test]
+  fn skips_empty_elements_of_accept_lists() {
+    let ranges = MediaRange::parse_list(" ,text/*;q=0.2,, */* ,");
+    assert_eq!(ranges.iter().map(ToString::to_string).collect::<Vec<String>>(),
+               ["text/*;q=0.2", "*/*"]);
+  }
+}
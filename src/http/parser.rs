@@ -0,0 +1,475 @@
+//! Resumable HTTP Request parser
+//!
+//! The parser is fed the whole per-connection buffer every time new bytes
+//! arrive. It remembers how far it has already scanned, so a request split
+//! across any number of TCP segments is only scanned once. `RequestLimits`
+//! are checked on every feed, so an oversized request is rejected as soon as
+//! it grows past a limit rather than once complete.
+
+use std::borrow::Cow;
+use std::convert::TryFrom;
+use std::mem;
+use std::str;
+
+use crate::http::chunked::ChunkedDecoder;
+use crate::http::error::ParseError;
+use crate::http::request::{HTTPRequest, HTTPRequestHeader, parse_content_length};
+
+const CRLF: &[u8] = b"\r\n";
+
+/// Result of feeding bytes into `RequestParser`
+#[derive(Debug)]
+pub enum ParseStatus<'a> {
+  /// More bytes are needed to complete the request.
+  Incomplete,
+
+  /// A full request was parsed, occupying the first `usize` bytes of buffer.
+  Complete(HTTPRequest<'a>, usize),
+}
+
+/// Maximum sizes, in bytes, of the parts of a request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RequestLimits {
+  /// Request line, excluding its CRLF, answered with `414 URI Too Long`.
+  pub request_line: usize,
+
+  /// Header field line, excluding its CRLF, answered with
+  /// `431 Request Header Fields Too Large`.
+  pub header_line: usize,
+
+  /// Number of header fields, answered with
+  /// `431 Request Header Fields Too Large`.
+  pub headers: usize,
+
+  /// Whole head, answered with `431 Request Header Fields Too Large`.
+  pub head: usize,
+
+  /// Body, decoded if chunked, answered with `413 Payload Too Large`.
+  pub body: usize,
+}
+
+impl Default for RequestLimits {
+  fn default() -> Self {
+    RequestLimits {
+      request_line: 8 * 1024,
+      header_line: 8 * 1024,
+      headers: 100,
+      head: 64 * 1024,
+      body: 8 * 1024 * 1024,
+    }
+  }
+}
+
+impl RequestLimits {
+  /// Most bytes a single request may span, framing included.
+  #[inline]
+  pub fn request(&self) -> usize {
+    self.head.saturating_add(self.body)
+  }
+
+  /// Check line `index` of the head, `0` being the request line.
+  #[inline]
+  fn check_line(&self, index: usize, len: usize) -> Result<(), ParseError> {
+    if index == 0 {
+      if len > self.request_line {
+        return Err(ParseError::RequestLineTooLong);
+      }
+    } else if index > self.headers {
+      return Err(ParseError::TooManyHeaders);
+    } else if len > self.header_line {
+      return Err(ParseError::HeaderTooLarge);
+    }
+    Ok(())
+  }
+}
+
+/// Enum of states of a resumable parse
+#[derive(Debug)]
+enum ParserState {
+  // Looking for the blank line, with the `lines` lines before `line_start`
+  // complete, and bytes before `scanned` known not to hold a CRLF after it.
+  Head { scanned: usize, line_start: usize, lines: usize },
+
+  // Head fully received, waiting for `Content-Length` bytes of body.
+  Body { head_len: usize, body_len: usize },
+
+  // Head fully received, decoding a chunked body.
+  Chunked { head_len: usize, decoder: ChunkedDecoder },
+}
+
+const HEAD_START: ParserState = ParserState::Head { scanned: 0, line_start: 0, lines: 0 };
+
+/// How the length of a request body is determined
+enum BodyFraming {
+  Length(usize),
+  Chunked,
+}
+
+/// Incremental parser of one HTTP Request at a time
+#[derive(Debug)]
+pub struct RequestParser {
+  state: ParserState,
+  limits: RequestLimits,
+}
+
+impl Default for RequestParser {
+  fn default() -> Self {
+    RequestParser::new()
+  }
+}
+
+impl RequestParser {
+  pub fn new() -> Self {
+    RequestParser::with_limits(RequestLimits::default())
+  }
+
+  pub fn with_limits(limits: RequestLimits) -> Self {
+    RequestParser { state: HEAD_START, limits }
+  }
+
+  pub fn limits(&self) -> &RequestLimits {
+    &self.limits
+  }
+
+  /// Whether the head of the current request has been received.
+  pub fn is_reading_body(&self) -> bool {
+    !matches!(self.state, ParserState::Head { .. })
+  }
+
+  /// Parse `buf`, which holds every byte received since the last complete
+  /// request. Once `Complete` is returned the parser is reset, and the
+  /// caller is expected to drop the consumed bytes from the buffer.
+  pub fn parse<'a>(&mut self, buf: &'a [u8])
+                   -> Result<ParseStatus<'a>, ParseError> {
+    if let ParserState::Head { .. } = self.state {
+      let head_len = match self.scan_head(buf)? {
+        Some(head_len) => head_len,
+        None => return self.incomplete(buf)
+      };
+      self.state = match body_framing(head_str(&buf[..head_len])?, &self.limits)? {
+        BodyFraming::Length(body_len) =>
+          ParserState::Body { head_len, body_len },
+        BodyFraming::Chunked =>
+          ParserState::Chunked {
+            head_len,
+            decoder: ChunkedDecoder::new(self.limits.body),
+          },
+      };
+    }
+
+    match &mut self.state {
+      ParserState::Body { head_len, body_len } => {
+        let (head_len, total_len) = (*head_len, *head_len + *body_len);
+        if buf.len() < total_len {
+          return Ok(ParseStatus::Incomplete);
+        }
+        self.state = HEAD_START;
+
+        let mut request = HTTPRequest::try_from(head_str(&buf[..head_len])?)?;
+        request.body = Cow::Borrowed(&buf[head_len..total_len]);
+        Ok(ParseStatus::Complete(request, total_len))
+      }
+
+      ParserState::Chunked { head_len, decoder } => {
+        let head_len = *head_len;
+        let body_len = match decoder.decode(&buf[head_len..])? {
+          Some(body_len) => body_len,
+          None => return self.incomplete(buf)
+        };
+        let (trailer_start, trailer_end) = decoder.trailer();
+        let body = mem::take(&mut decoder.body);
+        self.state = HEAD_START;
+
+        let mut request = HTTPRequest::try_from(head_str(&buf[..head_len])?)?;
+        request.body = Cow::Owned(body);
+        // Trailer fields are merged into the header fields, but for those
+        // that must not be sent in a trailer
+        let trailer = head_str(&buf[head_len + trailer_start..head_len + trailer_end])?;
+        for line in trailer.split("\r\n").filter(|line| !line.is_empty()) {
+          let field = HTTPRequestHeader::try_from(line)?;
+          if allowed_in_trailer(&field) {
+            request.header.append(field);
+          }
+        }
+        Ok(ParseStatus::Complete(request, head_len + body_len))
+      }
+
+      ParserState::Head { .. } =>
+        unreachable!("Parser should have left head state!")
+    }
+  }
+
+  /// Scan the head lines received since the last call, checking each one
+  /// against the limits. Returns the length of the head once its blank line
+  /// arrived.
+  fn scan_head(&mut self, buf: &[u8]) -> Result<Option<usize>, ParseError> {
+    let (scanned, line_start, lines) = match &mut self.state {
+      ParserState::Head { scanned, line_start, lines } => (scanned, line_start, lines),
+      _ => return Ok(None)
+    };
+
+    // Resume a byte before `scanned` in case a CRLF was split
+    let mut pos = scanned.saturating_sub(CRLF.len() - 1).max(*line_start);
+    while let Some(index) = find(&buf[pos..], CRLF) {
+      let line_end = pos + index;
+      let head_len = line_end + CRLF.len();
+      if head_len > self.limits.head {
+        return Err(ParseError::HeadTooLarge);
+      }
+      if line_end == *line_start {
+        return Ok(Some(head_len));
+      }
+      self.limits.check_line(*lines, line_end - *line_start)?;
+      *lines += 1;
+      *line_start = head_len;
+      pos = head_len;
+    }
+    *scanned = buf.len();
+
+    // The line received so far may already be too long
+    let partial = &buf[*line_start..];
+    let partial = partial.strip_suffix(b"\r").unwrap_or(partial);
+    if !partial.is_empty() {
+      self.limits.check_line(*lines, partial.len())?;
+    }
+    if buf.len() > self.limits.head {
+      return Err(ParseError::HeadTooLarge);
+    }
+    Ok(None)
+  }
+
+  /// `Incomplete`, unless `buf` already spans more than a request may.
+  #[inline]
+  fn incomplete<'a>(&self, buf: &[u8]) -> Result<ParseStatus<'a>, ParseError> {
+    if buf.len() >= self.limits.request() {
+      return Err(ParseError::BodyTooLarge);
+    }
+    Ok(ParseStatus::Incomplete)
+  }
+}
+
+#[inline]
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+  haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+#[inline]
+fn head_str(head: &[u8]) -> Result<&str, ParseError> {
+  str::from_utf8(head).map_err(|_| ParseError::InvalidEncoding)
+}
+
+/// Fields of unknown names that must not be sent in a trailer
+const TRAILER_DENYLIST: &[&str] = &[
+  "Authorization", "Cache-Control", "Content-Encoding", "Content-Range", "Cookie", "Expect",
+  "Keep-Alive", "Max-Forwards", "Pragma", "Proxy-Authorization", "TE", "Trailer", "Upgrade",
+];
+
+/// Whether `field` may be merged from a trailer. Fields controlling message
+/// framing, routing, request modifiers, authentication and content handling
+/// are only trusted from the head (RFC 9110, section 6.5.1).
+fn allowed_in_trailer(field: &HTTPRequestHeader) -> bool {
+  match field {
+    HTTPRequestHeader::Referer(_) | HTTPRequestHeader::UserAgent(_) => true,
+    HTTPRequestHeader::_OtherHeader(name, _) =>
+      !TRAILER_DENYLIST.iter().any(|denied| denied.eq_ignore_ascii_case(name)),
+    _ => false
+  }
+}
+
+/// Find how the body length is determined from a request head, chunked
+/// Transfer-Encoding or `Content-Length`, no body being expected if neither
+/// is present.
+///
+/// A request of both, or of differing `Content-Length` values, is rejected
+/// rather than trusting one of them, as an intermediary may trust the other.
+fn body_framing(head: &str, limits: &RequestLimits) -> Result<BodyFraming, ParseError> {
+  let mut chunked = false;
+  let mut content_length: Option<usize> = None;
+  for line in head.split("\r\n").skip(1) {
+    if let Some(colon) = line.find(':') {
+      let name = &line[..colon];
+      let value = line[colon + 1..].trim();
+      if name.eq_ignore_ascii_case("Transfer-Encoding") {
+        let last_coding = value.rsplit(',').next().unwrap_or_default().trim();
+        if !last_coding.eq_ignore_ascii_case("chunked") {
+          return Err(ParseError::UnsupportedTransferEncoding);
+        }
+        chunked = true;
+      }
+      if name.eq_ignore_ascii_case("Content-Length") {
+        let body_len = parse_content_length(value)?;
+        if content_length.is_some_and(|content_length| content_length != body_len) {
+          return Err(ParseError::InvalidContentLength);
+        }
+        content_length = Some(body_len);
+      }
+    }
+  }
+  match content_length {
+    Some(_) if chunked => Err(ParseError::InvalidContentLength),
+    _ if chunked => Ok(BodyFraming::Chunked),
+    Some(body_len) if body_len > limits.body => Err(ParseError::BodyTooLarge),
+    body_len => Ok(BodyFraming::Length(body_len.unwrap_or(0)))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::http::request::HttpMethod;
+  use crate::http::respond::StatusCode;
+
+  /// Feed `request` one byte at a time, returning the parsed body and the
+  /// bytes consumed.
+  fn parse_bytewise(parser: &mut RequestParser, request: &[u8]) -> (Vec<u8>, usize) {
+    for end in 1..request.len() {
+      assert!(matches!(parser.parse(&request[..end]), Ok(ParseStatus::Incomplete)),
+              "Completed early at byte [{}]!", end);
+    }
+    match parser.parse(request) {
+      Ok(ParseStatus::Complete(request, size)) => (request.body.into_owned(), size),
+      status => panic!("Unexpected status [{:?}]!", status)
+    }
+  }
+
+  #[test]
+  fn resumes_across_split_crlf() {
+    let mut parser = RequestParser::new();
+    let request = b"GET /index.html HTTP/1.1\r\nHost: localhost\r\n\r\n";
+    assert_eq!(parse_bytewise(&mut parser, request), (Vec::new(), request.len()));
+  }
+
+  #[test]
+  fn waits_for_the_whole_body() {
+    let mut parser = RequestParser::new();
+    let request = b"POST /echo HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello";
+    assert_eq!(parse_bytewise(&mut parser, request), (b"hello".to_vec(), request.len()));
+  }
+
+  #[test]
+  fn parses_pipelined_requests_in_order() {
+    let mut parser = RequestParser::new();
+    let mut buf = b"GET /a HTTP/1.1\r\n\r\nPOST /b HTTP/1.1\r\nContent-Length: 2\r\n\r\nhi\
+      GET /c HTTP/1.1\r\n".to_vec();
+
+    let mut paths = Vec::new();
+    while let ParseStatus::Complete(request, size) = parser.parse(&buf).unwrap() {
+      paths.push((request.method, request.request_uri.path().map(str::to_owned),
+                  request.body.into_owned()));
+      buf.drain(..size);
+    }
+    assert_eq!(paths, [
+      (HttpMethod::Get, Some("/a".to_owned()), Vec::new()),
+      (HttpMethod::Post, Some("/b".to_owned()), b"hi".to_vec()),
+    ]);
+    assert_eq!(buf, b"GET /c HTTP/1.1\r\n");
+    buf.extend_from_slice(b"\r\n");
+    assert!(matches!(parser.parse(&buf), Ok(ParseStatus::Complete(_, 19))));
+  }
+
+  fn limited() -> RequestParser {
+    RequestParser::with_limits(RequestLimits {
+      request_line: 32,
+      header_line: 24,
+      headers: 2,
+      head: 96,
+      body: 8,
+    })
+  }
+
+  fn parse_err(parser: &mut RequestParser, buf: &[u8]) -> ParseError {
+    match parser.parse(buf) {
+      Err(err) => err,
+      status => panic!("Unexpected status [{:?}]!", status)
+    }
+  }
+
+  #[test]
+  fn rejects_a_long_request_line_before_its_crlf() {
+    let buf = format!("GET /{} HTTP/1.1", "a".repeat(32));
+    assert_eq!(parse_err(&mut limited(), buf.as_bytes()), ParseError::RequestLineTooLong);
+    assert_eq!(ParseError::RequestLineTooLong.status_code(), StatusCode::URITooLong);
+  }
+
+  #[test]
+  fn rejects_large_or_many_header_fields() {
+    let buf = format!("GET / HTTP/1.1\r\nX-Long: {}", "a".repeat(24));
+    assert_eq!(parse_err(&mut limited(), buf.as_bytes()), ParseError::HeaderTooLarge);
+
+    let buf = b"GET / HTTP/1.1\r\nA: 1\r\nB: 2\r\nC: 3\r\n\r\n";
+    assert_eq!(parse_err(&mut limited(), buf), ParseError::TooManyHeaders);
+    assert_eq!(ParseError::TooManyHeaders.status_code(),
+               StatusCode::RequestHeaderFieldsTooLarge);
+  }
+
+  #[test]
+  fn rejects_a_large_head_or_body() {
+    let mut parser = limited();
+    parser.limits.head = 40;
+    let buf = b"GET / HTTP/1.1\r\nA: 1234567890\r\nB: 1234567890\r\n";
+    assert_eq!(parse_err(&mut parser, buf), ParseError::HeadTooLarge);
+
+    let buf = b"POST / HTTP/1.1\r\nContent-Length: 9\r\n\r\n";
+    assert_eq!(parse_err(&mut limited(), buf), ParseError::BodyTooLarge);
+    assert_eq!(ParseError::BodyTooLarge.status_code(), StatusCode::PayloadTooLarge);
+  }
+
+  #[test]
+  fn rejects_differing_content_lengths() {
+    let buf = b"POST / HTTP/1.1\r\nContent-Length: 3\r\nContent-Length: 10\r\n\r\n";
+    assert_eq!(parse_err(&mut RequestParser::new(), buf), ParseError::InvalidContentLength);
+  }
+
+  #[test]
+  fn accepts_repeated_equal_content_lengths() {
+    let mut parser = RequestParser::new();
+    let request = b"POST / HTTP/1.1\r\nContent-Length: 3\r\ncontent-length: 3\r\n\r\nabc";
+    assert_eq!(parse_bytewise(&mut parser, request), (b"abc".to_vec(), request.len()));
+  }
+
+  #[test]
+  fn rejects_both_transfer_encoding_and_content_length() {
+    let buf = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\nContent-Length: 3\r\n\r\n";
+    assert_eq!(parse_err(&mut RequestParser::new(), buf), ParseError::InvalidContentLength);
+    let buf = b"POST / HTTP/1.1\r\nContent-Length: 3\r\nTransfer-Encoding: chunked\r\n\r\n";
+    assert_eq!(parse_err(&mut RequestParser::new(), buf), ParseError::InvalidContentLength);
+  }
+
+  #[test]
+  fn rejects_content_lengths_not_all_digits() {
+    for value in ["+3", "-1", " 3", "3 ", "", "0x3", "99999999999999999999999"] {
+      assert_eq!(parse_content_length(value), Err(ParseError::InvalidContentLength), "{:?}", value);
+    }
+    assert_eq!(parse_content_length("007"), Ok(7));
+
+    for value in ["+3", "-1", "99999999999999999999999"] {
+      let buf = format!("POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\nabc", value);
+      assert_eq!(parse_err(&mut RequestParser::new(), buf.as_bytes()),
+                 ParseError::InvalidContentLength, "{:?}", value);
+      let head = format!("POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\n", value);
+      assert_eq!(HTTPRequest::try_from(head.as_str()).err(),
+                 Some(ParseError::InvalidContentLength), "{:?}", value);
+    }
+  }
+
+  #[test]
+  fn merges_only_allowed_trailer_fields() {
+    let mut parser = RequestParser::new();
+    let buf = b"POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n\
+        2\r\nhi\r\n0\r\nX-Checksum: 1a2b\r\nHost: b\r\nContent-Length: 9\r\n\
+        Connection: close\r\ntransfer-encoding: gzip\r\ntrailer: X-Checksum\r\n\r\n";
+    let request = match parser.parse(buf) {
+      Ok(ParseStatus::Complete(request, size)) if size == buf.len() => request,
+      status => panic!("Unexpected status [{:?}]!", status)
+    };
+    assert_eq!(request.body, &b"hi"[..]);
+    let fields: Vec<(&str, String)> = request.header.iter()
+        .map(|field| (field.name(), field.value().into_owned()))
+        .collect();
+    assert_eq!(fields, [
+      ("Host", "a".to_string()),
+      ("Transfer-Encoding", "chunked".to_string()),
+      ("X-Checksum", "1a2b".to_string()),
+    ]);
+  }
+}
@@ -0,0 +1,264 @@
+//! Range requests, see RFC 7233.
+//!
+//! A `200 OK` respond to a `GET` request is cut down to the requested byte
+//! ranges before being written if it advertises `Accept-Ranges: bytes` and
+//! has a body of known length. One satisfiable range is answered with a
+//! single-part `206 Partial Content`, several with a `multipart/byteranges`
+//! one, and none with `416 Range Not Satisfiable`.
+
+use std::time::SystemTime;
+
+use crate::http::conditional::EntityTag;
+use crate::http::date::parse_http_date;
+use crate::http::request::{HTTPRequest, HttpMethod};
+use crate::http::respond::{HTTPRespond, StatusCode};
+
+/// Enum of byte-range-spec of a `Range` header field
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteRange {
+  // `first-last`, both inclusive.
+  FromTo(u64, u64),
+
+  // `first-`, up to the end.
+  From(u64),
+
+  // `-length`, the last `length` bytes.
+  Suffix(u64),
+}
+
+impl ByteRange {
+  /// Parse the value of a `Range` header field, `None` if its unit is not
+  /// `bytes` or it is malformed, in which case it must be ignored.
+  pub fn parse_ranges(value: &str) -> Option<Vec<ByteRange>> {
+    let (unit, specs) = value.trim().split_once('=')?;
+    if !unit.trim().eq_ignore_ascii_case("bytes") {
+      return None;
+    }
+    let ranges = specs.split(',')
+        .map(str::trim)
+        .filter(|spec| !spec.is_empty())
+        .map(ByteRange::parse)
+        .collect::<Option<Vec<ByteRange>>>()?;
+    if ranges.is_empty() { None } else { Some(ranges) }
+  }
+
+  /// Parse a single byte-range-spec, e.g. `0-499`, `500-` or `-500`.
+  pub fn parse(spec: &str) -> Option<ByteRange> {
+    let (first, last) = spec.split_once('-')?;
+    let parse = |value: &str| -> Option<u64> {
+      if value.is_empty() || !value.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+      }
+      value.parse().ok()
+    };
+    match (first, last) {
+      ("", last) => Some(ByteRange::Suffix(parse(last)?)),
+      (first, "") => Some(ByteRange::From(parse(first)?)),
+      (first, last) => {
+        let (first, last) = (parse(first)?, parse(last)?);
+        if first > last { None } else { Some(ByteRange::FromTo(first, last)) }
+      }
+    }
+  }
+
+  /// First and last byte, inclusive, of the range within `len` bytes, `None`
+  /// if unsatisfiable.
+  pub fn resolve(self, len: u64) -> Option<(u64, u64)> {
+    let (first, last) = match self {
+      ByteRange::FromTo(first, last) => (first, last.min(len.checked_sub(1)?)),
+      ByteRange::From(first) => (first, len.checked_sub(1)?),
+      ByteRange::Suffix(0) => return None,
+      ByteRange::Suffix(length) => (len.saturating_sub(length), len.checked_sub(1)?),
+    };
+    if first > last { None } else { Some((first, last)) }
+  }
+}
+
+/// Enum of validators of an `If-Range` header field
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IfRange {
+  ETag(EntityTag),
+  Date(SystemTime),
+}
+
+impl IfRange {
+  /// Parse the value of an `If-Range` header field, `None` if malformed.
+  pub fn parse(value: &str) -> Option<IfRange> {
+    let value = value.trim();
+    match EntityTag::parse(value) {
+      Some(etag) => Some(IfRange::ETag(etag)),
+      None => parse_http_date(value).map(IfRange::Date)
+    }
+  }
+
+  /// Whether the representation is still the one the ranges were computed
+  /// against. Only strong validators match.
+  pub fn matches(&self, etag: Option<&EntityTag>, last_modified: Option<SystemTime>) -> bool {
+    match self {
+      IfRange::ETag(expected) => etag.is_some_and(|etag| expected.strong_eq(etag)),
+      IfRange::Date(date) => last_modified == Some(*date),
+    }
+  }
+}
+
+/// `Range` and `If-Range` header fields of a `GET` request, kept beyond the
+/// request buffer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RangeRequest {
+  pub ranges: Vec<ByteRange>,
+  pub if_range: Option<IfRange>,
+}
+
+impl RangeRequest {
+  /// Ranges requested by `request`, `None` if not a `GET` request with a
+  /// valid `Range` header field.
+  pub fn of(request: &HTTPRequest) -> Option<Self> {
+    if request.method != HttpMethod::Get {
+      return None;
+    }
+    Some(RangeRequest {
+      ranges: request.range()?,
+      if_range: request.if_range(),
+    })
+  }
+
+  /// Cut `respond` down to the requested ranges, if it is a `200 OK`
+  /// accepting byte ranges and its `If-Range` validator still matches.
+  pub fn apply(&self, respond: HTTPRespond<'static>) -> HTTPRespond<'static> {
+    if respond.status_code != StatusCode::Ok {
+      return respond;
+    }
+    let accepts_ranges = respond.header.accept_ranges()
+        .is_some_and(|value| value.trim().eq_ignore_ascii_case("bytes"));
+    let etag = respond.header.etag();
+    if !accepts_ranges || !self.is_current(etag.as_ref(), respond.header.last_modified()) {
+      return respond;
+    }
+    respond.into_ranges(&self.ranges)
+  }
+
+  /// Whether the `If-Range` validator, if any, still matches the
+  /// representation of `etag` and `last_modified`.
+  pub fn is_current(&self, etag: Option<&EntityTag>, last_modified: Option<SystemTime>) -> bool {
+    self.if_range.as_ref().is_none_or(|if_range| if_range.matches(etag, last_modified))
+  }
+}
+
+/// Satisfiable `ranges` within `len` bytes, sorted, with the overlapping or
+/// adjacent ones merged.
+pub(crate) fn coalesce(ranges: &[ByteRange], len: u64) -> Vec<(u64, u64)> {
+  let mut resolved = ranges.iter()
+      .filter_map(|range| range.resolve(len))
+      .collect::<Vec<(u64, u64)>>();
+  resolved.sort_unstable();
+
+  let mut merged: Vec<(u64, u64)> = Vec::with_capacity(resolved.len());
+  for (first, last) in resolved {
+    match merged.last_mut() {
+      Some((_, merged_last)) if first <= merged_last.saturating_add(1) =>
+        *merged_last = (*merged_last).max(last),
+      _ => merged.push((first, last))
+    }
+  }
+  merged
+}
+
+/// Body of a `multipart/byteranges` respond holding `parts` of a body of
+/// `len` bytes, each labelled with `content_type` if any.
+pub(crate) fn multipart_body(parts: &[(u64, u64, &[u8])], len: u64,
+                             content_type: Option<&str>, boundary: &str) -> Vec<u8> {
+  let mut multipart = Vec::new();
+  for &(first, last, part) in parts {
+    multipart.extend_from_slice(format!("--{}\r\n", boundary).as_bytes());
+    if let Some(content_type) = content_type {
+      multipart.extend_from_slice(format!("Content-Type: {}\r\n", content_type).as_bytes());
+    }
+    multipart.extend_from_slice(
+      format!("Content-Range: {}\r\n\r\n", content_range(first, last, len)).as_bytes());
+    multipart.extend_from_slice(part);
+    multipart.extend_from_slice(b"\r\n");
+  }
+  multipart.extend_from_slice(format!("--{}--\r\n", boundary).as_bytes());
+  multipart
+}
+
+/// Value of `Content-Range` for bytes `first` to `last` of `len`.
+#[inline]
+pub(crate) fn content_range(first: u64, last: u64, len: u64) -> String {
+  format!("bytes {}-{}/{}", first, last, len)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parses_byte_range_specs() {
+    assert_eq!(ByteRange::parse("0-499"), Some(ByteRange::FromTo(0, 499)));
+    assert_eq!(ByteRange::parse("500-"), Some(ByteRange::From(500)));
+    assert_eq!(ByteRange::parse("-500"), Some(ByteRange::Suffix(500)));
+    assert_eq!(ByteRange::parse("5-5"), Some(ByteRange::FromTo(5, 5)));
+  }
+
+  #[test]
+  fn rejects_malformed_byte_range_specs() {
+    for spec in ["", "-", "500", "9-5", "+1-5", "1-+5", "a-5", "1 -5", "99999999999999999999-"] {
+      assert_eq!(ByteRange::parse(spec), None, "{:?}", spec);
+    }
+  }
+
+  #[test]
+  fn parses_range_header_values() {
+    assert_eq!(ByteRange::parse_ranges("bytes=0-0, -1"),
+               Some(vec![ByteRange::FromTo(0, 0), ByteRange::Suffix(1)]));
+    assert_eq!(ByteRange::parse_ranges(" Bytes = 10- ,, "),
+               Some(vec![ByteRange::From(10)]));
+    assert_eq!(ByteRange::parse_ranges("items=0-5"), None);
+    assert_eq!(ByteRange::parse_ranges("bytes="), None);
+    assert_eq!(ByteRange::parse_ranges("bytes=0-5,x"), None);
+    assert_eq!(ByteRange::parse_ranges("0-5"), None);
+  }
+
+  #[test]
+  fn resolves_ranges_within_the_length() {
+    assert_eq!(ByteRange::FromTo(0, 499).resolve(1000), Some((0, 499)));
+    assert_eq!(ByteRange::FromTo(500, 5000).resolve(1000), Some((500, 999)));
+    assert_eq!(ByteRange::From(900).resolve(1000), Some((900, 999)));
+    assert_eq!(ByteRange::Suffix(100).resolve(1000), Some((900, 999)));
+    assert_eq!(ByteRange::Suffix(5000).resolve(1000), Some((0, 999)));
+  }
+
+  #[test]
+  fn resolves_unsatisfiable_ranges_to_none() {
+    assert_eq!(ByteRange::FromTo(1000, 1001).resolve(1000), None);
+    assert_eq!(ByteRange::From(1000).resolve(1000), None);
+    assert_eq!(ByteRange::Suffix(0).resolve(1000), None);
+    assert_eq!(ByteRange::FromTo(0, 0).resolve(0), None);
+    assert_eq!(ByteRange::Suffix(1).resolve(0), None);
+  }
+
+  #[test]
+  fn coalesces_overlapping_and_adjacent_ranges() {
+    let ranges = [ByteRange::FromTo(50, 59), ByteRange::FromTo(0, 9), ByteRange::FromTo(10, 19),
+      ByteRange::FromTo(55, 70), ByteRange::FromTo(30, 39)];
+    assert_eq!(coalesce(&ranges, 100), [(0, 19), (30, 39), (50, 70)]);
+  }
+
+  #[test]
+  fn coalesces_away_unsatisfiable_ranges() {
+    let ranges = [ByteRange::From(200), ByteRange::Suffix(10), ByteRange::From(95)];
+    assert_eq!(coalesce(&ranges, 100), [(90, 99)]);
+    assert_eq!(coalesce(&[ByteRange::From(100)], 100), []);
+  }
+
+  #[test]
+  fn writes_multipart_byteranges() {
+    let body = b"0123456789";
+    let parts = [(0, 1, &body[0..2]), (8, 9, &body[8..10])];
+    assert_eq!(
+      multipart_body(&parts, 10, Some("text/plain"), "SEP"),
+      b"--SEP\r\nContent-Type: text/plain\r\nContent-Range: bytes 0-1/10\r\n\r\n01\r\n\
+        --SEP\r\nContent-Type: text/plain\r\nContent-Range: bytes 8-9/10\r\n\r\n89\r\n\
+        --SEP--\r\n".to_vec());
+  }
+}
@@ -24,9 +24,18 @@
 //! licenseID=string&content=string&/paramsXML=string
 //! ```
 
+use std::borrow::Cow;
 use std::convert::TryFrom;
-
+use std::net::SocketAddr;
+use std::time::SystemTime;
+
+use crate::http::conditional::{EntityTag, Preconditions};
+use crate::http::error::ParseError;
+use crate::http::header::{Header, HeaderMap};
+use crate::http::negotiation::{self, MediaRange, MediaType};
+use crate::http::range::{ByteRange, IfRange};
 use crate::http::request::HTTPRequestParsingState::*;
+use crate::http::respond::StatusCode;
 use crate::http::version::HttpVersion;
 
 /// Struct of parsed HTTP Request
@@ -38,14 +47,77 @@ pub struct HTTPRequest<'a> {
   pub http_version: HttpVersion,
 
   // Header fields
-  pub header: Vec<HTTPRequestHeader<'a>>,
+  pub header: HeaderMap<HTTPRequestHeader<'a>>,
+
+  // Body field, decoded from chunks if sent with chunked Transfer-Encoding
+  pub body: Cow<'a, [u8]>,
+
+  // Addresses of the connection the request arrived on, set by `Server`
+  pub local_addr: Option<SocketAddr>,
+  pub peer_addr: Option<SocketAddr>,
+}
+
+impl<'a> HTTPRequest<'a> {
+  /// Whether the connection should stay open after responding, following
+  /// the `Connection` header or else the default of `http_version`.
+  pub fn keep_alive(&self) -> bool {
+    for option in self.header.connection() {
+      if option.eq_ignore_ascii_case("close") {
+        return false;
+      }
+      if option.eq_ignore_ascii_case("keep-alive") {
+        return true;
+      }
+    }
+    self.http_version == HttpVersion::Http_1_1
+  }
+
+  /// Conditional header fields of the request, `None` if it has none.
+  pub fn preconditions(&self) -> Option<Preconditions> {
+    Preconditions::of(self)
+  }
+
+  /// Status code to answer with instead of acting on the request, if its
+  /// preconditions fail against the current validators of the target
+  /// resource.
+  pub fn evaluate_preconditions(&self, etag: Option<&EntityTag>,
+                                last_modified: Option<SystemTime>) -> Option<StatusCode> {
+    self.preconditions()?.evaluate(etag, last_modified)
+  }
+
+  /// Byte ranges of the `Range` header field, `None` if absent, not in bytes
+  /// or malformed.
+  pub fn range(&self) -> Option<Vec<ByteRange>> {
+    ByteRange::parse_ranges(self.header.range()?)
+  }
 
-  // Body field
-  pub body: String,
+  /// Validator of the `If-Range` header field, `None` if absent or
+  /// malformed.
+  pub fn if_range(&self) -> Option<IfRange> {
+    IfRange::parse(self.header.if_range()?)
+  }
+
+  /// Media ranges of the `Accept` header fields, malformed ones dropped,
+  /// `None` if absent.
+  pub fn accept(&self) -> Option<Vec<&MediaRange<'a>>> {
+    self.header.accept()
+  }
+
+  /// Offered media type the client prefers per `Accept`, `None` if it
+  /// accepts none of `offers`, see `negotiation::negotiate`.
+  pub fn negotiate<'o>(&self, offers: &[&'o str]) -> Option<MediaType<'o>> {
+    negotiation::negotiate(self, offers)
+  }
+
+  /// Offered language tag the client prefers per `Accept-Language`, `None`
+  /// if it accepts none of `offers`.
+  pub fn negotiate_language<'o>(&self, offers: &[&'o str]) -> Option<&'o str> {
+    negotiation::negotiate_language(self, offers)
+  }
 }
 
 impl<'a> TryFrom<&'a str> for HTTPRequest<'a> {
-  type Error = &'static str;
+  type Error = ParseError;
 
   fn try_from(s: &'a str) -> Result<Self, Self::Error> {
     let mut method = None;
@@ -61,24 +133,20 @@ impl<'a> TryFrom<&'a str> for HTTPRequest<'a> {
       match status {
         ProcessingRequestLine => {
           let req_line = line.split(' ').collect::<Vec<&str>>();
-          if req_line.len() < 3 {
-            println!("Failed to parse Request Line from [{:?}]", line);
-          } else {
-            method = Some(req_line[0].into());
-            request_uri = Some(req_line[1].into());
-            http_version = Some(req_line[2].into());
-            status = ProcessingHeaders;
+          if req_line.len() != 3 {
+            return Err(ParseError::InvalidRequestLine);
           }
+          method = Some(HttpMethod::try_from(req_line[0])?);
+          request_uri = Some(RequestURI::try_from(req_line[1])?);
+          http_version = Some(HttpVersion::try_from(req_line[2])?);
+          status = ProcessingHeaders;
           parsed_str_count += line.len() + 2;
         }
 
         ProcessingHeaders => {
           if !line.is_empty() {
-            if header.is_none() {
-              header = Some(vec![])
-            }
-            header.as_mut().expect("Failed to get header vector!")
-                .push(line.into())
+            header.get_or_insert_with(HeaderMap::new)
+                .append(HTTPRequestHeader::try_from(line)?)
           } else {
             status = ProcessingBody;
           }
@@ -86,26 +154,29 @@ impl<'a> TryFrom<&'a str> for HTTPRequest<'a> {
         }
 
         ProcessingBody => {
-          body = Some((&s[parsed_str_count..]).to_owned());
+          body = Some(Cow::Borrowed(&s.as_bytes()[parsed_str_count..]));
         }
       }
     };
-    if method.is_some() && request_uri.is_some() && http_version.is_some() {
+    if let (Some(method), Some(request_uri), Some(http_version)) =
+    (method, request_uri, http_version) {
       Ok(HTTPRequest {
-        method: method.expect("Failed to get METHOD!"),
-        request_uri: request_uri.expect("Failed to get REQUEST URI!"),
-        http_version: http_version.expect("Failed to get HTTP VERSION!"),
+        method,
+        request_uri,
+        http_version,
         header: header.unwrap_or_default(),
         body: body.unwrap_or_default(),
+        local_addr: None,
+        peer_addr: None,
       })
     } else {
-      Err("Invalid request content!")
+      Err(ParseError::InvalidRequestLine)
     }
   }
 }
 
 /// Enum of HTTP Method field
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum HttpMethod {
   /* The GET method is used to retrieve information from the given server using
    * a given URI. Requests using GET should only retrieve data and should have
@@ -173,27 +244,200 @@ pub enum RequestURI<'a> {
 /// Enum of Header field
 #[derive(Debug)]
 pub enum HTTPRequestHeader<'a> {
-  Accept(Vec<HTTPRequestHeaderAccept<'a>>),
+  Accept(Vec<MediaRange<'a>>),
   AcceptEncoding(Vec<&'a str>),
   AcceptLanguage(&'a str),
   Connection(&'a str),
   ContentLength(usize),
   ContentType(&'a str),
   Host(&'a str),
+  IfMatch(&'a str),
+  IfModifiedSince(&'a str),
+  IfNoneMatch(&'a str),
+  IfRange(&'a str),
+  IfUnmodifiedSince(&'a str),
+  Range(&'a str),
   Referer(&'a str),
+  TransferEncoding(&'a str),
   UserAgent(&'a str),
   _OtherHeader(&'a str, &'a str),
 }
 
-/// Struct of Header field "Accept"
-#[derive(Debug)]
-pub struct HTTPRequestHeaderAccept<'a> {
-  mime_type: &'a str,
-  mime_subtype: &'a str,
-  q_factor_weighting: Option<f32>,
+impl<'a> HTTPRequestHeader<'a> {
+  /// Field name as written on the wire, or as received if not known.
+  pub fn name(&self) -> &'a str {
+    match self {
+      HTTPRequestHeader::Accept(_) => "Accept",
+      HTTPRequestHeader::AcceptEncoding(_) => "Accept-Encoding",
+      HTTPRequestHeader::AcceptLanguage(_) => "Accept-Language",
+      HTTPRequestHeader::Connection(_) => "Connection",
+      HTTPRequestHeader::ContentLength(_) => "Content-Length",
+      HTTPRequestHeader::ContentType(_) => "Content-Type",
+      HTTPRequestHeader::Host(_) => "Host",
+      HTTPRequestHeader::IfMatch(_) => "If-Match",
+      HTTPRequestHeader::IfModifiedSince(_) => "If-Modified-Since",
+      HTTPRequestHeader::IfNoneMatch(_) => "If-None-Match",
+      HTTPRequestHeader::IfRange(_) => "If-Range",
+      HTTPRequestHeader::IfUnmodifiedSince(_) => "If-Unmodified-Since",
+      HTTPRequestHeader::Range(_) => "Range",
+      HTTPRequestHeader::Referer(_) => "Referer",
+      HTTPRequestHeader::TransferEncoding(_) => "Transfer-Encoding",
+      HTTPRequestHeader::UserAgent(_) => "User-Agent",
+      HTTPRequestHeader::_OtherHeader(name, _) => name,
+    }
+  }
+
+  /// Field value, written back from its parsed form for `Accept`,
+  /// `Accept-Encoding` and `Content-Length`.
+  pub fn value(&self) -> Cow<'a, str> {
+    match self {
+      HTTPRequestHeader::Accept(ranges) => Cow::Owned(ranges.iter()
+          .map(MediaRange::to_string)
+          .collect::<Vec<String>>()
+          .join(", ")),
+      HTTPRequestHeader::AcceptEncoding(codings) => Cow::Owned(codings.join(", ")),
+      HTTPRequestHeader::ContentLength(length) => Cow::Owned(length.to_string()),
+      _ => Cow::Borrowed(self.text().unwrap_or_default())
+    }
+  }
+
+  /// Field value as received, `None` if parsed.
+  fn text(&self) -> Option<&'a str> {
+    match self {
+      HTTPRequestHeader::Accept(_) |
+      HTTPRequestHeader::AcceptEncoding(_) |
+      HTTPRequestHeader::ContentLength(_) => None,
+      HTTPRequestHeader::AcceptLanguage(value) |
+      HTTPRequestHeader::Connection(value) |
+      HTTPRequestHeader::ContentType(value) |
+      HTTPRequestHeader::Host(value) |
+      HTTPRequestHeader::IfMatch(value) |
+      HTTPRequestHeader::IfModifiedSince(value) |
+      HTTPRequestHeader::IfNoneMatch(value) |
+      HTTPRequestHeader::IfRange(value) |
+      HTTPRequestHeader::IfUnmodifiedSince(value) |
+      HTTPRequestHeader::Range(value) |
+      HTTPRequestHeader::Referer(value) |
+      HTTPRequestHeader::TransferEncoding(value) |
+      HTTPRequestHeader::UserAgent(value) |
+      HTTPRequestHeader::_OtherHeader(_, value) => Some(value),
+    }
+  }
+}
+
+impl Header for HTTPRequestHeader<'_> {
+  fn name(&self) -> &str {
+    HTTPRequestHeader::name(self)
+  }
+}
+
+/// Typed accessors of the known header fields. Those of list fields gather
+/// the items of every occurrence, as if they were sent as one; the others
+/// return the first occurrence.
+impl<'a> HeaderMap<HTTPRequestHeader<'a>> {
+  /// Media ranges of the `Accept` header fields, malformed ones dropped,
+  /// `None` if absent.
+  pub fn accept(&self) -> Option<Vec<&MediaRange<'a>>> {
+    let mut fields = self.iter().filter_map(|header| match header {
+      HTTPRequestHeader::Accept(ranges) => Some(ranges),
+      _ => None
+    }).peekable();
+    fields.peek()?;
+    Some(fields.flatten().collect())
+  }
+
+  /// Codings of the `Accept-Encoding` header fields, with their parameters,
+  /// `None` if absent.
+  pub fn accept_encoding(&self) -> Option<Vec<&'a str>> {
+    let mut fields = self.iter().filter_map(|header| match header {
+      HTTPRequestHeader::AcceptEncoding(codings) => Some(codings),
+      _ => None
+    }).peekable();
+    fields.peek()?;
+    Some(fields.flatten().copied().collect())
+  }
+
+  /// Language ranges of the `Accept-Language` header fields, with their
+  /// parameters, `None` if absent.
+  pub fn accept_language(&self) -> Option<Vec<&'a str>> {
+    self.list("Accept-Language")
+  }
+
+  /// Options of the `Connection` header fields.
+  pub fn connection(&self) -> Vec<&'a str> {
+    self.list("Connection").unwrap_or_default()
+  }
+
+  pub fn content_length(&self) -> Option<usize> {
+    self.iter().find_map(|header| match header {
+      HTTPRequestHeader::ContentLength(length) => Some(*length),
+      _ => None
+    })
+  }
+
+  pub fn content_type(&self) -> Option<&'a str> {
+    self.str("Content-Type")
+  }
+
+  pub fn host(&self) -> Option<&'a str> {
+    self.str("Host")
+  }
+
+  pub fn if_match(&self) -> Option<&'a str> {
+    self.str("If-Match")
+  }
+
+  pub fn if_modified_since(&self) -> Option<&'a str> {
+    self.str("If-Modified-Since")
+  }
+
+  pub fn if_none_match(&self) -> Option<&'a str> {
+    self.str("If-None-Match")
+  }
+
+  pub fn if_range(&self) -> Option<&'a str> {
+    self.str("If-Range")
+  }
+
+  pub fn if_unmodified_since(&self) -> Option<&'a str> {
+    self.str("If-Unmodified-Since")
+  }
+
+  pub fn range(&self) -> Option<&'a str> {
+    self.str("Range")
+  }
+
+  pub fn referer(&self) -> Option<&'a str> {
+    self.str("Referer")
+  }
+
+  pub fn transfer_encoding(&self) -> Option<&'a str> {
+    self.str("Transfer-Encoding")
+  }
+
+  pub fn user_agent(&self) -> Option<&'a str> {
+    self.str("User-Agent")
+  }
+
+  /// Value of the first field named `name`.
+  fn str(&self, name: &str) -> Option<&'a str> {
+    self.get(name)?.text()
+  }
+
+  /// Items of every field named `name`, `None` if absent.
+  fn list(&self, name: &str) -> Option<Vec<&'a str>> {
+    let mut fields = self.get_all(name).filter_map(HTTPRequestHeader::text).peekable();
+    fields.peek()?;
+    Some(fields
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .collect())
+  }
 }
 
 /// Enum of states when parsing from str/String to HTTPRequest
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug)]
 enum HTTPRequestParsingState {
   ProcessingRequestLine,
@@ -201,143 +445,130 @@ enum HTTPRequestParsingState {
   ProcessingBody,
 }
 
-impl From<&str> for HttpMethod {
-  fn from(s: &str) -> Self {
+impl HttpMethod {
+  /// Method as written on the wire.
+  pub fn as_str(self) -> &'static str {
+    match self {
+      HttpMethod::Get => "GET",
+      HttpMethod::Head => "HEAD",
+      HttpMethod::Post => "POST",
+      HttpMethod::Put => "PUT",
+      HttpMethod::Delete => "DELETE",
+      HttpMethod::Connect => "CONNECT",
+      HttpMethod::Option => "OPTIONS",
+      HttpMethod::Trace => "TRACE",
+    }
+  }
+}
+
+impl TryFrom<&str> for HttpMethod {
+  type Error = ParseError;
+
+  fn try_from(s: &str) -> Result<Self, Self::Error> {
     match s.to_ascii_uppercase().as_str() {
-      "GET" => HttpMethod::Get,
-      "HEAD" => HttpMethod::Head,
-      "POST" => HttpMethod::Post,
-      "PUT" => HttpMethod::Put,
-      "DELETE" => HttpMethod::Delete,
-      "CONNECT" => HttpMethod::Connect,
-      "OPTION" => HttpMethod::Option,
-      "TRACE" => HttpMethod::Trace,
-      _ => panic!("Bad HTTP Method [{}]!", s)
+      "GET" => Ok(HttpMethod::Get),
+      "HEAD" => Ok(HttpMethod::Head),
+      "POST" => Ok(HttpMethod::Post),
+      "PUT" => Ok(HttpMethod::Put),
+      "DELETE" => Ok(HttpMethod::Delete),
+      "CONNECT" => Ok(HttpMethod::Connect),
+      "OPTIONS" => Ok(HttpMethod::Option),
+      "TRACE" => Ok(HttpMethod::Trace),
+      _ => Err(ParseError::InvalidMethod(s.to_owned()))
     }
   }
 }
 
-impl<'a> From<&'a str> for RequestURI<'a> {
-  fn from(s: &'a str) -> Self {
+impl<'a> RequestURI<'a> {
+  /// Path component without the query, `None` for `Asterisk`.
+  pub fn path(&self) -> Option<&'a str> {
+    let path_and_query = match *self {
+      RequestURI::Asterisk => return None,
+      RequestURI::AbsolutePath(path) => path,
+      RequestURI::AbsoluteUri(uri) => {
+        let authority = &uri[uri.find("://").map_or(0, |index| index + 3)..];
+        authority.find('/').map_or("/", |index| &authority[index..])
+      }
+    };
+    Some(path_and_query.split(['?', '#'])
+        .next().unwrap_or_default())
+  }
+
+  /// Query component without the leading `?`, if any.
+  pub fn query(&self) -> Option<&'a str> {
+    match *self {
+      RequestURI::Asterisk => None,
+      RequestURI::AbsolutePath(uri) | RequestURI::AbsoluteUri(uri) => {
+        let query = &uri[uri.find('?')? + 1..];
+        Some(query.split('#').next().unwrap_or_default())
+      }
+    }
+  }
+}
+
+impl<'a> TryFrom<&'a str> for RequestURI<'a> {
+  type Error = ParseError;
+
+  fn try_from(s: &'a str) -> Result<Self, Self::Error> {
     match s {
-      "*" => RequestURI::Asterisk,
+      "*" => Ok(RequestURI::Asterisk),
       _ => {
         if s.contains("://") {
-          RequestURI::AbsoluteUri(s)
+          Ok(RequestURI::AbsoluteUri(s))
         } else if s.starts_with('/') {
-          RequestURI::AbsolutePath(s)
+          Ok(RequestURI::AbsolutePath(s))
         } else {
-          panic!("Bad Request URI [{}]!", s)
+          Err(ParseError::InvalidUri(s.to_owned()))
         }
       }
     }
   }
 }
 
-impl<'a> From<&'a str> for HTTPRequestHeader<'a> {
-  fn from(s: &'a str) -> Self {
-    let colon = s.find(':').expect(
-      format!("Failed to find a colon in [{}]!", s).as_str()
-    );
-
-    match s[..colon].to_ascii_uppercase().as_str() {
-      "ACCEPT" => {
-        let mut vec = vec![];
-        let mut s = &s[colon + 1..];
-        while s.starts_with(' ') {
-          s = &s[1..];
-        }
-        for mut item in s.split(',') {
-          item = item.trim();
-
-          let slash_index = item.find('/')
-              .expect("Failed to parse MIME Type in Accept header!");
-          let mime_type = &item[..slash_index];
-          item = &item[(slash_index + 1)..];
-
-          let semicolon_index = item.find(';');
-          let mime_subtype;
-          let q_factor_weighting;
-
-          if semicolon_index.is_some() {
-            let semicolon_index = semicolon_index
-                .expect("Failed to parse MIME Subtype in Accept header!");
-            mime_subtype = &item[..semicolon_index];
-            item = &item[(semicolon_index + 1)..];
-            while item.starts_with(' ') || item.starts_with('q') ||
-                item.starts_with('=') { item = &item[1..]; }
-
-            q_factor_weighting = Some(item.parse::<f32>()
-                .expect("Failed to parse Q-Factor Weighting to float number"));
-          } else {
-            mime_subtype = item;
-            q_factor_weighting = None;
-          }
-          vec.push(HTTPRequestHeaderAccept { mime_type, mime_subtype, q_factor_weighting });
-        }
-        HTTPRequestHeader::Accept(vec)
-      }
-      "ACCEPT-LANGUAGE" => {
-        let mut s = &s[colon + 1..];
-        while s.starts_with(' ') {
-          s = &s[1..];
-        }
-        HTTPRequestHeader::AcceptLanguage(s)
-      }
-      "ACCEPT-ENCODING" => {
-        let mut s = &s[colon + 1..];
-        while s.starts_with(' ') {
-          s = &s[1..];
-        }
-        HTTPRequestHeader::AcceptEncoding(s.split_ascii_whitespace().collect())
-      }
-      "CONNECTION" => {
-        let mut s = &s[colon + 1..];
-        while s.starts_with(' ') {
-          s = &s[1..];
-        }
-        HTTPRequestHeader::Connection(s)
-      }
-      "CONTENT-LENGTH" => {
-        let mut s = &s[colon + 1..];
-        while s.starts_with(' ') {
-          s = &s[1..];
-        }
-        HTTPRequestHeader::ContentLength(s.parse()
-            .expect("Failed to convert Content-Length into usize!"))
-      }
-      "CONTENT-TYPE" => {
-        let mut s = &s[colon + 1..];
-        while s.starts_with(' ') {
-          s = &s[1..];
-        }
-        HTTPRequestHeader::ContentType(s)
-      }
-      "HOST" => {
-        let mut s = &s[colon + 1..];
-        while s.starts_with(' ') {
-          s = &s[1..];
-        }
-        HTTPRequestHeader::Host(s)
-      }
-      "REFERER" => {
-        let mut s = &s[colon + 1..];
-        while s.starts_with(' ') {
-          s = &s[1..];
-        }
-        HTTPRequestHeader::Referer(s)
-      }
-      "USER-AGENT" => {
-        let mut s = &s[colon + 1..];
-        while s.starts_with(' ') {
-          s = &s[1..];
-        }
-        HTTPRequestHeader::UserAgent(s)
-      }
-      _ => {
-        HTTPRequestHeader::_OtherHeader(&s[..colon],
-                                        &s[colon + 2..])
-      }
+impl<'a> TryFrom<&'a str> for HTTPRequestHeader<'a> {
+  type Error = ParseError;
+
+  fn try_from(s: &'a str) -> Result<Self, Self::Error> {
+    let malformed = || ParseError::MalformedHeader { line: s.to_owned() };
+    let colon = s.find(':').ok_or_else(malformed)?;
+    let name = &s[..colon];
+    if name.is_empty() || name.ends_with(' ') {
+      return Err(malformed());
     }
+    let value = s[colon + 1..].trim();
+
+    Ok(match name.to_ascii_uppercase().as_str() {
+      "ACCEPT" => HTTPRequestHeader::Accept(MediaRange::parse_list(value)),
+      "ACCEPT-LANGUAGE" => HTTPRequestHeader::AcceptLanguage(value),
+      "ACCEPT-ENCODING" =>
+        HTTPRequestHeader::AcceptEncoding(value.split(',')
+            .map(str::trim)
+            .filter(|coding| !coding.is_empty())
+            .collect()),
+      "CONNECTION" => HTTPRequestHeader::Connection(value),
+      "CONTENT-LENGTH" =>
+        HTTPRequestHeader::ContentLength(parse_content_length(value)?),
+      "CONTENT-TYPE" => HTTPRequestHeader::ContentType(value),
+      "HOST" => HTTPRequestHeader::Host(value),
+      "IF-MATCH" => HTTPRequestHeader::IfMatch(value),
+      "IF-MODIFIED-SINCE" => HTTPRequestHeader::IfModifiedSince(value),
+      "IF-NONE-MATCH" => HTTPRequestHeader::IfNoneMatch(value),
+      "IF-RANGE" => HTTPRequestHeader::IfRange(value),
+      "IF-UNMODIFIED-SINCE" => HTTPRequestHeader::IfUnmodifiedSince(value),
+      "RANGE" => HTTPRequestHeader::Range(value),
+      "REFERER" => HTTPRequestHeader::Referer(value),
+      "TRANSFER-ENCODING" => HTTPRequestHeader::TransferEncoding(value),
+      "USER-AGENT" => HTTPRequestHeader::UserAgent(value),
+      _ => HTTPRequestHeader::_OtherHeader(name, value)
+    })
   }
-}
\ No newline at end of file
+}
+
+/// Parse the value of a `Content-Length` header field, which must be all
+/// digits: no sign nor whitespace is allowed.
+pub(crate) fn parse_content_length(value: &str) -> Result<usize, ParseError> {
+  if value.is_empty() || !value.bytes().all(|byte| byte.is_ascii_digit()) {
+    return Err(ParseError::InvalidContentLength);
+  }
+  value.parse().map_err(|_| ParseError::InvalidContentLength)
+}
@@ -22,47 +22,291 @@
 //! {status:200,msg:"OK"}
 //! ```
 
+use std::borrow::Cow;
+use std::fmt::{self, Debug, Formatter};
+use std::io::{Error, Write};
+use std::time::SystemTime;
+
+use crate::http::chunked::{encode_chunk, LAST_CHUNK};
+use crate::http::conditional::{CacheControl, EntityTag};
+use crate::http::date::{fmt_http_date, parse_http_date};
+use crate::http::header::{Header, HeaderMap};
+use crate::http::range::{self, ByteRange};
 use crate::http::version::HttpVersion;
 
+/// Iterator producing the chunks of a chunked respond body
+pub type Chunks<'a> = Box<dyn Iterator<Item=Vec<u8>> + Send + 'a>;
+
 /// Struct of parsed HTTP Respond
 #[derive(Debug)]
 pub struct HTTPRespond<'a> {
   // First line
   pub http_version: HttpVersion,
   pub status_code: StatusCode,
-  pub reason_phrase: &'a str,
+  pub reason_phrase: Option<&'a str>,
 
   // Header fields
-  pub header: Vec<HttpRespondHeader<'a>>,
+  pub header: HeaderMap<HttpRespondHeader<'a>>,
 
   // Body field
-  pub body: &'a str,
+  pub body: RespondBody<'a>,
+}
+
+/// Enum of Body field
+pub enum RespondBody<'a> {
+  // Body of known length, sent with `Content-Length`.
+  Full(Cow<'a, [u8]>),
+
+  // Body of unknown length, sent with chunked `Transfer-Encoding` as the
+  // iterator yields. Empty chunks are skipped.
+  Chunked(Chunks<'a>),
+}
+
+impl Debug for RespondBody<'_> {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    match self {
+      RespondBody::Full(body) => f.debug_tuple("Full").field(body).finish(),
+      RespondBody::Chunked(_) => f.write_str("Chunked(..)"),
+    }
+  }
 }
 
-#[allow(dead_code)]
 impl<'a> HTTPRespond<'a> {
-  fn from_body(body: &'a str,
-               http_version: HttpVersion,
-               status_code: StatusCode,
-               reason_phrase: &'a str) -> Self {
+  pub fn from_body<T>(body: T,
+                      http_version: HttpVersion,
+                      status_code: StatusCode,
+                      reason_phrase: Option<&'a str>) -> Self
+    where T: Into<Cow<'a, [u8]>> {
+    HTTPRespond {
+      http_version,
+      status_code,
+      reason_phrase,
+      header: HeaderMap::new(),
+      body: RespondBody::Full(body.into()),
+    }
+  }
+
+  /// Respond whose body is streamed chunk by chunk from `chunks`.
+  pub fn from_chunks<T>(chunks: T,
+                        http_version: HttpVersion,
+                        status_code: StatusCode,
+                        reason_phrase: Option<&'a str>) -> Self
+    where T: Iterator<Item=Vec<u8>> + Send + 'a {
     HTTPRespond {
       http_version,
       status_code,
       reason_phrase,
-      header: Vec::new(),
-      body,
+      header: HeaderMap::new(),
+      body: RespondBody::Chunked(Box::new(chunks)),
+    }
+  }
+
+  pub fn with_header(respond: &mut HTTPRespond<'a>,
+                     header: HttpRespondHeader<'a>) {
+    respond.header.append(header);
+  }
+
+  pub fn with_etag(respond: &mut HTTPRespond<'a>, etag: &EntityTag) {
+    HTTPRespond::with_header(respond, HttpRespondHeader::ETag(etag.to_string().into()));
+  }
+
+  /// Add a strong `ETag` hashing the body, unless it is chunked.
+  pub fn with_content_etag(respond: &mut HTTPRespond<'a>) {
+    if let RespondBody::Full(body) = &respond.body {
+      let etag = EntityTag::from_bytes(body);
+      HTTPRespond::with_etag(respond, &etag);
+    }
+  }
+
+  pub fn with_last_modified(respond: &mut HTTPRespond<'a>, time: SystemTime) {
+    HTTPRespond::with_header(respond, HttpRespondHeader::LastModified(fmt_http_date(time).into()));
+  }
+
+  pub fn with_cache_control(respond: &mut HTTPRespond<'a>, cache_control: &CacheControl) {
+    HTTPRespond::with_header(respond,
+                             HttpRespondHeader::CacheControl(cache_control.to_string().into()));
+  }
+
+  /// Leave the body out, e.g. answering `HEAD`, but for the `Content-Length`
+  /// or chunked `Transfer-Encoding` it would have been sent with.
+  pub fn without_body(mut self) -> HTTPRespond<'a> {
+    let has_length = self.header.content_length().is_some() ||
+        self.header.get("Transfer-Encoding").is_some();
+    if !has_length && self.status_code.allows_body() {
+      let length = match &self.body {
+        RespondBody::Full(body) => HttpRespondHeader::ContentLength(body.len().to_string().into()),
+        RespondBody::Chunked(_) => HttpRespondHeader::TransferEncoding("chunked".into()),
+      };
+      self.header.append(length);
+    }
+    HTTPRespond { body: RespondBody::Full(Cow::Borrowed(&[])), ..self }
+  }
+
+  /// Cut a body of known length down to `ranges`: answer with a
+  /// `206 Partial Content` of a single part if one range is satisfiable, of
+  /// `multipart/byteranges` if several are, or `416 Range Not Satisfiable`
+  /// if none is. Chunked responds are returned as is.
+  pub fn into_ranges(self, ranges: &[ByteRange]) -> HTTPRespond<'a> {
+    let body = match self.body {
+      RespondBody::Full(body) => body,
+      chunked => return HTTPRespond { body: chunked, ..self }
+    };
+    let len = body.len() as u64;
+    let parts = range::coalesce(ranges, len).into_iter()
+        .map(|(first, last)| (first, last, &body[first as usize..=last as usize]))
+        .collect::<Vec<(u64, u64, &[u8])>>();
+    HTTPRespond { body: RespondBody::Full(Cow::Borrowed(&[])), ..self }.into_parts(len, &parts)
+  }
+
+  /// Answer with `parts` of a body of `len` bytes, each the first and last
+  /// byte of a satisfiable range along with its content, sorted and not
+  /// overlapping: see `into_ranges`.
+  pub(crate) fn into_parts(self, len: u64, parts: &[(u64, u64, &[u8])]) -> HTTPRespond<'a> {
+    let mut header = self.header;
+    header.remove("Content-Length");
+
+    let (status_code, body) = match parts {
+      [] => {
+        let status_code = StatusCode::RangeNotSatisfiable;
+        header = HeaderMap::new();
+        header.append(HttpRespondHeader::ContentRange(format!("bytes */{}", len).into()));
+        (status_code, Cow::Borrowed(status_code.reason_phrase().as_bytes()))
+      }
+      &[(first, last, part)] => {
+        header.append(HttpRespondHeader::ContentRange(
+          range::content_range(first, last, len).into()));
+        (StatusCode::PartialContent, Cow::Owned(part.to_vec()))
+      }
+      parts => {
+        let content_type = header.remove("Content-Type");
+        // Hashing the parts keeps the boundary out of them
+        let hashed = parts.iter().flat_map(|(_, _, part)| part.iter().copied())
+            .collect::<Vec<u8>>();
+        let boundary = format!("hello_server_{}", EntityTag::from_bytes(&hashed).tag);
+        let body = range::multipart_body(
+          parts, len, content_type.as_ref().map(HttpRespondHeader::value), &boundary);
+        header.append(HttpRespondHeader::ContentType(
+          format!("multipart/byteranges; boundary={}", boundary).into()));
+        (StatusCode::PartialContent, Cow::Owned(body))
+      }
+    };
+    HTTPRespond {
+      http_version: self.http_version,
+      status_code,
+      reason_phrase: None,
+      header,
+      body: RespondBody::Full(body),
     }
   }
 
-  fn with_header(respond: &mut HTTPRespond<'a>,
-                 header: HttpRespondHeader<'a>) {
-    respond.header.push(header);
+  /// Serialize the status line and header fields into `writer`.
+  ///
+  /// `Content-Length` (or chunked `Transfer-Encoding`) and `Date` are added
+  /// unless already present in `header`, the former only if the status code
+  /// allows a body. Line breaks of the reason phrase and of field values are
+  /// written as spaces, and fields of invalid names left out, so that no
+  /// header field can be injected.
+  pub fn write_head_to<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+    self.write_head(writer, true)
+  }
+
+  /// Serialize the status line and header fields of a respond whose body
+  /// is ended by closing the connection, as for HTTP/1.0 clients of a
+  /// chunked respond: neither `Content-Length` nor `Transfer-Encoding` is
+  /// written.
+  pub(crate) fn write_close_delimited_head_to<W: Write>(&self,
+                                                        writer: &mut W) -> Result<(), Error> {
+    self.write_head(writer, false)
   }
+
+  fn write_head<W: Write>(&self, writer: &mut W, framed: bool) -> Result<(), Error> {
+    write!(writer, "{} {} ", self.http_version.as_str(), self.status_code.code())?;
+    write_line_safe(writer,
+                    self.reason_phrase.unwrap_or_else(|| self.status_code.reason_phrase()))?;
+    writer.write_all(b"\r\n")?;
+
+    let mut has_length = false;
+    let mut has_date = false;
+    for header in &self.header {
+      match header {
+        HttpRespondHeader::ContentLength(_) |
+        HttpRespondHeader::TransferEncoding(_) if !framed => continue,
+        HttpRespondHeader::ContentLength(_) |
+        HttpRespondHeader::TransferEncoding(_) => has_length = true,
+        HttpRespondHeader::Date(_) => has_date = true,
+        _ => {}
+      }
+      if !is_field_name(header.name()) {
+        continue;
+      }
+      write!(writer, "{}: ", header.name())?;
+      write_line_safe(writer, header.value())?;
+      writer.write_all(b"\r\n")?;
+    }
+    if framed && !has_length && self.status_code.allows_body() {
+      match &self.body {
+        RespondBody::Full(body) =>
+          write!(writer, "Content-Length: {}\r\n", body.len())?,
+        RespondBody::Chunked(_) =>
+          writer.write_all(b"Transfer-Encoding: chunked\r\n")?,
+      }
+    }
+    if !has_date {
+      write!(writer, "Date: {}\r\n", fmt_http_date(SystemTime::now()))?;
+    }
+
+    writer.write_all(b"\r\n")
+  }
+
+  /// Serialize the whole respond into `writer`, draining every chunk of a
+  /// chunked body. The body is left out if the status code allows none.
+  pub fn write_to<W: Write>(self, writer: &mut W) -> Result<(), Error> {
+    self.write_head_to(writer)?;
+    if !self.status_code.allows_body() {
+      return Ok(());
+    }
+    match self.body {
+      RespondBody::Full(body) => writer.write_all(&body),
+      RespondBody::Chunked(chunks) => {
+        for chunk in chunks.filter(|chunk| !chunk.is_empty()) {
+          writer.write_all(&encode_chunk(&chunk))?;
+        }
+        writer.write_all(LAST_CHUNK)
+      }
+    }
+  }
+
+  /// Serialize into a newly allocated buffer, see `write_to`.
+  pub fn to_bytes(self) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(128);
+    self.write_to(&mut bytes)
+        .expect("Writing into a Vec should never fail!");
+    bytes
+  }
+}
+
+/// Whether `name` is a valid field name, i.e. a non-empty token.
+#[inline]
+fn is_field_name(name: &str) -> bool {
+  !name.is_empty() &&
+      name.bytes().all(|byte| byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte))
+}
+
+/// Write `text` with its CR, LF and NUL replaced by spaces, so that it
+/// cannot end the line it is written on.
+fn write_line_safe<W: Write>(writer: &mut W, text: &str) -> Result<(), Error> {
+  let mut lines = text.split(['\r', '\n', '\0']);
+  writer.write_all(lines.next().unwrap_or_default().as_bytes())?;
+  for line in lines {
+    writer.write_all(b" ")?;
+    writer.write_all(line.as_bytes())?;
+  }
+  Ok(())
 }
 
 /// Enum of HTTP Status Code field
 #[allow(non_camel_case_types, dead_code)]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum StatusCode {
   Continue = 100,
   SwitchingProtocols = 101,
@@ -130,14 +374,327 @@ pub enum StatusCode {
   NetworkAuthenticationRequired = 511,
 }
 
+impl StatusCode {
+  /// Numeric value of the status code.
+  pub fn code(self) -> u16 {
+    self as u16
+  }
+
+  /// Whether responds with the status code may have a body, and thus a
+  /// `Content-Length`.
+  pub fn allows_body(self) -> bool {
+    !matches!(self.code(), 100..=199 | 204 | 304)
+  }
+
+  /// Default Reason-Phrase of the status code.
+  pub fn reason_phrase(self) -> &'static str {
+    match self {
+      StatusCode::Continue => "Continue",
+      StatusCode::SwitchingProtocols => "Switching Protocols",
+      StatusCode::Processing => "Processing",
+
+      StatusCode::Ok => "OK",
+      StatusCode::Created => "Created",
+      StatusCode::Accepted => "Accepted",
+      StatusCode::NonAuthoritativeInformation => "Non-Authoritative Information",
+      StatusCode::NoContent => "No Content",
+      StatusCode::ResetContent => "Reset Content",
+      StatusCode::PartialContent => "Partial Content",
+      StatusCode::MultiStatus => "Multi-Status",
+      StatusCode::AlreadyReported => "Already Reported",
+      StatusCode::IMUsed => "IM Used",
+
+      StatusCode::MultipleChoices => "Multiple Choices",
+      StatusCode::MovedPermanently => "Moved Permanently",
+      StatusCode::Found => "Found",
+      StatusCode::SeeOther => "See Other",
+      StatusCode::NotModified => "Not Modified",
+      StatusCode::UseProxy => "Use Proxy",
+      StatusCode::TemporaryRedirect => "Temporary Redirect",
+      StatusCode::PermanentRedirect => "Permanent Redirect",
+
+      StatusCode::BadRequest => "Bad Request",
+      StatusCode::Unauthorized => "Unauthorized",
+      StatusCode::PaymentRequired => "Payment Required",
+      StatusCode::Forbidden => "Forbidden",
+      StatusCode::NotFound => "Not Found",
+      StatusCode::MethodNotAllowed => "Method Not Allowed",
+      StatusCode::NotAcceptable => "Not Acceptable",
+      StatusCode::ProxyAuthenticationRequired => "Proxy Authentication Required",
+      StatusCode::RequestTimeout => "Request Timeout",
+      StatusCode::Conflict => "Conflict",
+      StatusCode::Gone => "Gone",
+      StatusCode::LengthRequired => "Length Required",
+      StatusCode::PreconditionFailed => "Precondition Failed",
+      StatusCode::PayloadTooLarge => "Payload Too Large",
+      StatusCode::URITooLong => "URI Too Long",
+      StatusCode::UnsupportedMediaType => "Unsupported Media Type",
+      StatusCode::RangeNotSatisfiable => "Range Not Satisfiable",
+      StatusCode::ExpectationFailed => "Expectation Failed",
+      StatusCode::ImATeapot => "I'm a teapot",
+      StatusCode::MisdirectedRequest => "Misdirected Request",
+      StatusCode::UnprocessableEntity => "Unprocessable Entity",
+      StatusCode::Locked => "Locked",
+      StatusCode::FailedDependency => "Failed Dependency",
+      StatusCode::UpgradeRequired => "Upgrade Required",
+      StatusCode::PreconditionRequired => "Precondition Required",
+      StatusCode::TooManyRequests => "Too Many Requests",
+      StatusCode::RequestHeaderFieldsTooLarge => "Request Header Fields Too Large",
+      StatusCode::UnavailableForLegalReasons => "Unavailable For Legal Reasons",
+
+      StatusCode::InternalServerError => "Internal Server Error",
+      StatusCode::NotImplemented => "Not Implemented",
+      StatusCode::BadGateway => "Bad Gateway",
+      StatusCode::ServiceUnavailable => "Service Unavailable",
+      StatusCode::GatewayTimeout => "Gateway Timeout",
+      StatusCode::HTTPVersionNotSupported => "HTTP Version Not Supported",
+      StatusCode::VariantAlsoNegotiates => "Variant Also Negotiates",
+      StatusCode::InsufficientStorage => "Insufficient Storage",
+      StatusCode::LoopDetected => "Loop Detected",
+      StatusCode::NotExtended => "Not Extended",
+      StatusCode::NetworkAuthenticationRequired => "Network Authentication Required",
+    }
+  }
+}
+
 /// Enum of Header field
 #[allow(dead_code)]
 #[derive(Debug)]
 pub enum HttpRespondHeader<'a> {
-  Age(&'a str),
-  ContentEncoding(&'a str),
-  ContentLength(&'a str),
-  ContentType(&'a str),
-  Server(&'a str),
-  _OtherHeader(&'a str, &'a str),
-}
\ No newline at end of file
+  AcceptRanges(Cow<'a, str>),
+  Age(Cow<'a, str>),
+  Allow(Cow<'a, str>),
+  CacheControl(Cow<'a, str>),
+  Connection(Cow<'a, str>),
+  ContentEncoding(Cow<'a, str>),
+  ContentLength(Cow<'a, str>),
+  ContentRange(Cow<'a, str>),
+  ContentType(Cow<'a, str>),
+  Date(Cow<'a, str>),
+  ETag(Cow<'a, str>),
+  Expires(Cow<'a, str>),
+  LastModified(Cow<'a, str>),
+  Location(Cow<'a, str>),
+  Server(Cow<'a, str>),
+  TransferEncoding(Cow<'a, str>),
+  Vary(Cow<'a, str>),
+  _OtherHeader(Cow<'a, str>, Cow<'a, str>),
+}
+
+impl<'a> HttpRespondHeader<'a> {
+  /// Field name as written on the wire.
+  pub fn name(&self) -> &str {
+    match self {
+      HttpRespondHeader::AcceptRanges(_) => "Accept-Ranges",
+      HttpRespondHeader::Age(_) => "Age",
+      HttpRespondHeader::Allow(_) => "Allow",
+      HttpRespondHeader::CacheControl(_) => "Cache-Control",
+      HttpRespondHeader::Connection(_) => "Connection",
+      HttpRespondHeader::ContentEncoding(_) => "Content-Encoding",
+      HttpRespondHeader::ContentLength(_) => "Content-Length",
+      HttpRespondHeader::ContentRange(_) => "Content-Range",
+      HttpRespondHeader::ContentType(_) => "Content-Type",
+      HttpRespondHeader::Date(_) => "Date",
+      HttpRespondHeader::ETag(_) => "ETag",
+      HttpRespondHeader::Expires(_) => "Expires",
+      HttpRespondHeader::LastModified(_) => "Last-Modified",
+      HttpRespondHeader::Location(_) => "Location",
+      HttpRespondHeader::Server(_) => "Server",
+      HttpRespondHeader::TransferEncoding(_) => "Transfer-Encoding",
+      HttpRespondHeader::Vary(_) => "Vary",
+      HttpRespondHeader::_OtherHeader(name, _) => name,
+    }
+  }
+
+  /// Field value as written on the wire.
+  pub fn value(&self) -> &str {
+    match self {
+      HttpRespondHeader::AcceptRanges(value) |
+      HttpRespondHeader::Age(value) |
+      HttpRespondHeader::Allow(value) |
+      HttpRespondHeader::CacheControl(value) |
+      HttpRespondHeader::Connection(value) |
+      HttpRespondHeader::ContentEncoding(value) |
+      HttpRespondHeader::ContentLength(value) |
+      HttpRespondHeader::ContentRange(value) |
+      HttpRespondHeader::ContentType(value) |
+      HttpRespondHeader::Date(value) |
+      HttpRespondHeader::ETag(value) |
+      HttpRespondHeader::Expires(value) |
+      HttpRespondHeader::LastModified(value) |
+      HttpRespondHeader::Location(value) |
+      HttpRespondHeader::Server(value) |
+      HttpRespondHeader::TransferEncoding(value) |
+      HttpRespondHeader::Vary(value) |
+      HttpRespondHeader::_OtherHeader(_, value) => value,
+    }
+  }
+}
+
+impl Header for HttpRespondHeader<'_> {
+  fn name(&self) -> &str {
+    HttpRespondHeader::name(self)
+  }
+}
+
+/// Typed accessors of the known header fields, each returning the first
+/// occurrence.
+impl<'a> HeaderMap<HttpRespondHeader<'a>> {
+  pub fn accept_ranges(&self) -> Option<&str> {
+    self.str("Accept-Ranges")
+  }
+
+  pub fn cache_control(&self) -> Option<&str> {
+    self.str("Cache-Control")
+  }
+
+  pub fn content_encoding(&self) -> Option<&str> {
+    self.str("Content-Encoding")
+  }
+
+  /// `Content-Length`, `None` if absent or not a number.
+  pub fn content_length(&self) -> Option<usize> {
+    self.str("Content-Length")?.trim().parse().ok()
+  }
+
+  pub fn content_type(&self) -> Option<&str> {
+    self.str("Content-Type")
+  }
+
+  /// `ETag`, `None` if absent or malformed.
+  pub fn etag(&self) -> Option<EntityTag> {
+    EntityTag::parse(self.str("ETag")?)
+  }
+
+  /// `Last-Modified`, `None` if absent or not an HTTP-date.
+  pub fn last_modified(&self) -> Option<SystemTime> {
+    parse_http_date(self.str("Last-Modified")?)
+  }
+
+  pub fn location(&self) -> Option<&str> {
+    self.str("Location")
+  }
+
+  pub fn vary(&self) -> Option<&str> {
+    self.str("Vary")
+  }
+
+  fn str(&self, name: &str) -> Option<&str> {
+    self.get(name).map(HttpRespondHeader::value)
+  }
+}
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn respond(status_code: StatusCode, body: &'static str) -> HTTPRespond<'static> {
+    HTTPRespond::from_body(body.as_bytes(), HttpVersion::Http_1_1, status_code, None)
+  }
+
+  /// Lines of the head of `bytes`, and its body.
+  fn split(bytes: Vec<u8>) -> (Vec<String>, String) {
+    let text = String::from_utf8(bytes).unwrap();
+    let (head, body) = text.split_once("\r\n\r\n").unwrap();
+    (head.split("\r\n").map(str::to_owned).collect(), body.to_owned())
+  }
+
+  /// Head lines other than `Date`, which must be present.
+  fn without_date(lines: Vec<String>) -> Vec<String> {
+    let date = lines.iter().position(|line| line.starts_with("Date: ")).unwrap();
+    assert!(parse_http_date(&lines[date]["Date: ".len()..]).is_some());
+    lines.into_iter().enumerate().filter(|(index, _)| *index != date).map(|(_, line)| line)
+        .collect()
+  }
+
+  #[test]
+  fn writes_the_status_line() {
+    let (lines, _) = split(respond(StatusCode::NotFound, "").to_bytes());
+    assert_eq!(lines[0], "HTTP/1.1 404 Not Found");
+
+    let mut custom = respond(StatusCode::Ok, "");
+    custom.http_version = HttpVersion::Http_1_0;
+    custom.reason_phrase = Some("Fine");
+    let (lines, _) = split(custom.to_bytes());
+    assert_eq!(lines[0], "HTTP/1.0 200 Fine");
+  }
+
+  #[test]
+  fn adds_content_length_and_date() {
+    let mut hello = respond(StatusCode::Ok, "Hello");
+    HTTPRespond::with_header(&mut hello, HttpRespondHeader::ContentType("text/plain".into()));
+    let (lines, body) = split(hello.to_bytes());
+    assert_eq!(without_date(lines),
+               ["HTTP/1.1 200 OK", "Content-Type: text/plain", "Content-Length: 5"]);
+    assert_eq!(body, "Hello");
+  }
+
+  #[test]
+  fn keeps_the_content_length_and_date_given() {
+    let mut hello = respond(StatusCode::Ok, "");
+    HTTPRespond::with_header(&mut hello, HttpRespondHeader::ContentLength("5".into()));
+    HTTPRespond::with_header(&mut hello, HttpRespondHeader::Date("yesterday".into()));
+    let (lines, _) = split(hello.to_bytes());
+    assert_eq!(lines, ["HTTP/1.1 200 OK", "Content-Length: 5", "Date: yesterday"]);
+  }
+
+  #[test]
+  fn leaves_out_the_body_of_status_codes_without_one() {
+    for status_code in [StatusCode::Continue, StatusCode::NoContent, StatusCode::NotModified] {
+      let (lines, body) = split(respond(status_code, "Hello").to_bytes());
+      assert_eq!(without_date(lines).len(), 1, "{:?}", status_code);
+      assert_eq!(body, "", "{:?}", status_code);
+    }
+  }
+
+  #[test]
+  fn answers_head_with_the_length_only() {
+    let (lines, body) = split(respond(StatusCode::Ok, "Hello").without_body().to_bytes());
+    assert_eq!(without_date(lines), ["HTTP/1.1 200 OK", "Content-Length: 5"]);
+    assert_eq!(body, "");
+
+    let chunks = vec![b"Hello".to_vec()].into_iter();
+    let chunked = HTTPRespond::from_chunks(chunks, HttpVersion::Http_1_1, StatusCode::Ok, None);
+    let (lines, body) = split(chunked.without_body().to_bytes());
+    assert_eq!(without_date(lines), ["HTTP/1.1 200 OK", "Transfer-Encoding: chunked"]);
+    assert_eq!(body, "");
+  }
+
+  #[test]
+  fn frames_chunked_bodies() {
+    let chunks = vec![b"Hello".to_vec(), Vec::new(), b", chunked world!".to_vec()].into_iter();
+    let chunked = HTTPRespond::from_chunks(chunks, HttpVersion::Http_1_1, StatusCode::Ok, None);
+    let (lines, body) = split(chunked.to_bytes());
+    assert_eq!(without_date(lines), ["HTTP/1.1 200 OK", "Transfer-Encoding: chunked"]);
+    assert_eq!(body, "5\r\nHello\r\n10\r\n, chunked world!\r\n0\r\n\r\n");
+  }
+
+  #[test]
+  fn writes_close_delimited_heads_without_framing() {
+    let chunks = vec![b"Hello".to_vec()].into_iter();
+    let mut chunked = HTTPRespond::from_chunks(chunks, HttpVersion::Http_1_1,
+                                               StatusCode::Ok, None);
+    HTTPRespond::with_header(&mut chunked, HttpRespondHeader::TransferEncoding("chunked".into()));
+    let mut head = Vec::new();
+    chunked.write_close_delimited_head_to(&mut head).unwrap();
+    assert_eq!(without_date(split(head).0), ["HTTP/1.1 200 OK"]);
+  }
+
+  #[test]
+  fn cannot_inject_header_fields() {
+    let mut injected = respond(StatusCode::Found, "");
+    injected.reason_phrase = Some("Found\r\nSet-Cookie: a=1");
+    HTTPRespond::with_header(&mut injected,
+                             HttpRespondHeader::Location("/next\r\nSet-Cookie: b=2".into()));
+    for (name, value) in [("X-A\r\nSet-Cookie", "c"), ("X-B: 1", "d"), ("X-C", "e\n\0f")] {
+      HTTPRespond::with_header(&mut injected,
+                               HttpRespondHeader::_OtherHeader(name.into(), value.into()));
+    }
+    let (lines, _) = split(injected.to_bytes());
+    assert_eq!(without_date(lines), [
+      "HTTP/1.1 302 Found  Set-Cookie: a=1",
+      "Location: /next  Set-Cookie: b=2",
+      "X-C: e  f",
+      "Content-Length: 0",
+    ]);
+  }
+}
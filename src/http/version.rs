@@ -1,6 +1,10 @@
+use std::convert::TryFrom;
+
+use crate::http::error::ParseError;
+
 /// Enum of Http Version field
 #[allow(non_camel_case_types)]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum HttpVersion {
   // HTTP/0.9
   Http_0_9,
@@ -15,14 +19,28 @@ pub enum HttpVersion {
   Http_2_0,
 }
 
-impl From<&str> for HttpVersion {
-  fn from(s: &str) -> Self {
+impl HttpVersion {
+  /// HTTP-Version as written on the wire.
+  pub fn as_str(self) -> &'static str {
+    match self {
+      HttpVersion::Http_0_9 => "HTTP/0.9",
+      HttpVersion::Http_1_0 => "HTTP/1.0",
+      HttpVersion::Http_1_1 => "HTTP/1.1",
+      HttpVersion::Http_2_0 => "HTTP/2.0",
+    }
+  }
+}
+
+impl TryFrom<&str> for HttpVersion {
+  type Error = ParseError;
+
+  fn try_from(s: &str) -> Result<Self, Self::Error> {
     match s {
-      "HTTP/0.9" => HttpVersion::Http_0_9,
-      "HTTP/1.0" => HttpVersion::Http_1_0,
-      "HTTP/1.1" => HttpVersion::Http_1_1,
-      "HTTP/2.0" => HttpVersion::Http_2_0,
-      _ => panic!("Bad Http Version [{}]!", s)
+      "HTTP/0.9" => Ok(HttpVersion::Http_0_9),
+      "HTTP/1.0" => Ok(HttpVersion::Http_1_0),
+      "HTTP/1.1" => Ok(HttpVersion::Http_1_1),
+      "HTTP/2.0" => Ok(HttpVersion::Http_2_0),
+      _ => Err(ParseError::InvalidVersion(s.to_owned()))
     }
   }
-}
\ No newline at end of file
+}
@@ -1,85 +1,220 @@
 use std::future::Future;
-use std::io::{Error, ErrorKind, Read, Write};
-use std::net::{IpAddr, SocketAddr, Shutdown};
+use std::io::{Error, ErrorKind};
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Instant;
 
 use mio::{Events, Interest};
 pub use mio::{Poll, Token};
-use mio::event::Event;
 use mio::net::TcpListener;
 pub use mio::net::TcpStream;
 
-use crate::connection_manager::ConnMgr;
+pub use crate::blocking::{BlockingTask, spawn_blocking};
+pub use crate::config::{ConfigError, ServerConfig};
+pub use crate::executor::next_event;
+use crate::executor::Executor;
+pub use crate::handler::{AsyncHandler, Handler};
+pub use crate::router::{Params, Router};
+pub use crate::server::Server;
+use crate::server::{ACCEPT_BACKOFF, AcceptError, classify_accept_error, listener_index,
+                    listener_token};
+pub use crate::shutdown::ServerHandle;
+use crate::shutdown::ShutdownState;
+pub use crate::static_files::StaticFiles;
 
 pub mod http;
+mod blocking;
+mod config;
+mod connection;
 mod connection_manager;
-mod no_hash_hasher;
+mod executor;
+mod handler;
 mod num_trait;
+mod router;
+mod server;
+mod shutdown;
+mod static_files;
+mod timer;
 
-const SERVER_INCOMING_TOKEN: Token = Token(0);
+pub(crate) const EXECUTOR_WAKER_TOKEN: Token = Token(usize::MAX);
+pub(crate) const SHUTDOWN_WAKER_TOKEN: Token = Token(usize::MAX - 1);
 
+static LOGGING: AtomicBool = AtomicBool::new(cfg!(debug_assertions));
 
+/// Whether to print the events of the poll loops.
+#[inline]
+pub(crate) fn logging() -> bool {
+  LOGGING.load(Ordering::Relaxed)
+}
+
+pub(crate) fn set_logging(enabled: bool) {
+  LOGGING.store(enabled, Ordering::Relaxed)
+}
+
+
+/// Run `callback` on every stream accepted per `config`, blocking until shut
+/// down by `SIGINT` or `SIGTERM`.
+///
+/// Only the addresses, the event capacity, the shutdown timeout and the
+/// logging settings of `config` apply, as streams are driven on a single
+/// thread.
 pub fn hello<T>(
-  ip_addr: IpAddr,
-  port: u16,
-  _gmt_in_hr: i32,
-  _callback: fn(TcpStream, u128) -> T,
+  config: ServerConfig,
+  callback: fn(TcpStream, u128) -> T,
 ) -> Result<(), Error>
   where T: Future + Send + 'static {
-// Parse IP address into socket address
-  let socket_addr = SocketAddr::new(ip_addr.into(), port);
+  let handle = start_hello(config, callback)?;
+  handle.handle_signals()?;
+  handle.join()
+}
+
+/// Run `callback` on every stream accepted per `config` in the background.
+///
+/// Once shut down through the returned `ServerHandle`, no more streams are
+/// accepted, and the callback futures still running are given until the
+/// deadline of the shutdown to complete.
+pub fn start_hello<T>(
+  config: ServerConfig,
+  callback: fn(TcpStream, u128) -> T,
+) -> Result<ServerHandle, Error>
+  where T: Future + Send + 'static {
+  if let Some(enabled) = config.logging {
+    set_logging(enabled);
+  }
 
-// Setup the server socket for accepting new request
-  let mut server_acceptor = TcpListener::bind(socket_addr)?;
+// Setup the server sockets for accepting new request
+  let mut server_acceptors = config.socket_addrs.iter()
+      .map(|socket_addr| server::bind(&config, *socket_addr))
+      .collect::<Result<Vec<TcpListener>, Error>>()?;
 
-// Create a Poll instance
-  let mut poll: Poll = Poll::new()?;
+// Create a Poll instance, and the executor driving callback futures
+  let poll: Poll = Poll::new()?;
+  let executor = Executor::new(&poll, EXECUTOR_WAKER_TOKEN)?;
 
-// Start listening for incoming connections
-  poll.registry().register(
-    &mut server_acceptor, SERVER_INCOMING_TOKEN,
-    Interest::READABLE)?;
+  // A `Poll` only has room for one `mio::Waker`, shared with the shutdown
+  let shutdown = Arc::new(ShutdownState::new(executor.poll_waker(), config.shutdown_timeout));
 
-// Setup the connection manager
-  let mut conn_mgr = ConnMgr::new();
+// Start listening for incoming connections, each listener on its own token
+  for (index, server_acceptor) in server_acceptors.iter_mut().enumerate() {
+    poll.registry().register(
+      server_acceptor, listener_token(index),
+      Interest::READABLE)?;
+  }
+
+  let thread = {
+    let shutdown = shutdown.clone();
+    thread::Builder::new()
+        .name("hello-loop".into())
+        .spawn(move || {
+          run_hello(poll, server_acceptors, executor, &config, &shutdown, callback)
+        })?
+  };
+  Ok(ServerHandle::new(shutdown, thread))
+}
+
+/// Poll loop driving the callback futures, until shut down and every one of
+/// them completed or the deadline passed.
+fn run_hello<T>(
+  mut poll: Poll,
+  mut server_acceptors: Vec<TcpListener>,
+  mut executor: Executor<()>,
+  config: &ServerConfig,
+  shutdown: &ShutdownState,
+  callback: fn(TcpStream, u128) -> T,
+) -> Result<(), Error>
+  where T: Future + Send + 'static {
+  let mut tcp_counter: u128 = 0;
 
 // Create storage for events
-  let mut events = Events::with_capacity(256);
+  let mut events = Events::with_capacity(config.event_capacity);
+
+  // When to retry accepting after running out of file descriptors
+  let mut retry_at: Option<Instant> = None;
+
+  // Deadline of the shutdown, once requested
+  let mut deadline: Option<Instant> = None;
 
   loop {
-    poll.poll(&mut events, None)?;
+    let timeout = match (retry_at, deadline) {
+      (Some(at), Some(deadline)) => Some(at.min(deadline)),
+      (at, deadline) => at.or(deadline)
+    }.map(|at| at.saturating_duration_since(Instant::now()));
+    if let Err(err) = poll.poll(&mut events, timeout) {
+      if err.kind() == ErrorKind::Interrupted {
+        continue;
+      }
+      return Err(err);
+    }
+    if retry_at.is_some_and(|at| Instant::now() >= at) {
+      retry_at = None;
+      for server_acceptor in &mut server_acceptors {
+        if handle_server_incoming(server_acceptor, &mut poll, &mut executor,
+                                  &mut tcp_counter, callback)? {
+          retry_at = Some(Instant::now() + ACCEPT_BACKOFF);
+        }
+      }
+    }
 
     for event in events.iter() {
-      if cfg!(debug_assertions) {
+      if logging() {
         println!("New Event [{:?}]!", event);
       }
 
       match event.token() {
-        SERVER_INCOMING_TOKEN =>
-          if !handle_server_incoming(&mut server_acceptor,
-                                     &mut poll,
-                                     &mut conn_mgr)? { continue; },
-
-        token =>
-          if !handle_server_request(&mut poll,
-                                    &mut conn_mgr,
-                                    event,
-                                    token)? { continue; }
+        // Also woken on shutdown, checked once every event is handled
+        EXECUTOR_WAKER_TOKEN => {
+          executor.poll_woken();
+        }
+
+        token => match listener_index(token, server_acceptors.len()) {
+          Some(index) =>
+            if handle_server_incoming(&mut server_acceptors[index],
+                                      &mut poll,
+                                      &mut executor,
+                                      &mut tcp_counter,
+                                      callback)? {
+              retry_at = Some(Instant::now() + ACCEPT_BACKOFF);
+            },
+          None => {
+            executor.poll_task(token.0);
+          }
+        }
       }
     }
-  };
+
+    if deadline.is_none() {
+      deadline = shutdown.deadline();
+      if let Some(deadline) = deadline {
+// Stop accepting, then wait for the callback futures to complete
+        if logging() {
+          println!("Shutting down in [{:?}]!",
+                   deadline.saturating_duration_since(Instant::now()));
+        }
+        for server_acceptor in &mut server_acceptors {
+          poll.registry().deregister(server_acceptor)?;
+        }
+        retry_at = None;
+      }
+    }
+    if let Some(deadline) = deadline {
+      if executor.is_empty() || Instant::now() >= deadline {
+        return Ok(());
+      }
+    }
+  }
 }
 
 pub fn hello_from_str<T>(
   ip_addr: &str,
   port: u16,
-  gmt_in_hr: i32,
   callback: fn(TcpStream, u128) -> T,
 ) -> Result<(), Error>
   where T: Future + Send + 'static {
   match ip_addr.parse() {
     Ok(addr) => {
-      hello(addr, port,
-            gmt_in_hr, callback)
+      hello(ServerConfig::new(addr, port), callback)
     }
     Err(err) => {
       panic!("Failed to parse IpAddr from [{}]! [{:?}]", ip_addr, err)
@@ -90,132 +225,53 @@ pub fn hello_from_str<T>(
 pub fn hello_from_into_addr<T, U>(
   ip_addr: U,
   port: u16,
-  gmt_in_hr: i32,
   callback: fn(TcpStream, u128) -> T,
 ) -> Result<(), Error>
   where T: Future + Send + 'static,
         U: Into<IpAddr> {
-  hello(ip_addr.into(), port,
-        gmt_in_hr, callback)
+  hello(ServerConfig::new(ip_addr.into(), port), callback)
 }
 
+/// Accept every pending connection, returning `true` if file descriptors ran
+/// out and accepting should be retried later.
 #[inline]
-fn handle_server_incoming(
+fn handle_server_incoming<T>(
   server_acceptor: &mut TcpListener,
   poll: &mut Poll,
-  conn_mgr: &mut ConnMgr,
-) -> Result<bool, Error> {
-  let (stream, addr) = server_acceptor.accept()?;
-  if cfg!(debug_assertions) {
-    println!("Incoming stream from address [{:?}]!", addr);
-  }
-  let token = conn_mgr.generate_token(stream);
-  poll.registry().register(
-    conn_mgr.get_stream(&token.0).unwrap(), token,
-    Interest::READABLE)?;
-//          Interest::READABLE | Interest::WRITABLE)?;
-  poll.registry().reregister(
-    server_acceptor, SERVER_INCOMING_TOKEN,
-    Interest::READABLE)?;
-  Ok(true)
-}
-
-#[inline]
-fn handle_server_request(
-  poll: &mut Poll,
-  conn_mgr: &mut ConnMgr,
-  event: &Event,
-  token: Token,
-) -> Result<bool, Error> {
-  let token_id = token.0;
-  if cfg!(debug_assertions) {
-    println!("Incoming stream with token id [{}]!", token_id);
-  }
-
-  if conn_mgr.get_stream(&token_id).is_none() {
-    panic!("Failed to get stream from token [{}]", token_id)
-  }
-
-  if (
-    event.is_readable() && !handle_stream_read(&poll, conn_mgr, token)?
-  ) || (
-    event.is_writable() && !handle_stream_write(poll, conn_mgr, token)?
-  ) {
-    return Ok(false);
-  }
-  Ok(true)
-}
-
-#[inline]
-fn handle_stream_read(
-  poll: &&mut Poll,
-  conn_mgr: &mut ConnMgr,
-  mut token: Token,
-) -> Result<bool, Error> {
-  let token_id = token.0;
-  let stream = conn_mgr.get_stream(&token_id).unwrap();
-  let mut buf = Vec::new();
-
-  match stream.read_to_end(&mut buf) {
-    Ok(0) => {
-      if cfg!(debug_assertions) {
-        println!("Dropping stream with token id [{}]!", token_id);
-      }
-      conn_mgr.release_token(&mut token, &poll)?;
-      return Ok(false); // Equivalent to `continue`
-    }
-
-    Ok(size) => {
-      if cfg!(debug_assertions) {
-        println!("`````````server received [{}] bytes!`````````", size);
-      }
-    }
-
-    Err(err) => {
-      if err.kind() != ErrorKind::WouldBlock {
-        panic!("Readable event returned Error [{:?}]!", err);
-      }
-      if cfg!(debug_assertions) {
-        println!("````````` Read buffer Starts Here `````````");
-        println!("{}", String::from_utf8_lossy(&buf));
-        println!("````````` Read buffer Ends Here `````````");
+  executor: &mut Executor<()>,
+  tcp_counter: &mut u128,
+  callback: fn(TcpStream, u128) -> T,
+) -> Result<bool, Error>
+  where T: Future + Send + 'static {
+  loop {
+    let (mut stream, addr) = match server_acceptor.accept() {
+      Ok(accepted) => accepted,
+      Err(err) => match classify_accept_error(&err) {
+        AcceptError::Drained => return Ok(false),
+        AcceptError::Transient => continue,
+        AcceptError::Exhausted => return Ok(true),
+        AcceptError::Fatal => return Err(err)
       }
-      poll.registry().reregister(
-        stream, token,
-        Interest::WRITABLE)?;
+    };
+    *tcp_counter = tcp_counter.wrapping_add(1);
+    if logging() {
+      println!("Incoming stream #{} from address [{:?}]!", tcp_counter, addr);
     }
-  }
-  Ok(true)
-}
-
-#[inline]
-fn handle_stream_write(
-  poll: &mut Poll,
-  conn_mgr: &mut ConnMgr,
-  token: Token,
-) -> Result<bool, Error> {
-  let token_id = token.0;
-  let stream = conn_mgr.get_stream(&token_id).unwrap();
-
-  match stream.write_all("HTTP/1.1 200 OK\r\nHello: Server\r\n\r\ndata here".as_bytes()) {
-    Ok(()) => {
-      println!("server wrote succeed!");
-      stream.shutdown(Shutdown::Write)?;
-      poll.registry().reregister(
-        stream, token,
-        Interest::READABLE)?;
-    }
-
-    Err(err) => {
-      if cfg!(debug_assertions) {
-        println!("Writable event returned Error [{:?}]!", err);
+    let token = match executor.vacant_token() {
+      Some(token) => token,
+      None => {
+        if logging() {
+          println!("No more available tokens, refusing stream #{}!", tcp_counter);
+        }
+        continue;
       }
-      if err.kind() != ErrorKind::WouldBlock {
-        panic!("Writable event returned Error [{:?}]!", err);
-      }
-    }
+    };
+    poll.registry().register(
+      &mut stream, token,
+      Interest::READABLE | Interest::WRITABLE)?;
+    let future = callback(stream, *tcp_counter);
+    executor.spawn(token, async move { future.await; });
   }
-  Ok(true)
 }
 
 
@@ -0,0 +1,280 @@
+//! Dispatch requests on `HttpMethod` and path.
+//!
+//! Path patterns are made of `/`-separated segments, each being either
+//! - static, e.g. `users`, matching itself only;
+//! - a parameter, e.g. `:id`, capturing exactly one segment;
+//! - a wildcard, e.g. `*path`, capturing the rest of the path. It may only
+//!   be the last segment.
+//!
+//! Example:
+//! ```no run
+//! let mut router = Router::new();
+//! router.get("/users/:id", |request, params| { ... });
+//! router.get("/static/*path", |request, params| { ... });
+//! Server::new(Ipv4Addr::LOCALHOST.into(), 6006).serve(router)
+//! ```
+
+use crate::handler::Handler;
+use crate::http::request::{HTTPRequest, HttpMethod};
+use crate::http::respond::{HTTPRespond, HttpRespondHeader, StatusCode};
+
+/// Handler of a route, given the parameters captured from the path.
+type RouteHandler = Box<dyn Fn(&HTTPRequest, &Params) -> HTTPRespond<'static>
++ Send + Sync>;
+
+/// Enum of path pattern segments
+#[derive(Debug)]
+enum Segment {
+  Static(String),
+  Param(String),
+  Wildcard(String),
+}
+
+struct Route {
+  method: HttpMethod,
+  segments: Vec<Segment>,
+  handler: RouteHandler,
+}
+
+/// Parameters captured by `:param` and `*wildcard` segments
+#[derive(Debug, Default)]
+pub struct Params<'a> {
+  pairs: Vec<(&'a str, &'a str)>,
+}
+
+impl<'a> Params<'a> {
+  /// Value captured for `name`, without the leading `:` or `*`.
+  pub fn get(&self, name: &str) -> Option<&'a str> {
+    self.pairs.iter()
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| *value)
+  }
+
+  pub fn iter(&self) -> impl Iterator<Item=&(&'a str, &'a str)> {
+    self.pairs.iter()
+  }
+}
+
+/// Table of routes, itself a `Handler` answering `404 Not Found` when no
+/// path matches, and `405 Method Not Allowed` when no method does.
+///
+/// `HEAD` requests without a route of their own are answered by the `GET`
+/// route, leaving the body out.
+#[derive(Default)]
+pub struct Router {
+  routes: Vec<Route>,
+}
+
+impl Router {
+  pub fn new() -> Self {
+    Router { routes: Vec::new() }
+  }
+
+  /// Register `handler` for requests of `method` matching `pattern`.
+  ///
+  /// Panics if a wildcard is not the last segment of `pattern`.
+  pub fn route<F>(&mut self, method: HttpMethod, pattern: &str, handler: F) -> &mut Self
+    where F: Fn(&HTTPRequest, &Params) -> HTTPRespond<'static> + Send + Sync + 'static {
+    let segments = split_path(pattern)
+        .map(|segment| {
+          if let Some(name) = segment.strip_prefix(':') {
+            Segment::Param(name.to_owned())
+          } else if let Some(name) = segment.strip_prefix('*') {
+            Segment::Wildcard(name.to_owned())
+          } else {
+            Segment::Static(segment.to_owned())
+          }
+        })
+        .collect::<Vec<Segment>>();
+    if let Some(index) = segments.iter()
+        .position(|segment| matches!(segment, Segment::Wildcard(_))) {
+      if index != segments.len() - 1 {
+        panic!("Wildcard must be the last segment of [{}]!", pattern)
+      }
+    }
+
+    self.routes.push(Route { method, segments, handler: Box::new(handler) });
+    self
+  }
+
+  pub fn get<F>(&mut self, pattern: &str, handler: F) -> &mut Self
+    where F: Fn(&HTTPRequest, &Params) -> HTTPRespond<'static> + Send + Sync + 'static {
+    self.route(HttpMethod::Get, pattern, handler)
+  }
+
+  pub fn post<F>(&mut self, pattern: &str, handler: F) -> &mut Self
+    where F: Fn(&HTTPRequest, &Params) -> HTTPRespond<'static> + Send + Sync + 'static {
+    self.route(HttpMethod::Post, pattern, handler)
+  }
+
+  pub fn put<F>(&mut self, pattern: &str, handler: F) -> &mut Self
+    where F: Fn(&HTTPRequest, &Params) -> HTTPRespond<'static> + Send + Sync + 'static {
+    self.route(HttpMethod::Put, pattern, handler)
+  }
+
+  pub fn delete<F>(&mut self, pattern: &str, handler: F) -> &mut Self
+    where F: Fn(&HTTPRequest, &Params) -> HTTPRespond<'static> + Send + Sync + 'static {
+    self.route(HttpMethod::Delete, pattern, handler)
+  }
+}
+
+impl Handler for Router {
+  fn handle(&self, request: &HTTPRequest) -> HTTPRespond<'static> {
+    let path = match request.request_uri.path() {
+      Some(path) => path,
+      None => return error_respond(request, StatusCode::NotFound)
+    };
+
+    // The most specific route wins, e.g. `/a/b` over `/a/:x` over `/a/*x`
+    let mut best: Option<BestRoute> = None;
+    let mut best_get: Option<BestRoute> = None;
+    let mut allowed: Vec<HttpMethod> = Vec::new();
+    for route in &self.routes {
+      let (specificity, params) = match match_route(&route.segments, path) {
+        Some(matched) => matched,
+        None => continue
+      };
+      if route.method == request.method {
+        keep_most_specific(&mut best, (route, specificity, params));
+        continue;
+      }
+      if route.method == HttpMethod::Get && request.method == HttpMethod::Head {
+        keep_most_specific(&mut best_get, (route, specificity, params));
+      }
+      if !allowed.contains(&route.method) {
+        allowed.push(route.method);
+      }
+    }
+
+    match (best, best_get) {
+      (Some((route, _, params)), _) => (route.handler)(request, &params),
+      (None, Some((route, _, params))) => (route.handler)(request, &params).without_body(),
+      (None, None) if allowed.is_empty() => error_respond(request, StatusCode::NotFound),
+      (None, None) => {
+        // `GET` routes answer `HEAD` too
+        if let Some(index) = allowed.iter().position(|method| *method == HttpMethod::Get) {
+          if !allowed.contains(&HttpMethod::Head) {
+            allowed.insert(index + 1, HttpMethod::Head);
+          }
+        }
+        let mut respond = error_respond(request, StatusCode::MethodNotAllowed);
+        let allow = allowed.iter()
+            .map(|method| method.as_str())
+            .collect::<Vec<&str>>()
+            .join(", ");
+        HTTPRespond::with_header(&mut respond, HttpRespondHeader::Allow(allow.into()));
+        respond
+      }
+    }
+  }
+}
+
+/// Route matched by a request, with its specificity and captured parameters.
+type BestRoute<'a> = (&'a Route, Vec<u8>, Params<'a>);
+
+#[inline]
+fn keep_most_specific<'a>(best: &mut Option<BestRoute<'a>>, matched: BestRoute<'a>) {
+  if best.as_ref().is_none_or(|(_, best, _)| matched.1 > *best) {
+    *best = Some(matched);
+  }
+}
+
+#[inline]
+fn split_path(path: &str) -> impl Iterator<Item=&str> {
+  path.split('/').filter(|segment| !segment.is_empty())
+}
+
+/// Match `path` against `segments`, returning the specificity of each
+/// segment matched (higher is more specific) and the captured parameters.
+fn match_route<'a>(segments: &'a [Segment], path: &'a str)
+                   -> Option<(Vec<u8>, Params<'a>)> {
+  let mut specificity = Vec::with_capacity(segments.len());
+  let mut params = Params::default();
+  let mut rest = path.trim_start_matches('/');
+
+  for segment in segments {
+    if let Segment::Wildcard(name) = segment {
+      specificity.push(0);
+      params.pairs.push((name.as_str(), rest));
+      return Some((specificity, params));
+    }
+
+    let end = rest.find('/').unwrap_or(rest.len());
+    let value = &rest[..end];
+    if value.is_empty() {
+      return None;
+    }
+    match segment {
+      Segment::Static(expected) if expected == value => specificity.push(2),
+      Segment::Param(name) => {
+        specificity.push(1);
+        params.pairs.push((name.as_str(), value));
+      }
+      _ => return None
+    }
+    rest = rest[end..].trim_start_matches('/');
+  }
+
+  if rest.is_empty() {
+    Some((specificity, params))
+  } else {
+    None
+  }
+}
+
+#[inline]
+fn error_respond(request: &HTTPRequest, status_code: StatusCode) -> HTTPRespond<'static> {
+  HTTPRespond::from_body(status_code.reason_phrase().as_bytes(),
+                         request.http_version, status_code, None)
+}
+
+#[cfg(test)]
+mod tests {
+  use std::convert::TryFrom;
+
+  use super::*;
+  use crate::http::respond::RespondBody;
+
+  fn router() -> Router {
+    let mut router = Router::new();
+    router.get("/users/:id", |request, params| {
+      HTTPRespond::from_body(params.get("id").unwrap_or_default().as_bytes().to_vec(),
+                             request.http_version, StatusCode::Ok, None)
+    });
+    router.post("/users/:id", |request, _| {
+      HTTPRespond::from_body(&b""[..], request.http_version, StatusCode::Created, None)
+    });
+    router
+  }
+
+  fn handle(router: &Router, request_line: &str) -> HTTPRespond<'static> {
+    let head = format!("{}\r\nHost: localhost\r\n\r\n", request_line);
+    router.handle(&HTTPRequest::try_from(head.as_str()).unwrap())
+  }
+
+  #[test]
+  fn answers_head_with_get_route_without_body() {
+    let respond = handle(&router(), "HEAD /users/42 HTTP/1.1");
+    assert_eq!(respond.status_code, StatusCode::Ok);
+    assert_eq!(respond.header.content_length(), Some(2));
+    assert!(matches!(respond.body, RespondBody::Full(body) if body.is_empty()));
+  }
+
+  #[test]
+  fn prefers_head_route_over_get_route() {
+    let mut router = router();
+    router.route(HttpMethod::Head, "/users/:id", |request, _| {
+      HTTPRespond::from_body(&b""[..], request.http_version, StatusCode::NoContent, None)
+    });
+    assert_eq!(handle(&router, "HEAD /users/42 HTTP/1.1").status_code, StatusCode::NoContent);
+  }
+
+  #[test]
+  fn lists_head_along_get_in_allow() {
+    let respond = handle(&router(), "DELETE /users/42 HTTP/1.1");
+    assert_eq!(respond.status_code, StatusCode::MethodNotAllowed);
+    assert_eq!(respond.header.get("Allow").map(HttpRespondHeader::value),
+               Some("GET, HEAD, POST"));
+    assert_eq!(handle(&router(), "GET /unknown HTTP/1.1").status_code, StatusCode::NotFound);
+  }
+}
@@ -0,0 +1,920 @@
+use std::io::{Error, ErrorKind, Read};
+use std::mem;
+use std::net::{IpAddr, Shutdown, SocketAddr};
+use std::sync::Arc;
+use std::sync::atomic::AtomicUsize;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use mio::{Events, Interest};
+use mio::event::Event;
+use mio::net::{TcpListener, TcpSocket};
+
+use crate::{EXECUTOR_WAKER_TOKEN, logging, Poll, set_logging, SHUTDOWN_WAKER_TOKEN,
+            TcpStream, Token};
+use crate::blocking;
+use crate::config::ServerConfig;
+use crate::connection::{Connection, ConnPermit, ConnState, RespondChecks};
+use crate::connection_manager::{ConnMgr, reserved_index, reserved_token};
+use crate::executor::Executor;
+use crate::handler::{AsyncHandler, AsyncService, Dispatch, Handler, Service, SyncService};
+use crate::http::parser::ParseStatus;
+use crate::http::request::HTTPRequest;
+use crate::http::respond::{HTTPRespond, HttpRespondHeader, RespondBody, StatusCode};
+use crate::http::version::HttpVersion;
+use crate::shutdown::{ServerHandle, ShutdownState};
+use crate::timer::TimerWheel;
+
+const READ_CHUNK_SIZE: usize = 4096;
+const LISTEN_BACKLOG: u32 = 1024;
+pub(crate) const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+const TIMER_SLOTS: usize = 512;
+const TIMER_RESOLUTION: Duration = Duration::from_millis(100);
+
+/// HTTP server answering every parsed `HTTPRequest` through a handler.
+///
+/// Connections are accepted on an acceptor thread and handed round-robin to
+/// `workers` threads, each running its own poll loop.
+///
+/// Example:
+/// ```no run
+/// Server::new(Ipv4Addr::LOCALHOST.into(), 6006).serve(handle)
+/// ```
+pub struct Server {
+  config: ServerConfig,
+}
+
+impl Server {
+  pub fn new(ip_addr: IpAddr, port: u16) -> Self {
+    Self::with_config(ServerConfig::new(ip_addr, port))
+  }
+
+  pub fn with_config(config: ServerConfig) -> Self {
+    Server { config }
+  }
+
+  /// Serve requests with a synchronous `Handler`, blocking until shut down
+  /// by `SIGINT` or `SIGTERM`.
+  pub fn serve<H: Handler>(self, handler: H) -> Result<(), Error> {
+    let handle = self.start(handler)?;
+    handle.handle_signals()?;
+    handle.join()
+  }
+
+  /// Serve requests with an `AsyncHandler`, blocking until shut down by
+  /// `SIGINT` or `SIGTERM`.
+  pub fn serve_async<H: AsyncHandler>(self, handler: H) -> Result<(), Error> {
+    let handle = self.start_async(handler)?;
+    handle.handle_signals()?;
+    handle.join()
+  }
+
+  /// Serve requests with a synchronous `Handler` in the background.
+  pub fn start<H: Handler>(self, handler: H) -> Result<ServerHandle, Error> {
+    self.run(Arc::new(SyncService(handler)))
+  }
+
+  /// Serve requests with an `AsyncHandler` in the background.
+  pub fn start_async<H: AsyncHandler>(self, handler: H) -> Result<ServerHandle, Error> {
+    self.run(Arc::new(AsyncService(handler)))
+  }
+
+  fn run(self, service: Arc<dyn Service>) -> Result<ServerHandle, Error> {
+    let config = self.config;
+    if let Some(enabled) = config.logging {
+      set_logging(enabled);
+    }
+
+// Setup the server sockets for accepting new request
+    let mut server_acceptors = config.socket_addrs.iter()
+        .map(|socket_addr| bind(&config, *socket_addr))
+        .collect::<Result<Vec<TcpListener>, Error>>()?;
+
+// Start the worker threads and the pool for their blocking work
+    blocking::init(config.blocking_threads)?;
+    let workers = (0..config.workers)
+        .map(|id| Worker::spawn(id, service.clone(), config.clone()))
+        .collect::<Result<Vec<Worker>, Error>>()?;
+
+// Create a Poll instance, and the waker breaking it on shutdown
+    let poll: Poll = Poll::new()?;
+    let shutdown = Arc::new(ShutdownState::new(
+      Arc::new(mio::Waker::new(poll.registry(), SHUTDOWN_WAKER_TOKEN)?),
+      config.shutdown_timeout));
+
+// Start listening for incoming connections, each listener on its own token
+    for (index, server_acceptor) in server_acceptors.iter_mut().enumerate() {
+      poll.registry().register(
+        server_acceptor, listener_token(index),
+        Interest::READABLE)?;
+    }
+
+    let thread = {
+      let shutdown = shutdown.clone();
+      thread::Builder::new()
+          .name("hello-acceptor".into())
+          .spawn(move || {
+            run_acceptor(poll, server_acceptors, workers, &config, &shutdown)
+          })?
+    };
+    Ok(ServerHandle::new(shutdown, thread))
+  }
+}
+
+/// Bind a listening socket on `socket_addr`, whose buffer sizes accepted
+/// streams inherit.
+pub(crate) fn bind(config: &ServerConfig,
+                   socket_addr: SocketAddr) -> Result<TcpListener, Error> {
+  let socket = match socket_addr {
+    SocketAddr::V4(_) => TcpSocket::new_v4()?,
+    SocketAddr::V6(_) => {
+      let socket = TcpSocket::new_v6()?;
+      // Leave IPv4 connections to the IPv4 listener sharing the port
+      if config.socket_addrs.iter().any(SocketAddr::is_ipv4) {
+        set_only_v6(&socket)?;
+      }
+      socket
+    }
+  };
+  socket.set_reuseaddr(true)?;
+  if let Some(size) = config.read_buffer_size {
+    socket.set_recv_buffer_size(size)?;
+  }
+  if let Some(size) = config.write_buffer_size {
+    socket.set_send_buffer_size(size)?;
+  }
+  socket.bind(socket_addr)?;
+  socket.listen(LISTEN_BACKLOG)
+}
+
+#[cfg(unix)]
+fn set_only_v6(socket: &TcpSocket) -> Result<(), Error> {
+  use std::os::unix::io::AsRawFd;
+
+  let enabled: libc::c_int = 1;
+  // SAFETY: `enabled` outlives the call, and its size is passed along.
+  let result = unsafe {
+    libc::setsockopt(socket.as_raw_fd(), libc::IPPROTO_IPV6, libc::IPV6_V6ONLY,
+                     &enabled as *const libc::c_int as *const libc::c_void,
+                     std::mem::size_of::<libc::c_int>() as libc::socklen_t)
+  };
+  if result != 0 {
+    return Err(Error::last_os_error());
+  }
+  Ok(())
+}
+
+/// IPv6 sockets only accept IPv6 connections by default there.
+#[cfg(not(unix))]
+fn set_only_v6(_socket: &TcpSocket) -> Result<(), Error> {
+  Ok(())
+}
+
+/// Token of the listener bound to the `index`-th address of the config,
+/// never colliding with the tokens of a `ConnMgr` on the same `Poll`.
+#[inline]
+pub(crate) fn listener_token(index: usize) -> Token {
+  reserved_token(index)
+}
+
+/// Index of the listener registered with `token`, out of `listeners`.
+#[inline]
+pub(crate) fn listener_index(token: Token, listeners: usize) -> Option<usize> {
+  reserved_index(token).filter(|index| *index < listeners)
+}
+
+/// Poll loop of the acceptor, handing streams accepted by any listener
+/// round-robin to `workers` until shut down.
+fn run_acceptor(
+  mut poll: Poll,
+  mut server_acceptors: Vec<TcpListener>,
+  mut workers: Vec<Worker>,
+  config: &ServerConfig,
+  shutdown: &ShutdownState,
+) -> Result<(), Error> {
+  let mut next_worker = 0;
+  let open_connections = Arc::new(AtomicUsize::new(0));
+
+// Create storage for events
+  let mut events = Events::with_capacity(config.event_capacity);
+
+  // When to retry accepting after running out of file descriptors
+  let mut retry_at: Option<Instant> = None;
+
+  let deadline = loop {
+    let timeout = retry_at.map(|at| at.saturating_duration_since(Instant::now()));
+    if let Err(err) = poll.poll(&mut events, timeout) {
+      if err.kind() == ErrorKind::Interrupted {
+        continue;
+      }
+      return Err(err);
+    }
+
+    // Any other token is the shutdown waker
+    let mut ready = events.iter()
+        .filter_map(|event| {
+          if logging() {
+            println!("New Event [{:?}]!", event);
+          }
+          listener_index(event.token(), server_acceptors.len())
+        })
+        .collect::<Vec<usize>>();
+    if retry_at.is_some_and(|at| Instant::now() >= at) {
+      retry_at = None;
+      ready = (0..server_acceptors.len()).collect();
+    }
+
+    for index in ready {
+      if accept_all(&mut server_acceptors[index], &mut workers, &mut next_worker,
+                    &open_connections, config.max_connections)? {
+        retry_at = Some(Instant::now() + ACCEPT_BACKOFF);
+      }
+    }
+
+    if let Some(deadline) = shutdown.deadline() {
+      break deadline;
+    }
+  };
+
+// Stop accepting, then wait for every worker to drain its connections
+  if logging() {
+    println!("Shutting down in [{:?}]!",
+             deadline.saturating_duration_since(Instant::now()));
+  }
+  for mut server_acceptor in server_acceptors {
+    poll.registry().deregister(&mut server_acceptor)?;
+  }
+
+  for worker in &mut workers {
+    worker.send(Command::Drain(deadline))?;
+  }
+  workers.iter_mut()
+      .map(Worker::join)
+      .fold(Ok(()), Result::and)
+}
+
+/// Accept every connection pending on `server_acceptor` and hand them
+/// round-robin to `workers`, as readiness is edge-triggered.
+///
+/// Returns `true` if file descriptors ran out, in which case the pending
+/// connections are left in the backlog until retrying.
+fn accept_all(
+  server_acceptor: &mut TcpListener,
+  workers: &mut [Worker],
+  next_worker: &mut usize,
+  open_connections: &Arc<AtomicUsize>,
+  max_connections: Option<usize>,
+) -> Result<bool, Error> {
+  loop {
+    let (stream, addr) = match server_acceptor.accept() {
+      Ok(accepted) => accepted,
+      Err(err) => match classify_accept_error(&err) {
+        AcceptError::Drained => return Ok(false),
+        AcceptError::Transient => {
+          if logging() {
+            println!("Skipping failed incoming stream! [{:?}]", err);
+          }
+          continue;
+        }
+        AcceptError::Exhausted => {
+          if logging() {
+            println!("Backing off accepting for [{:?}]! [{:?}]", ACCEPT_BACKOFF, err);
+          }
+          return Ok(true);
+        }
+        AcceptError::Fatal => return Err(err)
+      }
+    };
+
+    let permit = match ConnPermit::acquire(open_connections, max_connections) {
+      Some(permit) => permit,
+      None => {
+        if logging() {
+          println!("Dropping stream from address [{:?}] over max connections!",
+                   addr);
+        }
+        drop(stream);
+        continue;
+      }
+    };
+    if logging() {
+      println!("Incoming stream from address [{:?}] for worker #{}!",
+               addr, next_worker);
+    }
+    workers[*next_worker].send(Command::Serve(stream, permit))?;
+    *next_worker = (*next_worker + 1) % workers.len();
+  }
+}
+
+/// Enum of ways to recover from a failed `accept`
+#[derive(Debug, PartialEq)]
+pub(crate) enum AcceptError {
+  // No connection is pending anymore.
+  Drained,
+
+  // Only the pending connection failed, accept the next one.
+  Transient,
+
+  // File descriptors or memory ran out, retry later.
+  Exhausted,
+
+  // The listener itself is broken.
+  Fatal,
+}
+
+pub(crate) fn classify_accept_error(err: &Error) -> AcceptError {
+  match err.kind() {
+    ErrorKind::WouldBlock => return AcceptError::Drained,
+    ErrorKind::Interrupted |
+    ErrorKind::ConnectionAborted |
+    ErrorKind::ConnectionReset |
+    ErrorKind::TimedOut => return AcceptError::Transient,
+    ErrorKind::OutOfMemory => return AcceptError::Exhausted,
+    _ => {}
+  }
+
+  #[cfg(unix)]
+  match err.raw_os_error() {
+    Some(libc::EMFILE) | Some(libc::ENFILE) |
+    Some(libc::ENOBUFS) | Some(libc::ENOMEM) => return AcceptError::Exhausted,
+    // Network errors already pending on the new connection, see accept(2)
+    Some(libc::EPROTO) | Some(libc::ENOPROTOOPT) | Some(libc::EHOSTDOWN) |
+    Some(libc::EHOSTUNREACH) | Some(libc::ENETDOWN) | Some(libc::ENETUNREACH) |
+    Some(libc::EOPNOTSUPP) | Some(libc::EPERM) => return AcceptError::Transient,
+    _ => {}
+  }
+
+  AcceptError::Fatal
+}
+
+/// Message from the acceptor to a worker.
+enum Command {
+  // Serve requests on the stream
+  Serve(TcpStream, ConnPermit),
+
+  // Close every connection, at the latest at the deadline, then stop
+  Drain(Instant),
+}
+
+/// Handle of a worker thread running its own poll loop over the streams it
+/// is sent.
+struct Worker {
+  sender: Sender<Command>,
+  poll_waker: Arc<mio::Waker>,
+  thread: Option<JoinHandle<Result<(), Error>>>,
+}
+
+impl Worker {
+  fn spawn(id: usize, service: Arc<dyn Service>,
+           config: ServerConfig) -> Result<Self, Error> {
+    let poll = Poll::new()?;
+    let executor = Executor::new(&poll, EXECUTOR_WAKER_TOKEN)?;
+    let poll_waker = executor.poll_waker();
+    let (sender, receiver) = channel();
+
+    let thread = thread::Builder::new()
+        .name(format!("hello-worker-{}", id))
+        .spawn(move || {
+          run_worker(poll, executor, &receiver, service.as_ref(), &config)
+        })?;
+
+    Ok(Worker { sender, poll_waker, thread: Some(thread) })
+  }
+
+  /// Send `command` to the worker, returning the error the worker stopped
+  /// with if it did.
+  fn send(&mut self, command: Command) -> Result<(), Error> {
+    if self.sender.send(command).is_err() {
+      return self.join()
+          .and(Err(Error::other("Worker thread stopped unexpectedly!")));
+    }
+    self.poll_waker.wake()
+  }
+
+  fn join(&mut self) -> Result<(), Error> {
+    match self.thread.take().map(JoinHandle::join) {
+      Some(Ok(result)) => result,
+      Some(Err(_)) => Err(Error::other("Worker thread panicked!")),
+      None => Ok(())
+    }
+  }
+}
+
+/// Poll loop of a worker, serving every stream received from `commands`
+/// until drained.
+fn run_worker(
+  mut poll: Poll,
+  mut executor: Executor<HTTPRespond<'static>>,
+  commands: &Receiver<Command>,
+  service: &dyn Service,
+  config: &ServerConfig,
+) -> Result<(), Error> {
+// Setup the connection manager
+  let mut conn_mgr = ConnMgr::new();
+
+// Create storage for events, and the wheel of connection timeouts
+  let mut events = Events::with_capacity(config.event_capacity);
+  let mut timers = TimerWheel::new(TIMER_SLOTS, TIMER_RESOLUTION);
+  let mut deadline: Option<Instant> = None;
+
+  loop {
+    let now = Instant::now();
+    let timeout = match (timers.timeout(now), deadline) {
+      (timeout, None) => timeout,
+      (timeout, Some(deadline)) => {
+        let until_deadline = deadline.saturating_duration_since(now);
+        Some(timeout.map_or(until_deadline, |timeout| timeout.min(until_deadline)))
+      }
+    };
+    if let Err(err) = poll.poll(&mut events, timeout) {
+      if err.kind() == ErrorKind::Interrupted {
+        continue;
+      }
+      return Err(err);
+    }
+
+    for event in events.iter() {
+      if logging() {
+        println!("New Event [{:?}]!", event);
+      }
+
+      match event.token() {
+        // Woken by either the acceptor thread or a task
+        EXECUTOR_WAKER_TOKEN => {
+          for command in commands.try_iter() {
+            match command {
+              Command::Serve(stream, permit) => {
+                if let Some(token) = handle_server_incoming(&mut poll, &mut conn_mgr,
+                                                            stream, permit, config)? {
+                  schedule_timeout(&mut timers, &mut conn_mgr, token);
+                }
+              }
+              Command::Drain(drain_deadline) => {
+                deadline = Some(drain_deadline);
+                drain(&mut poll, &mut conn_mgr)?;
+              }
+            }
+          }
+          for (token_id, respond) in executor.poll_woken() {
+            handle_respond(&mut poll, &mut conn_mgr, &mut executor,
+                           service, Token(token_id), respond)?;
+            schedule_timeout(&mut timers, &mut conn_mgr, Token(token_id));
+          }
+        }
+
+        token => {
+          if handle_server_request(&mut poll,
+                                   &mut conn_mgr,
+                                   &mut executor,
+                                   service,
+                                   event,
+                                   token)? {
+            schedule_timeout(&mut timers, &mut conn_mgr, token);
+          }
+        }
+      }
+    }
+
+    let now = Instant::now();
+    if let Some(deadline) = deadline {
+      if conn_mgr.is_empty() || now >= deadline {
+        return release_all(&mut poll, &mut conn_mgr, &mut executor);
+      }
+    }
+    for (token_id, deadline) in timers.expire(now) {
+      handle_timeout(&mut poll, &mut conn_mgr, &mut executor, &mut timers, token_id, deadline)?;
+    }
+  };
+}
+
+/// Register `stream` with a token of its own, refusing it if none is left.
+#[inline]
+fn handle_server_incoming(
+  poll: &mut Poll,
+  conn_mgr: &mut ConnMgr<Connection>,
+  stream: TcpStream,
+  permit: ConnPermit,
+  config: &ServerConfig,
+) -> Result<Option<Token>, Error> {
+  let token = match conn_mgr.generate_token(Connection::new(stream, permit, config)) {
+    Some(token) => token,
+    None => {
+      if logging() {
+        println!("No more available tokens, refusing stream!");
+      }
+      return Ok(None);
+    }
+  };
+  poll.registry().register(
+    conn_mgr.get_mut(&token.0).unwrap(), token,
+    Interest::READABLE)?;
+  Ok(Some(token))
+}
+
+#[inline]
+fn handle_server_request(
+  poll: &mut Poll,
+  conn_mgr: &mut ConnMgr<Connection>,
+  executor: &mut Executor<HTTPRespond<'static>>,
+  service: &dyn Service,
+  event: &Event,
+  token: Token,
+) -> Result<bool, Error> {
+  let token_id = token.0;
+  if logging() {
+    println!("Incoming stream with token id [{}]!", token_id);
+  }
+
+  if conn_mgr.get_mut(&token_id).is_none() {
+    // The connection was released earlier in this batch of events
+    return Ok(false);
+  }
+
+  if (
+    event.is_readable() &&
+        !handle_stream_read(poll, conn_mgr, executor, service, token)?
+  ) || (
+    event.is_writable() &&
+        !handle_stream_write(poll, conn_mgr, executor, service, token)?
+  ) {
+    return Ok(false);
+  }
+  Ok(true)
+}
+
+#[inline]
+fn handle_stream_read(
+  poll: &mut Poll,
+  conn_mgr: &mut ConnMgr<Connection>,
+  executor: &mut Executor<HTTPRespond<'static>>,
+  service: &dyn Service,
+  token: Token,
+) -> Result<bool, Error> {
+  let conn = conn_mgr.get_mut(&token.0).unwrap();
+  let mut chunk = [0; READ_CHUNK_SIZE];
+
+  // Drain the socket, as mio only reports edge-triggered readiness. Reading
+  // stops early once the buffer holds more than a request may span, as the
+  // parser then either completes a request, making the connection register
+  // interest again once answered, or fails.
+  let max_buffered = conn.parser.limits().request();
+  while conn.read_buf.len() < max_buffered {
+    match conn.stream.read(&mut chunk) {
+      Ok(0) => {
+        conn.read_closed = true;
+        break;
+      }
+
+      Ok(size) => {
+        if logging() {
+          println!("`````````server received [{}] bytes!`````````", size);
+        }
+        conn.read_buf.extend_from_slice(&chunk[..size]);
+      }
+
+      Err(err) => {
+        match err.kind() {
+          ErrorKind::WouldBlock => break,
+          ErrorKind::Interrupted => continue,
+          _ => {
+            if logging() {
+              println!("Readable event returned Error [{:?}]!", err);
+            }
+            conn.read_closed = true;
+            break;
+          }
+        }
+      }
+    }
+  }
+
+  process_requests(poll, conn_mgr, executor, service, token)
+}
+
+/// Answer every complete request in the read buffer in order, stopping at
+/// an in-flight async handler, then move the connection to its next state
+/// and register the interest matching it. Returns `false` if the connection
+/// was released.
+fn process_requests(
+  poll: &mut Poll,
+  conn_mgr: &mut ConnMgr<Connection>,
+  executor: &mut Executor<HTTPRespond<'static>>,
+  service: &dyn Service,
+  mut token: Token,
+) -> Result<bool, Error> {
+  let token_id = token.0;
+  let conn = conn_mgr.get_mut(&token_id).unwrap();
+
+  // Responds are answered in order, so wait for the current one to finish
+  if conn.state() != ConnState::Handling && !conn.is_streaming() {
+    while !conn.closing {
+      let mut pending = None;
+      let consumed = match conn.parser.parse(&conn.read_buf) {
+        Ok(ParseStatus::Complete(mut request, size)) => {
+          request.local_addr = conn.local_addr;
+          request.peer_addr = conn.peer_addr;
+          if logging() {
+            println!("Parsed request [{:?}]!", request);
+          }
+          let connection = respond_connection(&request);
+          conn.closing = connection == Some("close");
+          conn.request_version = request.http_version;
+          let checks = RespondChecks::of(&request, conn.compression);
+          match service.dispatch(&request) {
+            Dispatch::Ready(respond) =>
+              queue_respond(conn, checks.apply(respond), connection)?,
+            Dispatch::Pending(future) => {
+              conn.respond_connection = connection;
+              conn.respond_checks = checks;
+              pending = Some(future);
+            }
+          }
+          size
+        }
+
+        Ok(ParseStatus::Incomplete) => break,
+
+        Err(err) => {
+          if logging() {
+            println!("Failed to parse request! [{}]", err);
+          }
+          let respond = HTTPRespond::from_body(
+            &b""[..], HttpVersion::Http_1_1, err.status_code(), None);
+          queue_respond(conn, respond, Some("close"))?;
+          conn.closing = true;
+          conn.read_buf.len()
+        }
+      };
+      conn.read_buf.drain(..consumed);
+
+      if let Some(future) = pending {
+        match executor.spawn(token, future) {
+          Some(respond) => {
+            let connection = conn.respond_connection.take();
+            let respond = mem::take(&mut conn.respond_checks).apply(respond);
+            queue_respond(conn, respond, connection)?;
+          }
+          None => {
+            conn.set_state(ConnState::Handling);
+            break;
+          }
+        }
+      }
+    }
+  }
+
+  if conn.state() != ConnState::Handling {
+    conn.set_state(if conn.closing || (conn.read_closed && !conn.has_outbound()) {
+      ConnState::Closing
+    } else if conn.has_outbound() {
+      ConnState::Writing
+    } else if conn.read_buf.is_empty() {
+      ConnState::KeepAliveIdle
+    } else if conn.parser.is_reading_body() {
+      ConnState::ReadingBody
+    } else {
+      ConnState::ReadingHead
+    });
+  }
+
+  if conn.has_outbound() {
+    conn.set_interest(poll.registry(), token, Interest::WRITABLE)?;
+  } else if conn.state() == ConnState::Closing {
+    if logging() {
+      println!("Dropping stream with token id [{}]!", token_id);
+    }
+    conn_mgr.release_token(&mut token, poll)?;
+    return Ok(false); // Equivalent to `continue`
+  } else {
+    conn.set_interest(poll.registry(), token, Interest::READABLE)?;
+  }
+  Ok(true)
+}
+
+/// `Connection` header to answer `request` with, if any.
+#[inline]
+fn respond_connection(request: &HTTPRequest) -> Option<&'static str> {
+  match (request.keep_alive(), request.http_version) {
+    (false, _) => Some("close"),
+    (true, HttpVersion::Http_1_1) => None,
+    (true, _) => Some("keep-alive"),
+  }
+}
+
+/// Queue `respond` for writing, adding the `Connection` header unless the
+/// handler already set one.
+///
+/// Chunked Transfer-Encoding is only understood since HTTP/1.1, so a chunked
+/// body is streamed as is to older clients and ended by closing the
+/// connection.
+#[inline]
+fn queue_respond(
+  conn: &mut Connection,
+  mut respond: HTTPRespond<'static>,
+  connection: Option<&'static str>,
+) -> Result<(), Error> {
+  let close_delimited = conn.request_version != HttpVersion::Http_1_1 &&
+      respond.status_code.allows_body() &&
+      matches!(respond.body, RespondBody::Chunked(_));
+  if close_delimited {
+    respond.header.insert(HttpRespondHeader::Connection("close".into()));
+    conn.closing = true;
+  } else if let Some(connection) = connection {
+    let has_connection = respond.header.contains("Connection");
+    if !has_connection {
+      HTTPRespond::with_header(&mut respond, HttpRespondHeader::Connection(connection.into()));
+    }
+  }
+  let mut bytes = Vec::new();
+  if close_delimited {
+    respond.write_close_delimited_head_to(&mut bytes)?;
+  } else {
+    respond.write_head_to(&mut bytes)?;
+  }
+  match respond.body {
+    _ if !respond.status_code.allows_body() => conn.queue(bytes),
+    RespondBody::Full(body) => {
+      bytes.extend_from_slice(&body);
+      conn.queue(bytes);
+    }
+    RespondBody::Chunked(chunks) => {
+      conn.queue(bytes);
+      conn.stream_chunks(chunks, !close_delimited);
+    }
+  }
+  Ok(())
+}
+
+/// Queue the respond produced by an async handler for writing.
+#[inline]
+fn handle_respond(
+  poll: &mut Poll,
+  conn_mgr: &mut ConnMgr<Connection>,
+  executor: &mut Executor<HTTPRespond<'static>>,
+  service: &dyn Service,
+  token: Token,
+  respond: HTTPRespond<'static>,
+) -> Result<(), Error> {
+  let conn = match conn_mgr.get_mut(&token.0) {
+    Some(conn) => conn,
+    None => return Ok(())
+  };
+  // A connection drained on shutdown while handling closes after this respond
+  let connection = conn.respond_connection.take();
+  let connection = if conn.closing { Some("close") } else { connection };
+  let respond = mem::take(&mut conn.respond_checks).apply(respond);
+  queue_respond(conn, respond, connection)?;
+  conn.set_state(ConnState::Writing);
+  process_requests(poll, conn_mgr, executor, service, token)?;
+  Ok(())
+}
+
+#[inline]
+fn handle_stream_write(
+  poll: &mut Poll,
+  conn_mgr: &mut ConnMgr<Connection>,
+  executor: &mut Executor<HTTPRespond<'static>>,
+  service: &dyn Service,
+  mut token: Token,
+) -> Result<bool, Error> {
+  let token_id = token.0;
+  let conn = conn_mgr.get_mut(&token_id).unwrap();
+
+  match conn.flush() {
+    Ok(true) => {
+      if logging() {
+        println!("server wrote succeed!");
+      }
+      if conn.state() == ConnState::Closing {
+        conn.stream.shutdown(Shutdown::Write)?;
+        conn_mgr.release_token(&mut token, poll)?;
+        return Ok(false);
+      }
+      process_requests(poll, conn_mgr, executor, service, token)
+    }
+
+    // Wait for the next writable event
+    Ok(false) => Ok(true),
+
+    Err(err) => {
+      if logging() {
+        println!("Writable event returned Error [{:?}]!", err);
+      }
+      executor.cancel(&token_id);
+      conn_mgr.release_token(&mut token, poll)?;
+      Ok(false)
+    }
+  }
+}
+
+/// Hand the deadline of the state `token` is in to `timers`, if new.
+#[inline]
+fn schedule_timeout(
+  timers: &mut TimerWheel,
+  conn_mgr: &mut ConnMgr<Connection>,
+  token: Token,
+) {
+  if let Some(deadline) = conn_mgr.get_mut(&token.0)
+      .and_then(Connection::take_unscheduled_deadline) {
+    timers.schedule(token.0, deadline);
+  }
+}
+
+/// Time out the connection of `token_id` if still in the state `deadline`
+/// was set for: answer `408 Request Timeout` if in the middle of a request,
+/// giving the peer the send timeout to read it, release it otherwise,
+/// cancelling its handler if any.
+fn handle_timeout(
+  poll: &mut Poll,
+  conn_mgr: &mut ConnMgr<Connection>,
+  executor: &mut Executor<HTTPRespond<'static>>,
+  timers: &mut TimerWheel,
+  token_id: usize,
+  deadline: Instant,
+) -> Result<(), Error> {
+  let conn = match conn_mgr.get_mut(&token_id) {
+    Some(conn) if conn.deadline() == deadline => conn,
+    _ => return Ok(())
+  };
+
+  match conn.state() {
+    ConnState::ReadingHead | ConnState::ReadingBody => {
+      if logging() {
+        println!("Request on stream with token id [{}] timed out!", token_id);
+      }
+      let respond = HTTPRespond::from_body(
+        &b""[..], HttpVersion::Http_1_1, StatusCode::RequestTimeout, None);
+      queue_respond(conn, respond, Some("close"))?;
+      conn.closing = true;
+      conn.set_state(ConnState::Closing);
+      conn.set_interest(poll.registry(), Token(token_id), Interest::WRITABLE)?;
+      schedule_timeout(timers, conn_mgr, Token(token_id));
+      Ok(())
+    }
+
+    ConnState::KeepAliveIdle => {
+      if logging() {
+        println!("Releasing idle stream with token id [{}]!", token_id);
+      }
+      conn_mgr.release_token(&mut Token(token_id), poll)
+    }
+
+    ConnState::Handling | ConnState::Writing | ConnState::Closing => {
+      if logging() {
+        println!("Respond on stream with token id [{}] timed out!", token_id);
+      }
+      executor.cancel(&token_id);
+      conn_mgr.release_token(&mut Token(token_id), poll)
+    }
+  }
+}
+
+/// Release every idle connection, and close those with a respond in flight
+/// once it is written. Those in the middle of a request are left to finish
+/// it before the deadline.
+fn drain(
+  poll: &mut Poll,
+  conn_mgr: &mut ConnMgr<Connection>,
+) -> Result<(), Error> {
+  let mut idle = Vec::new();
+  for (token_id, conn) in conn_mgr.iter_mut() {
+    match conn.state() {
+      ConnState::KeepAliveIdle => idle.push(token_id),
+      ConnState::Handling => conn.closing = true,
+      ConnState::Writing => {
+        conn.closing = true;
+        conn.set_state(ConnState::Closing);
+      }
+      ConnState::ReadingHead | ConnState::ReadingBody | ConnState::Closing => {}
+    }
+  }
+
+  for token_id in idle {
+    if logging() {
+      println!("Releasing idle stream with token id [{}] on shutdown!", token_id);
+    }
+    conn_mgr.release_token(&mut Token(token_id), poll)?;
+  }
+  Ok(())
+}
+
+/// Release every connection left, cancelling their in-flight handlers.
+fn release_all(
+  poll: &mut Poll,
+  conn_mgr: &mut ConnMgr<Connection>,
+  executor: &mut Executor<HTTPRespond<'static>>,
+) -> Result<(), Error> {
+  let token_ids = conn_mgr.iter()
+      .map(|(token_id, _)| token_id)
+      .collect::<Vec<usize>>();
+
+  for token_id in token_ids {
+    if logging() {
+      println!("Dropping stream with token id [{}] on shutdown!", token_id);
+    }
+    executor.cancel(&token_id);
+    conn_mgr.release_token(&mut Token(token_id), poll)?;
+  }
+  Ok(())
+}
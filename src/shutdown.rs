@@ -0,0 +1,264 @@
+//! Stopping a running `Server`.
+//!
+//! A shutdown breaks the poll loop of the acceptor thread through a
+//! `mio::Waker`. The acceptor then stops accepting connections and asks
+//! every worker to drain, i.e. to release its idle connections and to close
+//! the others once their in-flight respond is written. Workers still busy
+//! at the deadline release their connections anyway.
+//!
+//! The single-threaded loop of `hello` stops accepting the same way, then
+//! keeps driving its callback futures until they complete or the deadline
+//! passes.
+
+use std::io::Error;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// State shared between a `ServerHandle` and the acceptor thread.
+pub(crate) struct ShutdownState {
+  deadline: Mutex<Option<Instant>>,
+
+  // Set from the signal handler, where locking `deadline` is not allowed
+  signaled: AtomicBool,
+  signal_timeout: Duration,
+
+  poll_waker: Arc<mio::Waker>,
+}
+
+impl ShutdownState {
+  pub fn new(poll_waker: Arc<mio::Waker>, signal_timeout: Duration) -> Self {
+    ShutdownState {
+      deadline: Mutex::new(None),
+      signaled: AtomicBool::new(false),
+      signal_timeout,
+      poll_waker,
+    }
+  }
+
+  /// Deadline of the requested shutdown, if any.
+  pub fn deadline(&self) -> Option<Instant> {
+    let mut deadline = self.deadline.lock()
+        .expect("Failed to lock shutdown deadline!");
+    if self.signaled.swap(false, Ordering::SeqCst) {
+      keep_earliest(&mut deadline, Instant::now() + self.signal_timeout);
+    }
+    *deadline
+  }
+
+  fn request(&self, deadline: Instant) -> Result<(), Error> {
+    keep_earliest(&mut self.deadline.lock()
+        .expect("Failed to lock shutdown deadline!"), deadline);
+    self.poll_waker.wake()
+  }
+}
+
+/// Keep the earliest of the requested deadlines.
+#[inline]
+fn keep_earliest(current: &mut Option<Instant>, deadline: Instant) {
+  if current.is_none_or(|current| deadline < current) {
+    *current = Some(deadline);
+  }
+}
+
+type ServerThread = JoinHandle<Result<(), Error>>;
+
+/// Handle of a `Server` started by `Server::start`, or of the poll loop
+/// started by `start_hello`, used to stop it.
+///
+/// Example:
+/// ```no run
+/// let handle = Server::new(Ipv4Addr::LOCALHOST.into(), 6006).start(handle)?;
+/// ...
+/// handle.shutdown_graceful(Duration::from_secs(5))?;
+/// handle.join()
+/// ```
+#[derive(Clone)]
+pub struct ServerHandle {
+  state: Arc<ShutdownState>,
+  thread: Arc<Mutex<Option<ServerThread>>>,
+}
+
+impl ServerHandle {
+  pub(crate) fn new(state: Arc<ShutdownState>, thread: ServerThread) -> Self {
+    ServerHandle {
+      state,
+      thread: Arc::new(Mutex::new(Some(thread))),
+    }
+  }
+
+  /// Stop the server right away, dropping every connection.
+  pub fn shutdown(&self) -> Result<(), Error> {
+    self.state.request(Instant::now())
+  }
+
+  /// Stop accepting connections, and give those open `timeout` to finish
+  /// their in-flight requests.
+  pub fn shutdown_graceful(&self, timeout: Duration) -> Result<(), Error> {
+    self.state.request(Instant::now() + timeout)
+  }
+
+  /// Shut down gracefully on `SIGINT` or `SIGTERM`, giving connections the
+  /// `ServerConfig::shutdown_timeout` to finish.
+  ///
+  /// Only the first server of the process handling signals is shut down by
+  /// them. Does nothing on non-unix platforms.
+  pub fn handle_signals(&self) -> Result<(), Error> {
+    if SIGNAL_TARGET.set(self.state.clone()).is_err() {
+      return Ok(());
+    }
+    install_signal_handler()
+  }
+
+  /// Block until the server has shut down, returning the error it stopped
+  /// with if any.
+  ///
+  /// Returns `Ok(())` right away if another clone has joined already.
+  pub fn join(&self) -> Result<(), Error> {
+    let thread = self.thread.lock()
+        .expect("Failed to lock server thread!")
+        .take();
+    match thread.map(JoinHandle::join) {
+      Some(Ok(result)) => result,
+      Some(Err(_)) => Err(Error::other("Server thread panicked!")),
+      None => Ok(())
+    }
+  }
+}
+
+static SIGNAL_TARGET: OnceLock<Arc<ShutdownState>> = OnceLock::new();
+
+/// Only touches atomics and writes to the waker, both async-signal-safe.
+#[cfg(unix)]
+extern "C" fn on_signal(_signal: libc::c_int) {
+  if let Some(state) = SIGNAL_TARGET.get() {
+    state.signaled.store(true, Ordering::SeqCst);
+    let _ = state.poll_waker.wake();
+  }
+}
+
+#[cfg(unix)]
+fn install_signal_handler() -> Result<(), Error> {
+  for signal in [libc::SIGINT, libc::SIGTERM] {
+    // SAFETY: `action` is fully initialized before being installed, and
+    // `on_signal` is async-signal-safe.
+    let result = unsafe {
+      let mut action: libc::sigaction = std::mem::zeroed();
+      action.sa_sigaction = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+      action.sa_flags = libc::SA_RESTART;
+      libc::sigemptyset(&mut action.sa_mask);
+      libc::sigaction(signal, &action, std::ptr::null_mut())
+    };
+    if result != 0 {
+      return Err(Error::last_os_error());
+    }
+  }
+  Ok(())
+}
+
+#[cfg(not(unix))]
+fn install_signal_handler() -> Result<(), Error> {
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use std::future::{self, Future};
+  use std::io::{Read, Write};
+  use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+  use std::task::{Poll, Waker};
+  use std::thread;
+
+  use super::*;
+  use crate::{Server, ServerConfig};
+  use crate::http::request::HTTPRequest;
+  use crate::http::respond::{HTTPRespond, StatusCode};
+  use crate::http::version::HttpVersion;
+
+  /// Completes `duration` from now, woken by a thread of its own.
+  fn delay(duration: Duration) -> impl Future<Output=()> + Send {
+    let state = Arc::new(Mutex::new((false, None::<Waker>)));
+    let timer = state.clone();
+    thread::spawn(move || {
+      thread::sleep(duration);
+      let mut timer = timer.lock().unwrap();
+      timer.0 = true;
+      if let Some(waker) = timer.1.take() {
+        waker.wake();
+      }
+    });
+    future::poll_fn(move |cx| {
+      let mut state = state.lock().unwrap();
+      if state.0 {
+        return Poll::Ready(());
+      }
+      state.1 = Some(cx.waker().clone());
+      Poll::Pending
+    })
+  }
+
+  /// Server on a free port of loopback, answering every request after
+  /// `handling`.
+  fn start(handling: Duration) -> (ServerHandle, SocketAddr) {
+    let addr = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap().local_addr().unwrap();
+    let config = ServerConfig::new(addr.ip(), addr.port()).workers(1).logging(false);
+    let handle = Server::with_config(config).start_async(move |_: &HTTPRequest| {
+      let handled = delay(handling);
+      async move {
+        handled.await;
+        HTTPRespond::from_body(&b"done"[..], HttpVersion::Http_1_1, StatusCode::Ok, None)
+      }
+    }).unwrap();
+    (handle, addr)
+  }
+
+  /// Stream of a request sent to `addr`, left time to be dispatched.
+  fn send_request(addr: SocketAddr) -> TcpStream {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+    stream.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+    thread::sleep(Duration::from_millis(200));
+    stream
+  }
+
+  fn read_all(stream: &mut TcpStream) -> String {
+    let mut bytes = Vec::new();
+    let _ = stream.read_to_end(&mut bytes);
+    String::from_utf8(bytes).unwrap()
+  }
+
+  #[test]
+  fn drains_in_flight_requests_before_stopping() {
+    let (handle, addr) = start(Duration::from_millis(500));
+    let mut idle = TcpStream::connect(addr).unwrap();
+    idle.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+    let mut busy = send_request(addr);
+
+    let started = Instant::now();
+    handle.shutdown_graceful(Duration::from_secs(10)).unwrap();
+    assert_eq!(read_all(&mut idle), "");
+    let respond = read_all(&mut busy);
+    assert!(respond.starts_with("HTTP/1.1 200 OK\r\n"), "{}", respond);
+    assert!(respond.contains("\r\nConnection: close\r\n"), "{}", respond);
+    assert!(respond.ends_with("\r\n\r\ndone"), "{}", respond);
+
+    handle.join().unwrap();
+    assert!(started.elapsed() < Duration::from_secs(5));
+    assert!(TcpStream::connect(addr).is_err());
+  }
+
+  #[test]
+  fn drops_requests_still_in_flight_at_the_deadline() {
+    let (handle, addr) = start(Duration::from_secs(30));
+    let mut busy = send_request(addr);
+
+    let started = Instant::now();
+    handle.shutdown_graceful(Duration::from_millis(200)).unwrap();
+    handle.join().unwrap();
+    assert!(started.elapsed() < Duration::from_secs(5));
+    assert_eq!(read_all(&mut busy), "");
+    // Joining again is a no-op
+    handle.join().unwrap();
+  }
+}
@@ -0,0 +1,595 @@
+//! Serve the files of a directory.
+//!
+//! Example:
+//! ```no run
+//! let assets = StaticFiles::new("dist").listing(true);
+//! Server::new(Ipv4Addr::LOCALHOST.into(), 6006).serve(assets)
+//! ```
+//!
+//! Mounted under a `Router` wildcard, the captured path is served instead of
+//! the request path:
+//! ```no run
+//! let assets = StaticFiles::new("dist");
+//! router.get("/assets/*path", move |request, params| {
+//!   assets.serve(request, params.get("path").unwrap_or_default())
+//! });
+//! ```
+
+use std::fmt::Write;
+use std::fs::{self, File};
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom};
+use std::path::{Component, Path, PathBuf};
+
+use crate::handler::Handler;
+use crate::http::conditional::EntityTag;
+use crate::http::mime;
+use crate::http::range::{self, RangeRequest};
+use crate::http::request::{HTTPRequest, HttpMethod};
+use crate::http::respond::{HTTPRespond, HttpRespondHeader, RespondBody, StatusCode};
+use crate::logging;
+
+const DEFAULT_INDEX: &str = "index.html";
+
+/// Most bytes of a file read at once, larger files or ranges of them being
+/// streamed in chunks of `CHUNK_SIZE` instead.
+const MAX_READ: u64 = 8 * 1024 * 1024;
+const CHUNK_SIZE: u64 = 64 * 1024;
+
+/// `Handler` serving the files under a root directory, answering `GET` and
+/// `HEAD` requests only.
+///
+/// Request paths are percent-decoded and resolved segment by segment, so
+/// that `..` and symbolic links cannot reach outside of the root: such
+/// requests are answered with `403 Forbidden`, as are directories without
+/// an index file unless listings are enabled.
+pub struct StaticFiles {
+  root: PathBuf,
+  index: Option<String>,
+  listing: bool,
+}
+
+impl StaticFiles {
+  /// Serve the files under `root`, answering directories with their
+  /// `index.html`.
+  pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+    StaticFiles {
+      root: root.into(),
+      index: Some(DEFAULT_INDEX.to_owned()),
+      listing: false,
+    }
+  }
+
+  /// Answer directories with their file named `index`, if any. `None` never
+  /// serves an index file.
+  pub fn index<S: Into<String>>(mut self, index: Option<S>) -> Self {
+    self.index = index.map(Into::into);
+    self
+  }
+
+  /// Answer directories without an index file with an HTML listing of their
+  /// entries.
+  pub fn listing(mut self, enabled: bool) -> Self {
+    self.listing = enabled;
+    self
+  }
+
+  /// Answer `request` with the file at `path`, relative to the root.
+  pub fn serve(&self, request: &HTTPRequest, path: &str) -> HTTPRespond<'static> {
+    if request.method != HttpMethod::Get && request.method != HttpMethod::Head {
+      let mut respond = error_respond(request, StatusCode::MethodNotAllowed);
+      HTTPRespond::with_header(&mut respond, HttpRespondHeader::Allow("GET, HEAD".into()));
+      return respond;
+    }
+
+    let file = match self.resolve(path) {
+      Ok(file) => file,
+      Err(status_code) => return error_respond(request, status_code)
+    };
+    let metadata = match fs::metadata(&file) {
+      Ok(metadata) => metadata,
+      Err(err) => return error_respond(request, io_status_code(&err))
+    };
+    if !metadata.is_dir() {
+      return file_respond(request, &file);
+    }
+
+    // Relative links of the index or listing resolve against the directory
+    let request_path = request.request_uri.path().unwrap_or("/");
+    if !request_path.ends_with('/') {
+      let mut location = request_path.to_owned();
+      location.push('/');
+      if let Some(query) = request.request_uri.query() {
+        location.push('?');
+        location.push_str(query);
+      }
+      let mut respond = error_respond(request, StatusCode::MovedPermanently);
+      HTTPRespond::with_header(&mut respond, HttpRespondHeader::Location(location.into()));
+      return respond;
+    }
+    if let Some(index) = &self.index {
+      let index = file.join(index);
+      if index.is_file() {
+        return file_respond(request, &index);
+      }
+    }
+    if self.listing {
+      let title = percent_decode(request_path);
+      return match listing(title.as_deref().unwrap_or(request_path), &file) {
+        Ok(html) => body_respond(request, mime::from_extension("html"), html.into_bytes()),
+        Err(err) => error_respond(request, io_status_code(&err))
+      };
+    }
+    error_respond(request, StatusCode::Forbidden)
+  }
+
+  /// Path of the file at the percent-encoded `path`, which must stay under
+  /// the root.
+  ///
+  /// Segments are decoded one by one, so that an encoded `/` is part of a
+  /// segment rather than a separator.
+  fn resolve(&self, path: &str) -> Result<PathBuf, StatusCode> {
+    let mut file = self.root.clone();
+    for segment in path.split('/') {
+      let segment = percent_decode(segment).ok_or(StatusCode::BadRequest)?;
+      if segment.is_empty() || segment == "." {
+        continue;
+      }
+      // Rejects `..`, encoded slashes, and anything parsed as a root or
+      // prefix on Windows
+      let mut components = Path::new(&segment).components();
+      match (components.next(), components.next()) {
+        (Some(Component::Normal(_)), None) if !segment.contains(['/', '\\', '\0']) =>
+          file.push(segment),
+        _ => return Err(StatusCode::Forbidden)
+      }
+    }
+
+    // Symbolic links may still point outside of the root
+    let root = fs::canonicalize(&self.root).map_err(|err| io_status_code(&err))?;
+    match fs::canonicalize(&file) {
+      Ok(canonical) if canonical.starts_with(&root) => Ok(file),
+      Ok(_) => Err(StatusCode::Forbidden),
+      Err(err) => Err(io_status_code(&err))
+    }
+  }
+}
+
+impl Handler for StaticFiles {
+  fn handle(&self, request: &HTTPRequest) -> HTTPRespond<'static> {
+    match request.request_uri.path() {
+      Some(path) => self.serve(request, path),
+      None => error_respond(request, StatusCode::NotFound)
+    }
+  }
+}
+
+#[inline]
+fn io_status_code(err: &io::Error) -> StatusCode {
+  match err.kind() {
+    ErrorKind::NotFound => StatusCode::NotFound,
+    ErrorKind::PermissionDenied => StatusCode::Forbidden,
+    _ => StatusCode::InternalServerError
+  }
+}
+
+/// Respond of the content of `path`, validated by its length and
+/// modification time so that `Server` answers conditional requests.
+///
+/// Only the requested ranges of the file are read, and none of it when
+/// answering `HEAD`.
+fn file_respond(request: &HTTPRequest, path: &Path) -> HTTPRespond<'static> {
+  let (mut file, metadata) = match File::open(path).and_then(|file| {
+    let metadata = file.metadata()?;
+    Ok((file, metadata))
+  }) {
+    Ok(opened) => opened,
+    Err(err) => return error_respond(request, io_status_code(&err))
+  };
+  let len = metadata.len();
+  let mut respond = HTTPRespond::from_body(&b""[..], request.http_version, StatusCode::Ok, None);
+  HTTPRespond::with_header(&mut respond,
+                           HttpRespondHeader::ContentType(mime::from_path(path).into()));
+  HTTPRespond::with_header(&mut respond, HttpRespondHeader::AcceptRanges("bytes".into()));
+  if let Ok(modified) = metadata.modified() {
+    HTTPRespond::with_etag(&mut respond, &EntityTag::from_metadata(len, modified));
+    HTTPRespond::with_last_modified(&mut respond, modified);
+  }
+  if request.method == HttpMethod::Head {
+    HTTPRespond::with_header(&mut respond,
+                             HttpRespondHeader::ContentLength(len.to_string().into()));
+    return respond;
+  }
+
+  let ranges = RangeRequest::of(request).filter(|range| {
+    range.is_current(respond.header.etag().as_ref(), respond.header.last_modified())
+  }).map(|range| range::coalesce(&range.ranges, len));
+  let read = match ranges {
+    // Several parts too large to read at once are not worth a multipart
+    Some(ranges) if ranges.len() > 1 &&
+        ranges.iter().map(|(first, last)| last - first + 1).sum::<u64>() > MAX_READ =>
+      read_whole(respond, file, len),
+    Some(ranges) => read_ranges(respond, &mut file, len, &ranges),
+    None => read_whole(respond, file, len)
+  };
+  read.unwrap_or_else(|err| error_respond(request, io_status_code(&err)))
+}
+
+/// Answer with the `len` bytes of `file`, streamed if more than `MAX_READ`.
+fn read_whole(mut respond: HTTPRespond<'static>, mut file: File,
+              len: u64) -> io::Result<HTTPRespond<'static>> {
+  respond.body = if len > MAX_READ {
+    RespondBody::Chunked(Box::new(FileChunks { file, remaining: len }))
+  } else {
+    RespondBody::Full(read_part(&mut file, 0, len)?.into())
+  };
+  Ok(respond)
+}
+
+/// Answer with `ranges` of the `len` bytes of `file`, see
+/// `HTTPRespond::into_parts`. A single range of more than `MAX_READ` bytes
+/// is streamed.
+fn read_ranges(mut respond: HTTPRespond<'static>, file: &mut File, len: u64,
+               ranges: &[(u64, u64)]) -> io::Result<HTTPRespond<'static>> {
+  if let &[(first, last)] = ranges {
+    if last - first + 1 > MAX_READ {
+      let file = file.try_clone()?;
+      file_at(&file, first)?;
+      respond.status_code = StatusCode::PartialContent;
+      HTTPRespond::with_header(&mut respond, HttpRespondHeader::ContentRange(
+        range::content_range(first, last, len).into()));
+      respond.body = RespondBody::Chunked(Box::new(FileChunks {
+        file,
+        remaining: last - first + 1,
+      }));
+      return Ok(respond);
+    }
+  }
+
+  let parts = ranges.iter()
+      .map(|&(first, last)| Ok((first, last, read_part(file, first, last - first + 1)?)))
+      .collect::<io::Result<Vec<(u64, u64, Vec<u8>)>>>()?;
+  let parts = parts.iter()
+      .map(|(first, last, part)| (*first, *last, part.as_slice()))
+      .collect::<Vec<(u64, u64, &[u8])>>();
+  Ok(respond.into_parts(len, &parts))
+}
+
+/// Read the `len` bytes of `file` from `offset`, failing if it has shrunk
+/// since.
+fn read_part(file: &mut File, offset: u64, len: u64) -> io::Result<Vec<u8>> {
+  file_at(file, offset)?;
+  let mut part = Vec::with_capacity(len as usize);
+  Read::by_ref(file).take(len).read_to_end(&mut part)?;
+  if (part.len() as u64) < len {
+    return Err(ErrorKind::UnexpectedEof.into());
+  }
+  Ok(part)
+}
+
+#[inline]
+fn file_at(mut file: &File, offset: u64) -> io::Result<()> {
+  file.seek(SeekFrom::Start(offset)).map(|_| ())
+}
+
+/// Chunks of the next `remaining` bytes of a file. Ends early if reading
+/// fails, as the head of the respond is out by then.
+struct FileChunks {
+  file: File,
+  remaining: u64,
+}
+
+impl Iterator for FileChunks {
+  type Item = Vec<u8>;
+
+  fn next(&mut self) -> Option<Vec<u8>> {
+    if self.remaining == 0 {
+      return None;
+    }
+    let mut chunk = Vec::with_capacity(self.remaining.min(CHUNK_SIZE) as usize);
+    match Read::by_ref(&mut self.file).take(CHUNK_SIZE.min(self.remaining))
+        .read_to_end(&mut chunk) {
+      Ok(0) => None,
+      Ok(read) => {
+        self.remaining -= read as u64;
+        Some(chunk)
+      }
+      Err(err) => {
+        if logging() {
+          println!("Failed to read file chunk! [{:?}]", err);
+        }
+        None
+      }
+    }
+  }
+}
+
+/// `200 OK` respond of `body`, left out but for its length if answering
+/// `HEAD`.
+fn body_respond(request: &HTTPRequest, content_type: &'static str,
+                body: Vec<u8>) -> HTTPRespond<'static> {
+  let length = body.len();
+  let body = if request.method == HttpMethod::Head { Vec::new() } else { body };
+  let mut respond = HTTPRespond::from_body(body, request.http_version, StatusCode::Ok, None);
+  HTTPRespond::with_header(&mut respond, HttpRespondHeader::ContentType(content_type.into()));
+  HTTPRespond::with_header(&mut respond,
+                           HttpRespondHeader::ContentLength(length.to_string().into()));
+  respond
+}
+
+#[inline]
+fn error_respond(request: &HTTPRequest, status_code: StatusCode) -> HTTPRespond<'static> {
+  HTTPRespond::from_body(status_code.reason_phrase().as_bytes(),
+                         request.http_version, status_code, None)
+}
+
+/// HTML page linking to every entry of `dir`, served at `path`, directories
+/// first.
+fn listing(path: &str, dir: &Path) -> Result<String, io::Error> {
+  let mut entries = Vec::new();
+  for entry in fs::read_dir(dir)? {
+    let entry = entry?;
+    let is_dir = fs::metadata(entry.path()).is_ok_and(|metadata| metadata.is_dir());
+    entries.push((!is_dir, entry.file_name().to_string_lossy().into_owned()));
+  }
+  entries.sort();
+
+  let title = html_escape(path);
+  let mut html = format!("<!DOCTYPE html>\n<html>\n<head>\n\
+    <meta charset=\"utf-8\">\n<title>Index of {0}</title>\n</head>\n\
+    <body>\n<h1>Index of {0}</h1>\n<ul>\n", title);
+  if path != "/" {
+    html.push_str("<li><a href=\"../\">../</a></li>\n");
+  }
+  for (is_file, name) in entries {
+    let slash = if is_file { "" } else { "/" };
+    let _ = writeln!(html, "<li><a href=\"{}{2}\">{}{2}</a></li>",
+                     percent_encode(&name), html_escape(&name), slash);
+  }
+  html.push_str("</ul>\n</body>\n</html>\n");
+  Ok(html)
+}
+
+/// Decode the `%XX` escapes of `path`, `None` if malformed or not UTF-8.
+fn percent_decode(path: &str) -> Option<String> {
+  let mut bytes = Vec::with_capacity(path.len());
+  let mut rest = path.as_bytes();
+  while let Some((&byte, tail)) = rest.split_first() {
+    if byte == b'%' {
+      let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+      bytes.push(u8::from_str_radix(hex, 16).ok()?);
+      rest = &tail[2..];
+    } else {
+      bytes.push(byte);
+      rest = tail;
+    }
+  }
+  String::from_utf8(bytes).ok()
+}
+
+/// Escape every byte of `segment` but the unreserved characters of URIs.
+fn percent_encode(segment: &str) -> String {
+  let mut encoded = String::with_capacity(segment.len());
+  for byte in segment.bytes() {
+    match byte {
+      b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' =>
+        encoded.push(byte as char),
+      _ => {
+        let _ = write!(encoded, "%{:02X}", byte);
+      }
+    }
+  }
+  encoded
+}
+
+fn html_escape(text: &str) -> String {
+  let mut escaped = String::with_capacity(text.len());
+  for char in text.chars() {
+    match char {
+      '&' => escaped.push_str("&amp;"),
+      '<' => escaped.push_str("&lt;"),
+      '>' => escaped.push_str("&gt;"),
+      '"' => escaped.push_str("&quot;"),
+      '\'' => escaped.push_str("&#39;"),
+      _ => escaped.push(char)
+    }
+  }
+  escaped
+}
+
+#[cfg(test)]
+mod tests {
+  use std::convert::TryFrom;
+
+  use super::*;
+
+  /// Empty directory named after `test`, unique to this process.
+  fn root(test: &str) -> PathBuf {
+    let root = std::env::temp_dir()
+        .join(format!("hello_server_static_{}_{}", std::process::id(), test));
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(&root).unwrap();
+    root
+  }
+
+  fn serve(files: &StaticFiles, head: &str) -> HTTPRespond<'static> {
+    files.handle(&HTTPRequest::try_from(head).unwrap())
+  }
+
+  fn get(files: &StaticFiles, path: &str) -> HTTPRespond<'static> {
+    serve(files, &format!("GET {} HTTP/1.1\r\n\r\n", path))
+  }
+
+  fn body(respond: &HTTPRespond) -> Vec<u8> {
+    match &respond.body {
+      RespondBody::Full(body) => body.to_vec(),
+      RespondBody::Chunked(_) => panic!("Unexpected chunked body!")
+    }
+  }
+
+  fn header<'r>(respond: &'r HTTPRespond, name: &str) -> Option<&'r str> {
+    respond.header.get(name).map(HttpRespondHeader::value)
+  }
+
+  #[test]
+  fn reads_requested_ranges_only() {
+    let root = root("ranges");
+    fs::write(root.join("digits.txt"), b"0123456789").unwrap();
+    let files = StaticFiles::new(&root);
+
+    let respond = serve(&files, "GET /digits.txt HTTP/1.1\r\nRange: bytes=2-4\r\n\r\n");
+    assert_eq!(respond.status_code, StatusCode::PartialContent);
+    assert_eq!(header(&respond, "Content-Range"), Some("bytes 2-4/10"));
+    assert_eq!(body(&respond), b"234");
+
+    let respond = serve(&files, "GET /digits.txt HTTP/1.1\r\nRange: bytes=0-0,-2\r\n\r\n");
+    assert_eq!(respond.status_code, StatusCode::PartialContent);
+    let multipart = String::from_utf8(body(&respond)).unwrap();
+    assert!(multipart.contains("Content-Range: bytes 0-0/10\r\n\r\n0\r\n"));
+    assert!(multipart.contains("Content-Range: bytes 8-9/10\r\n\r\n89\r\n"));
+
+    let respond = serve(&files, "GET /digits.txt HTTP/1.1\r\nRange: bytes=10-\r\n\r\n");
+    assert_eq!(respond.status_code, StatusCode::RangeNotSatisfiable);
+
+    let respond = serve(&files, "HEAD /digits.txt HTTP/1.1\r\n\r\n");
+    assert_eq!(respond.header.content_length(), Some(10));
+    assert!(body(&respond).is_empty());
+
+    let respond = serve(&files, "GET /digits.txt HTTP/1.1\r\n\r\n");
+    assert_eq!(body(&respond), b"0123456789");
+    fs::remove_dir_all(&root).unwrap();
+  }
+
+  #[test]
+  fn forbids_paths_out_of_the_root() {
+    let parent = root("traversal");
+    let root = parent.join("www");
+    fs::create_dir_all(root.join("sub")).unwrap();
+    fs::write(parent.join("secret.txt"), b"secret").unwrap();
+    fs::write(root.join("sub").join("a.txt"), b"a").unwrap();
+    let files = StaticFiles::new(&root);
+
+    for path in ["/../secret.txt", "/sub/../../secret.txt", "/%2e%2e/secret.txt",
+      "/sub/%2E%2E/%2e%2e/secret.txt", "/..%2fsecret.txt", "/sub%2fa.txt", "/sub%5ca.txt"] {
+      assert_eq!(get(&files, path).status_code, StatusCode::Forbidden, "{}", path);
+    }
+    assert_eq!(get(&files, "/sub/%zz").status_code, StatusCode::BadRequest);
+    assert_eq!(body(&get(&files, "/sub/./%61.txt")), b"a");
+    fs::remove_dir_all(&parent).unwrap();
+  }
+
+  #[cfg(unix)]
+  #[test]
+  fn forbids_symbolic_links_out_of_the_root() {
+    use std::os::unix::fs::symlink;
+
+    let parent = root("symlinks");
+    let root = parent.join("www");
+    fs::create_dir_all(&root).unwrap();
+    fs::write(parent.join("secret.txt"), b"secret").unwrap();
+    fs::write(root.join("public.txt"), b"public").unwrap();
+    symlink(parent.join("secret.txt"), root.join("escape.txt")).unwrap();
+    symlink(&parent, root.join("parent")).unwrap();
+    symlink(root.join("public.txt"), root.join("alias.txt")).unwrap();
+    let files = StaticFiles::new(&root);
+
+    assert_eq!(get(&files, "/escape.txt").status_code, StatusCode::Forbidden);
+    assert_eq!(get(&files, "/parent/secret.txt").status_code, StatusCode::Forbidden);
+    assert_eq!(body(&get(&files, "/alias.txt")), b"public");
+    fs::remove_dir_all(&parent).unwrap();
+  }
+
+  #[test]
+  fn tells_missing_files_from_forbidden_directories() {
+    let root = root("status");
+    fs::create_dir_all(root.join("empty")).unwrap();
+    let files = StaticFiles::new(&root);
+
+    assert_eq!(get(&files, "/missing.txt").status_code, StatusCode::NotFound);
+    assert_eq!(get(&files, "/missing/").status_code, StatusCode::NotFound);
+    assert_eq!(get(&files, "/empty/").status_code, StatusCode::Forbidden);
+
+    let respond = serve(&files, "POST /empty/ HTTP/1.1\r\n\r\n");
+    assert_eq!(respond.status_code, StatusCode::MethodNotAllowed);
+    assert_eq!(header(&respond, "Allow"), Some("GET, HEAD"));
+    fs::remove_dir_all(&root).unwrap();
+  }
+
+  #[test]
+  fn answers_directories_with_their_index() {
+    let root = root("index");
+    fs::create_dir_all(root.join("docs")).unwrap();
+    fs::write(root.join("docs").join("index.html"), b"<h1>Docs</h1>").unwrap();
+    fs::write(root.join("docs").join("home.html"), b"<h1>Home</h1>").unwrap();
+
+    let respond = get(&StaticFiles::new(&root), "/docs/");
+    assert_eq!(respond.status_code, StatusCode::Ok);
+    assert_eq!(body(&respond), b"<h1>Docs</h1>");
+
+    let respond = get(&StaticFiles::new(&root).index(Some("home.html")), "/docs/");
+    assert_eq!(body(&respond), b"<h1>Home</h1>");
+
+    let respond = get(&StaticFiles::new(&root).index(None::<String>), "/docs/");
+    assert_eq!(respond.status_code, StatusCode::Forbidden);
+    fs::remove_dir_all(&root).unwrap();
+  }
+
+  #[test]
+  fn redirects_directories_to_their_trailing_slash() {
+    let root = root("redirect");
+    fs::create_dir_all(root.join("docs")).unwrap();
+    let files = StaticFiles::new(&root);
+
+    let respond = get(&files, "/docs");
+    assert_eq!(respond.status_code, StatusCode::MovedPermanently);
+    assert_eq!(header(&respond, "Location"), Some("/docs/"));
+
+    let respond = get(&files, "/docs?page=2");
+    assert_eq!(header(&respond, "Location"), Some("/docs/?page=2"));
+    fs::remove_dir_all(&root).unwrap();
+  }
+
+  #[test]
+  fn escapes_names_of_listings() {
+    let root = root("listing");
+    fs::create_dir_all(root.join("a dir")).unwrap();
+    fs::write(root.join("<b>&\"x'.txt"), b"").unwrap();
+    let files = StaticFiles::new(&root).listing(true);
+
+    let respond = get(&files, "/");
+    assert_eq!(respond.status_code, StatusCode::Ok);
+    assert_eq!(header(&respond, "Content-Type"), Some("text/html; charset=utf-8"));
+    let html = String::from_utf8(body(&respond)).unwrap();
+    let dir = html.find("<li><a href=\"a%20dir/\">a dir/</a></li>").unwrap();
+    let file = html.find("<li><a href=\"%3Cb%3E%26%22x%27.txt\">&lt;b&gt;&amp;&quot;x&#39;.txt\
+      </a></li>").unwrap();
+    assert!(dir < file, "Directories are listed first");
+    assert!(!html.contains("<b>"));
+    assert!(!html.contains("href=\"../\""));
+
+    let html = String::from_utf8(body(&get(&files, "/a%20dir/"))).unwrap();
+    assert!(html.contains("<title>Index of /a dir/</title>"));
+    assert!(html.contains("<li><a href=\"../\">../</a></li>"));
+    fs::remove_dir_all(&root).unwrap();
+  }
+
+  #[test]
+  fn detects_media_types_from_extensions() {
+    let root = root("mime");
+    for name in ["page.HTML", "style.css", "data.json", "image.png", "blob.unknown", "README"] {
+      fs::write(root.join(name), b"").unwrap();
+    }
+    let files = StaticFiles::new(&root);
+
+    for (path, mime_type) in [
+      ("/page.HTML", "text/html; charset=utf-8"),
+      ("/style.css", "text/css; charset=utf-8"),
+      ("/data.json", "application/json"),
+      ("/image.png", "image/png"),
+      ("/blob.unknown", mime::DEFAULT_MIME_TYPE),
+      ("/README", mime::DEFAULT_MIME_TYPE),
+    ] {
+      assert_eq!(header(&get(&files, path), "Content-Type"), Some(mime_type), "{}", path);
+    }
+    fs::remove_dir_all(&root).unwrap();
+  }
+}
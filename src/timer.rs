@@ -0,0 +1,160 @@
+//! A hashed timer wheel keeping the deadlines of the connections of a worker.
+//!
+//! Time is split into ticks of `resolution`, and every timer is stored in the
+//! slot of the tick its deadline falls in, modulo the number of slots. Timers
+//! due in a later round of the wheel stay in their slot until then. Timers
+//! are never removed before they expire: the owner checks whether a fired
+//! deadline is still current instead.
+
+use std::convert::TryFrom;
+use std::mem;
+use std::time::{Duration, Instant};
+
+pub(crate) struct TimerWheel {
+  slots: Vec<Vec<(usize, Instant)>>,
+  resolution: Duration,
+  origin: Instant,
+
+  // Last tick whose slot has been expired
+  current: u64,
+  len: usize,
+}
+
+impl TimerWheel {
+  pub fn new(slot_count: usize, resolution: Duration) -> Self {
+    TimerWheel {
+      slots: (0..slot_count).map(|_| Vec::new()).collect(),
+      resolution,
+      origin: Instant::now(),
+      current: 0,
+      len: 0,
+    }
+  }
+
+  /// Tick `instant` falls in, rounded up so that no timer fires early.
+  #[inline]
+  fn tick_of(&self, instant: Instant) -> u64 {
+    let nanos = instant.saturating_duration_since(self.origin).as_nanos();
+    nanos.div_ceil(self.resolution.as_nanos()) as u64
+  }
+
+  /// Ticks fully elapsed at `instant`.
+  #[inline]
+  fn elapsed_ticks(&self, instant: Instant) -> u64 {
+    let nanos = instant.saturating_duration_since(self.origin).as_nanos();
+    (nanos / self.resolution.as_nanos()) as u64
+  }
+
+  #[inline]
+  fn slot_of(&self, tick: u64) -> usize {
+    (tick % self.slots.len() as u64) as usize
+  }
+
+  /// Fire `token_id` once `deadline` has passed.
+  pub fn schedule(&mut self, token_id: usize, deadline: Instant) {
+    let tick = self.tick_of(deadline).max(self.current + 1);
+    let slot = self.slot_of(tick);
+    self.slots[slot].push((token_id, deadline));
+    self.len += 1;
+  }
+
+  /// Time from `now` until the next tick holding timers, to be used as the
+  /// poll timeout. `None` if there is no timer.
+  pub fn timeout(&self, now: Instant) -> Option<Duration> {
+    if self.len == 0 {
+      return None;
+    }
+    let ticks = (1..=self.slots.len() as u64)
+        .find(|ticks| !self.slots[self.slot_of(self.current + ticks)].is_empty())
+        .unwrap_or(1);
+    let ticks = u32::try_from(self.current + ticks).unwrap_or(u32::MAX);
+    let at = self.origin + self.resolution.saturating_mul(ticks);
+    Some(at.saturating_duration_since(now))
+  }
+
+  /// Remove and return every timer whose deadline has passed by `now`.
+  pub fn expire(&mut self, now: Instant) -> Vec<(usize, Instant)> {
+    let now_tick = self.elapsed_ticks(now).max(self.current);
+    let steps = (now_tick - self.current).min(self.slots.len() as u64);
+
+    let mut fired = Vec::new();
+    for tick in self.current + 1..=self.current + steps {
+      let slot = self.slot_of(tick);
+      let (due, later) = mem::take(&mut self.slots[slot]).into_iter()
+          .partition::<Vec<(usize, Instant)>, _>(|(_, deadline)| *deadline <= now);
+      self.slots[slot] = later;
+      fired.extend(due);
+    }
+    self.current = now_tick;
+    self.len -= fired.len();
+    fired
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const MS: Duration = Duration::from_millis(1);
+
+  #[test]
+  fn fires_timers_once_due() {
+    let mut wheel = TimerWheel::new(8, 10 * MS);
+    let origin = wheel.origin;
+    wheel.schedule(1, origin + 25 * MS);
+    wheel.schedule(2, origin + 45 * MS);
+
+    assert_eq!(wheel.expire(origin + 20 * MS), []);
+    assert_eq!(wheel.expire(origin + 30 * MS), [(1, origin + 25 * MS)]);
+    assert_eq!(wheel.expire(origin + 30 * MS), []);
+    assert_eq!(wheel.expire(origin + 50 * MS), [(2, origin + 45 * MS)]);
+    assert_eq!(wheel.timeout(origin + 50 * MS), None);
+  }
+
+  #[test]
+  fn keeps_timers_of_later_rounds() {
+    let mut wheel = TimerWheel::new(4, 10 * MS);
+    let origin = wheel.origin;
+    // Both in the slot of tick 2, a round apart
+    wheel.schedule(1, origin + 95 * MS);
+    wheel.schedule(2, origin + 15 * MS);
+
+    assert_eq!(wheel.expire(origin + 20 * MS), [(2, origin + 15 * MS)]);
+    assert_eq!(wheel.expire(origin + 60 * MS), []);
+    assert_eq!(wheel.expire(origin + 100 * MS), [(1, origin + 95 * MS)]);
+  }
+
+  #[test]
+  fn expires_every_slot_after_a_long_pause() {
+    let mut wheel = TimerWheel::new(4, 10 * MS);
+    let origin = wheel.origin;
+    for token_id in 0..8 {
+      wheel.schedule(token_id, origin + (token_id as u32 + 1) * 10 * MS);
+    }
+    let mut fired = wheel.expire(origin + 1000 * MS).into_iter()
+        .map(|(token_id, _)| token_id)
+        .collect::<Vec<usize>>();
+    fired.sort_unstable();
+    assert_eq!(fired, (0..8).collect::<Vec<usize>>());
+  }
+
+  #[test]
+  fn times_out_at_the_next_occupied_tick() {
+    let mut wheel = TimerWheel::new(8, 10 * MS);
+    let origin = wheel.origin;
+    assert_eq!(wheel.timeout(origin), None);
+    wheel.schedule(1, origin + 35 * MS);
+    assert_eq!(wheel.timeout(origin), Some(40 * MS));
+    assert_eq!(wheel.timeout(origin + 50 * MS), Some(Duration::ZERO));
+  }
+
+  #[test]
+  fn never_schedules_into_an_expired_tick() {
+    let mut wheel = TimerWheel::new(8, 10 * MS);
+    let origin = wheel.origin;
+    assert_eq!(wheel.expire(origin + 50 * MS), []);
+    wheel.schedule(1, origin + 10 * MS);
+    assert_eq!(wheel.expire(origin + 59 * MS), []);
+    assert_eq!(wheel.expire(origin + 60 * MS), [(1, origin + 10 * MS)]);
+  }
+}